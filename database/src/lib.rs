@@ -1,6 +1,22 @@
+/// Totals of stored data, for monitoring.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DatabaseStats {
+    /// Chats with at least one ticket.
+    pub users: usize,
+    pub tickets: usize,
+    pub subscriptions: usize,
+    pub watches: usize,
+}
+
 pub trait Database {
     type User;
     type Ticket;
+    type Journey;
+    type Watch;
+    type Observation;
+    type TrainNumber;
+    type Settings;
+    type MessageId;
     type Error;
 
     fn insert_ticket_data(
@@ -11,9 +27,116 @@ pub trait Database {
 
     fn retrieve_user_trains(&self, user_id: Self::User) -> impl Iterator<Item = Self::Ticket>;
     fn users(&self) -> impl Iterator<Item = (Self::User, impl Iterator<Item = Self::Ticket>)>;
+    /// Removes the ticket together with all its subscriptions.
     fn remove_user_train(
         &self,
         user_id: Self::User,
         train: Self::Ticket,
     ) -> Result<(), Self::Error>;
+    /// Remembers the chat member, who added the ticket to a group chat, the first one is kept.
+    fn set_added_by(
+        &self,
+        owner: Self::User,
+        ticket: Self::Ticket,
+        member: Self::User,
+    ) -> Result<(), Self::Error>;
+    fn added_by(&self, owner: Self::User, ticket: &Self::Ticket) -> Option<Self::User>;
+
+    /// Stores invite code, which lets other users subscribe to owner's ticket.
+    fn insert_invite(
+        &self,
+        code: String,
+        owner: Self::User,
+        ticket: Self::Ticket,
+    ) -> Result<(), Self::Error>;
+    fn resolve_invite(&self, code: &str) -> Option<(Self::User, Self::Ticket)>;
+
+    /// Subscribes another user to owner's ticket, so they receive the same notifications.
+    fn subscribe(
+        &self,
+        owner: Self::User,
+        ticket: Self::Ticket,
+        subscriber: Self::User,
+    ) -> Result<(), Self::Error>;
+    fn unsubscribe(
+        &self,
+        owner: Self::User,
+        ticket: Self::Ticket,
+        subscriber: Self::User,
+    ) -> Result<(), Self::Error>;
+    fn subscribers(
+        &self,
+        owner: Self::User,
+        ticket: &Self::Ticket,
+    ) -> impl Iterator<Item = Self::User>;
+    /// Tickets of other users, `subscriber` is subscribed to, together with their owners.
+    fn subscriptions(
+        &self,
+        subscriber: Self::User,
+    ) -> impl Iterator<Item = (Self::User, Self::Ticket)>;
+
+    /// Keeps the ticket after departure and reports delays to `chat` until the train arrives.
+    fn track_arrival(
+        &self,
+        owner: Self::User,
+        ticket: Self::Ticket,
+        chat: Self::User,
+    ) -> Result<(), Self::Error>;
+    fn untrack_arrival(
+        &self,
+        owner: Self::User,
+        ticket: Self::Ticket,
+        chat: Self::User,
+    ) -> Result<(), Self::Error>;
+    fn arrival_trackers(
+        &self,
+        owner: Self::User,
+        ticket: &Self::Ticket,
+    ) -> impl Iterator<Item = Self::User>;
+
+    /// Journey keeps its own copy of legs, so it outlives tickets removed after their departure.
+    fn insert_journey(&self, user: Self::User, journey: Self::Journey) -> Result<(), Self::Error>;
+    fn user_journeys(&self, user: Self::User) -> impl Iterator<Item = Self::Journey>;
+    fn journeys(&self) -> impl Iterator<Item = (Self::User, Self::Journey)>;
+    fn remove_journey(&self, user: Self::User, journey: &Self::Journey) -> Result<(), Self::Error>;
+
+    /// Replaces previous watch of the same train number.
+    fn insert_watch(&self, user: Self::User, watch: Self::Watch) -> Result<(), Self::Error>;
+    fn user_watches(&self, user: Self::User) -> impl Iterator<Item = Self::Watch>;
+    fn watches(&self) -> impl Iterator<Item = (Self::User, Self::Watch)>;
+    fn remove_watch(&self, user: Self::User, watch: &Self::Watch) -> Result<(), Self::Error>;
+
+    /// Keeps one observation per train and day, the one with the largest delay.
+    fn record_delay(&self, observation: Self::Observation);
+    /// Matches train numbers the way [`Self::TrainNumber`] defines it.
+    fn delay_history(
+        &self,
+        train_number: &Self::TrainNumber,
+    ) -> impl Iterator<Item = Self::Observation>;
+
+    /// Preferences of the chat, defaults if it has not changed any.
+    fn user_settings(&self, user: Self::User) -> Self::Settings;
+    fn update_settings(
+        &self,
+        user: Self::User,
+        settings: Self::Settings,
+    ) -> Result<(), Self::Error>;
+
+    /// Message of `chat` kept up to date with the status of owner's ticket,
+    /// removed together with the ticket or the subscription of `chat`.
+    fn set_status_message(
+        &self,
+        owner: Self::User,
+        ticket: Self::Ticket,
+        chat: Self::User,
+        message_id: Self::MessageId,
+    ) -> Result<(), Self::Error>;
+    fn status_message(
+        &self,
+        owner: Self::User,
+        ticket: &Self::Ticket,
+        chat: Self::User,
+    ) -> Option<Self::MessageId>;
+
+    fn stats(&self) -> DatabaseStats;
 }
@@ -17,5 +17,22 @@ pub enum ParsePdfError {
     #[error("Erorr parsing time: {0}")]
     TimeParse(#[from] chrono::ParseError),
     #[error("Erorr parsing train number: {0}")]
-    ParseTrainNumber(String),
+    ParseTrainNumber(#[from] train_number::TrainNumberError),
+}
+
+impl ParsePdfError {
+    /// Variant name, for labelling metrics.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            ParsePdfError::PdfExtractError(_) => "PdfExtractError",
+            ParsePdfError::DepartureDateTimeAbsent => "DepartureDateTimeAbsent",
+            ParsePdfError::TrainNumberLineAbsent => "TrainNumberLineAbsent",
+            ParsePdfError::TrainNumberLineNotPoizd => "TrainNumberLineNotPoizd",
+            ParsePdfError::TrainNumberLineNotTrainNumber => "TrainNumberLineNotTrainNumber",
+            ParsePdfError::DepartureDateAbsent => "DepartureDateAbsent",
+            ParsePdfError::DepartureTimeAbsent => "DepartureTimeAbsent",
+            ParsePdfError::TimeParse(_) => "TimeParse",
+            ParsePdfError::ParseTrainNumber(_) => "ParseTrainNumber",
+        }
+    }
 }
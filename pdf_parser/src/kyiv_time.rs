@@ -0,0 +1,35 @@
+//! Serde of ticket times as RFC 3339 text, read back in Kyiv time zone, which all tickets use.
+
+use chrono::{DateTime, FixedOffset};
+use chrono_tz::{Europe::Kyiv, Tz};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+pub fn serialize<S: Serializer>(datetime: &DateTime<Tz>, serializer: S) -> Result<S::Ok, S::Error> {
+    datetime.fixed_offset().serialize(serializer)
+}
+
+pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<DateTime<Tz>, D::Error> {
+    DateTime::<FixedOffset>::deserialize(deserializer).map(|datetime| datetime.with_timezone(&Kyiv))
+}
+
+pub mod option {
+    use chrono::{DateTime, FixedOffset};
+    use chrono_tz::{Europe::Kyiv, Tz};
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    pub fn serialize<S: Serializer>(
+        datetime: &Option<DateTime<Tz>>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        datetime
+            .map(|datetime| datetime.fixed_offset())
+            .serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<DateTime<Tz>>, D::Error> {
+        Option::<DateTime<FixedOffset>>::deserialize(deserializer)
+            .map(|datetime| datetime.map(|datetime| datetime.with_timezone(&Kyiv)))
+    }
+}
@@ -1,9 +1,13 @@
 mod errors;
+mod kyiv_time;
 
 use chrono::{DateTime, TimeZone};
 use chrono_tz::{Europe::Kyiv, Tz};
 use pdf_extract::OutputError;
+use serde::{Deserialize, Serialize};
+use train_number::TrainNumber;
 
+#[allow(clippy::single_component_path_imports)]
 use chrono_tz;
 pub use errors::ParsePdfError;
 
@@ -23,6 +27,7 @@ impl DataExtractor for &std::path::Path {
     }
 }
 
+#[allow(clippy::manual_ok_or, clippy::redundant_closure)]
 pub fn parse_departure_data_from_pdf(
     data: impl DataExtractor,
 ) -> Result<TicketData, ParsePdfError> {
@@ -59,14 +64,113 @@ pub fn parse_departure_data_from_pdf(
         .split_once(' ')
         .ok_or(ParsePdfError::TrainNumberLineNotTrainNumber)?;
 
+    let train_number = train_number.parse()?;
+    let arrival_datetime = parse_arrival_datetime(&string_data);
+
     Ok(TicketData {
         departure_datetime,
-        train_number: train_number.to_owned(),
+        train_number,
+        arrival_datetime,
+        departure_station: parse_station(&string_data, "Відправлення "),
+        arrival_station: parse_station(&string_data, "Призначення "),
+    })
+}
+
+/// Station line looks like `Відправлення 2200001 КИЇВ-ПАСАЖИРСЬКИЙ Вагон 01 К ЛЬВ`,
+/// where the uppercase words after the code are the station name.
+fn parse_station(string_data: &str, line_start: &str) -> Option<TicketStation> {
+    let station_line = string_data
+        .split('\n')
+        .find(|line| line.starts_with(line_start))?;
+
+    let mut tokens = station_line.split_whitespace().skip(1);
+    let code = tokens
+        .next()
+        .filter(|code| code.chars().all(|c| c.is_ascii_digit()))?;
+    let name = tokens
+        .take_while(|token| !token.chars().any(char::is_lowercase))
+        .collect::<Vec<_>>()
+        .join(" ");
+
+    (!name.is_empty()).then(|| TicketStation {
+        code: Some(code.to_owned()),
+        name,
     })
 }
 
-#[derive(Debug, PartialEq, Eq, Hash, Clone)]
+/// Arrival line looks like `Дата/час приб. 044* 20.05.2017 05:44`,
+/// where the token before the date is not always present.
+fn parse_arrival_datetime(string_data: &str) -> Option<DateTime<Tz>> {
+    let arrival_time_full_str = string_data
+        .split('\n')
+        .find(|line| line.starts_with("Дата/час приб. "))?;
+
+    let tokens = arrival_time_full_str
+        .split_whitespace()
+        .skip(2)
+        .collect::<Vec<_>>();
+
+    tokens.windows(2).find_map(|date_time| {
+        let time_str = format!("{} {}", date_time[0], date_time[1]);
+        let naive = chrono::NaiveDateTime::parse_from_str(&time_str, "%d.%m.%Y %H:%M").ok()?;
+        Kyiv.from_local_datetime(&naive).single()
+    })
+}
+
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Serialize, Deserialize)]
 pub struct TicketData {
+    #[serde(with = "kyiv_time")]
     pub departure_datetime: DateTime<Tz>,
-    pub train_number: String,
+    pub train_number: TrainNumber,
+    /// Absent for tickets without arrival line.
+    #[serde(with = "kyiv_time::option")]
+    pub arrival_datetime: Option<DateTime<Tz>>,
+    /// Absent for tickets added without pdf.
+    pub departure_station: Option<TicketStation>,
+    pub arrival_station: Option<TicketStation>,
+}
+
+/// Station as printed on a ticket, with UZ station code and uppercase name.
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Serialize, Deserialize)]
+pub struct TicketStation {
+    /// Absent for stations typed by the user.
+    pub code: Option<String>,
+    pub name: String,
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+    use chrono_tz::Europe::Kyiv;
+
+    use crate::{parse_departure_data_from_pdf, TicketStation};
+
+    #[test]
+    fn test_ticket_parser() {
+        let path =
+            std::path::Path::new(concat!(env!("CARGO_MANIFEST_DIR"), "/../assets/ticket.pdf"));
+
+        let ticket = parse_departure_data_from_pdf(path).expect("Error parsing ticket");
+
+        assert_eq!(ticket.train_number.to_string(), "043");
+        assert_eq!(
+            ticket.departure_datetime,
+            Kyiv.with_ymd_and_hms(2017, 5, 19, 18, 50, 0).unwrap()
+        );
+        assert_eq!(
+            ticket.arrival_datetime,
+            Some(Kyiv.with_ymd_and_hms(2017, 5, 20, 5, 44, 0).unwrap())
+        );
+        assert_eq!(
+            ticket.departure_station,
+            Some(TicketStation {
+                code: Some("2200001".to_owned()),
+                name: "КИЇВ-ПАСАЖИРСЬКИЙ".to_owned()
+            })
+        );
+        assert_eq!(
+            ticket.arrival_station.map(|station| station.name),
+            Some("ІВАНО-ФРАНКІВСЬК".to_owned())
+        );
+    }
 }
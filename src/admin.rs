@@ -0,0 +1,435 @@
+use std::{
+    collections::VecDeque,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex,
+    },
+};
+
+use chrono::DateTime;
+use chrono_tz::Tz;
+use dashmap::DashMap;
+use database::Database;
+use rclite::Arc;
+use telegram::{Button, ChatId, Keyboard, Reply, TelegramClient};
+use tokio::task::JoinHandle;
+use tokio_util::sync::CancellationToken;
+use tracing::{error, info};
+
+use crate::{
+    broadcast::{self, Audience, BroadcastError, BroadcastJob},
+    config::AdminConfig,
+    consts::{
+        ADMIN_PARSE_FAILURES_KEPT, ADMIN_UPCOMING_LISTED, ADMIN_UPCOMING_WITHIN,
+        ADMIN_USAGE_MESSAGE, BROADCAST_RUNNING_MESSAGE, UNKNOWN_BUTTON_MESSAGE,
+        UNKNOWN_COMMAND_MESSAGE,
+    },
+    delays::SharedDelays,
+    mydb::MyDb,
+};
+
+pub const ADMIN_CALLBACK_PREFIX: &str = "admin:";
+const BROADCAST_SEND: &str = "broadcast:send";
+const BROADCAST_CANCEL: &str = "broadcast:cancel";
+
+/// `/admin` commands of operators. Operators are configured by Telegram user id, which is
+/// the chat id of their private chat with the bot, so the commands don't work in groups.
+#[derive(Clone)]
+pub struct Admin(Arc<AdminInner>);
+
+struct AdminInner {
+    config: AdminConfig,
+    /// Newest last.
+    parse_failures: Mutex<VecDeque<ParseFailure>>,
+    /// Broadcast waiting for confirmation, by admin chat.
+    pending_broadcasts: DashMap<ChatId, (Audience, String)>,
+    /// One broadcast at a time, so that chats are not flooded.
+    broadcasting: AtomicBool,
+    /// Task delivering the broadcast, awaited at shutdown until it saves the progress.
+    delivery: Mutex<Option<JoinHandle<()>>>,
+    shutdown: CancellationToken,
+}
+
+struct ParseFailure {
+    at: DateTime<Tz>,
+    chat: ChatId,
+    kind: &'static str,
+    error: String,
+}
+
+impl Admin {
+    /// Broadcasts stop and save their progress, when `shutdown` is cancelled.
+    pub fn new(config: AdminConfig, shutdown: CancellationToken) -> Self {
+        Self(Arc::new(AdminInner {
+            config,
+            parse_failures: Mutex::new(VecDeque::new()),
+            pending_broadcasts: DashMap::new(),
+            broadcasting: AtomicBool::new(false),
+            delivery: Mutex::new(None),
+            shutdown,
+        }))
+    }
+
+    /// Continues the broadcast interrupted by a restart, if any.
+    pub fn resume_broadcast(&self, tg: &TelegramClient) -> Result<(), BroadcastError> {
+        let path = &self.0.config.broadcast_state_file;
+        if let Some(job) = BroadcastJob::load(path)? {
+            info!(admin = %job.admin, next = job.next, "resuming broadcast");
+            self.start_broadcast(tg, job);
+        }
+        Ok(())
+    }
+
+    /// Waits for the broadcast being delivered, if any, to save its progress after shutdown.
+    pub async fn stopped_broadcast(&self) {
+        let delivery = self.0.delivery.lock().expect("poisoned delivery").take();
+        if let Some(delivery) = delivery {
+            if let Err(e) = delivery.await {
+                error!(%e, "broadcast delivery");
+            }
+        }
+    }
+
+    pub fn is_admin(&self, chat: ChatId) -> bool {
+        self.0.config.user_ids.contains(&chat.0)
+    }
+
+    /// Keeps the last [`ADMIN_PARSE_FAILURES_KEPT`] failures for `/admin parse-failures`.
+    pub fn record_parse_failure(
+        &self,
+        chat: ChatId,
+        kind: &'static str,
+        error: String,
+        at: DateTime<Tz>,
+    ) {
+        let mut failures = self
+            .0
+            .parse_failures
+            .lock()
+            .expect("poisoned parse failures");
+        failures.push_back(ParseFailure {
+            at,
+            chat,
+            kind,
+            error,
+        });
+        while failures.len() > ADMIN_PARSE_FAILURES_KEPT {
+            failures.pop_front();
+        }
+    }
+
+    /// Non-admins get the same answer as for an unknown command.
+    pub async fn command(
+        &self,
+        db: &MyDb,
+        delays: &SharedDelays,
+        user: ChatId,
+        args: &str,
+        now: DateTime<Tz>,
+    ) -> Result<Reply, String> {
+        if !self.is_admin(user) {
+            return Err(UNKNOWN_COMMAND_MESSAGE.to_owned());
+        }
+
+        let (subcommand, args) = args
+            .split_once(char::is_whitespace)
+            .map(|(subcommand, args)| (subcommand, args.trim()))
+            .unwrap_or((args, ""));
+        match subcommand {
+            "stats" => Ok(stats(db, now).into()),
+            "user" => user_info(db, args).map(Reply::from),
+            "parse-failures" => Ok(self.parse_failures().into()),
+            "upstream" => Ok(upstream(delays).await.into()),
+            "broadcast" => self.prepare_broadcast(db, user, args),
+            _ => Err(ADMIN_USAGE_MESSAGE.to_owned()),
+        }
+    }
+
+    /// Handles confirmation buttons, `data` is without [`ADMIN_CALLBACK_PREFIX`].
+    pub fn callback(
+        &self,
+        db: &MyDb,
+        tg: &TelegramClient,
+        user: ChatId,
+        data: &str,
+    ) -> Result<Reply, String> {
+        if !self.is_admin(user) {
+            return Err(UNKNOWN_BUTTON_MESSAGE.to_owned());
+        }
+        let Some((_, (audience, text))) = self.0.pending_broadcasts.remove(&user) else {
+            return Err("No broadcast is waiting for confirmation.".to_owned());
+        };
+
+        match data {
+            BROADCAST_SEND => {
+                let recipients = audience.chats(db);
+                let count = recipients.len();
+                let job = BroadcastJob::new(user, text, audience, recipients);
+                if !self.start_broadcast(tg, job) {
+                    return Err(BROADCAST_RUNNING_MESSAGE.to_owned());
+                }
+                Ok(format!("Broadcast to {count} chats started, progress follows.").into())
+            }
+            BROADCAST_CANCEL => Ok("Broadcast cancelled.".to_owned().into()),
+            _ => Err(UNKNOWN_BUTTON_MESSAGE.to_owned()),
+        }
+    }
+
+    /// `false` without starting, if another broadcast is being delivered.
+    fn start_broadcast(&self, tg: &TelegramClient, job: BroadcastJob) -> bool {
+        if self
+            .0
+            .broadcasting
+            .compare_exchange(false, true, Ordering::AcqRel, Ordering::Acquire)
+            .is_err()
+        {
+            return false;
+        }
+        let admin = self.clone();
+        let tg = tg.clone();
+        let delivery = tokio::spawn(async move {
+            let config = &admin.0.config;
+            let path = config.broadcast_state_file.clone();
+            let shutdown = admin.0.shutdown.clone();
+            broadcast::deliver(job, tg, path, config.broadcast_per_second, shutdown).await;
+            admin.0.broadcasting.store(false, Ordering::Release);
+        });
+        *self.0.delivery.lock().expect("poisoned delivery") = Some(delivery);
+        true
+    }
+
+    fn parse_failures(&self) -> String {
+        let failures = self
+            .0
+            .parse_failures
+            .lock()
+            .expect("poisoned parse failures");
+        if failures.is_empty() {
+            return "No ticket parse failures since start.".to_owned();
+        }
+        let lines = failures
+            .iter()
+            .rev()
+            .map(|failure| {
+                format!(
+                    "{at} chat {chat}: {kind}, {error}",
+                    at = failure.at.format("%d.%m %H:%M"),
+                    chat = failure.chat,
+                    kind = failure.kind,
+                    error = failure.error
+                )
+            })
+            .collect::<Vec<_>>();
+        format!("Last ticket parse failures:\n{}", lines.join("\n"))
+    }
+
+    /// `/admin broadcast <audience>` with the text on the following lines. The text is
+    /// shown to the admin and sent only after the confirmation button, without text
+    /// the chats are only counted.
+    fn prepare_broadcast(&self, db: &MyDb, user: ChatId, args: &str) -> Result<Reply, String> {
+        let (audience, text) = args
+            .split_once('\n')
+            .map(|(audience, text)| (audience, text.trim()))
+            .unwrap_or((args, ""));
+        let audience = audience.parse::<Audience>()?;
+        let count = audience.chats(db).len();
+        if text.is_empty() {
+            return Ok(format!("Broadcast to {audience} would be sent to {count} chats.").into());
+        }
+        if self.0.broadcasting.load(Ordering::Acquire) {
+            return Err(BROADCAST_RUNNING_MESSAGE.to_owned());
+        }
+
+        let preview = format!("This message will be sent to {audience}, {count} chats:\n\n{text}");
+        self.0
+            .pending_broadcasts
+            .insert(user, (audience, text.to_owned()));
+
+        Ok(Reply {
+            text: preview,
+            keyboard: Some(Keyboard(vec![vec![
+                Button {
+                    text: format!("Send to {count} chats"),
+                    data: format!("{ADMIN_CALLBACK_PREFIX}{BROADCAST_SEND}"),
+                },
+                Button {
+                    text: "Cancel".to_owned(),
+                    data: format!("{ADMIN_CALLBACK_PREFIX}{BROADCAST_CANCEL}"),
+                },
+            ]])),
+        })
+    }
+}
+
+fn stats(db: &MyDb, now: DateTime<Tz>) -> String {
+    let stats = db.stats();
+    let mut upcoming = db
+        .users()
+        .flat_map(|(_, tickets)| tickets)
+        .filter(|ticket| {
+            ticket.departure_datetime > now
+                && ticket.departure_datetime <= now + ADMIN_UPCOMING_WITHIN
+        })
+        .collect::<Vec<_>>();
+    upcoming.sort_by_key(|ticket| ticket.departure_datetime);
+
+    let mut message = format!(
+        "Users: {users}\nTickets: {tickets}\nShared ticket subscriptions: {subscriptions}\nWatched trains: {watches}\nDepartures within {hours} hours: {upcoming}",
+        users = stats.users,
+        tickets = stats.tickets,
+        subscriptions = stats.subscriptions,
+        watches = stats.watches,
+        hours = ADMIN_UPCOMING_WITHIN.num_hours(),
+        upcoming = upcoming.len()
+    );
+    for ticket in upcoming.iter().take(ADMIN_UPCOMING_LISTED) {
+        message.push_str(&format!(
+            "\n№{} {}",
+            ticket.train_number,
+            ticket.departure_datetime.format("%d.%m %H:%M")
+        ));
+    }
+    message
+}
+
+fn user_info(db: &MyDb, args: &str) -> Result<String, String> {
+    let chat = args
+        .parse::<i64>()
+        .map(ChatId)
+        .map_err(|_| "Usage: /admin user <chat id>".to_owned())?;
+
+    let mut tickets = db.retrieve_user_trains(chat).collect::<Vec<_>>();
+    tickets.sort_by_key(|ticket| ticket.departure_datetime);
+    let subscriptions = db.subscriptions(chat).count();
+    let watches = db.user_watches(chat).collect::<Vec<_>>();
+    let journeys = db.user_journeys(chat).count();
+
+    let mut message = format!(
+        "Chat {chat}: {tickets} tickets, {subscriptions} shared tickets, {watches} watches, {journeys} journeys",
+        tickets = tickets.len(),
+        watches = watches.len()
+    );
+    for ticket in &tickets {
+        let route = match (&ticket.departure_station, &ticket.arrival_station) {
+            (Some(departure), Some(arrival)) => format!(", {} → {}", departure.name, arrival.name),
+            _ => String::new(),
+        };
+        let trackers = db.arrival_trackers(chat, ticket).count();
+        message.push_str(&format!(
+            "\n№{} {}{route}, {} subscribers, {trackers} tracking",
+            ticket.train_number,
+            ticket.departure_datetime.format("%d.%m %H:%M"),
+            db.subscribers(chat, ticket).count()
+        ));
+    }
+    for watch in &watches {
+        message.push_str(&format!(
+            "\nWatching №{} until {}",
+            watch.train_number,
+            watch.until.format("%d.%m %H:%M")
+        ));
+    }
+    Ok(message)
+}
+
+async fn upstream(delays: &SharedDelays) -> String {
+    let health = delays.health();
+    let last_success = health
+        .last_success
+        .map(|at| at.format("%d.%m %H:%M:%S UTC").to_string())
+        .unwrap_or_else(|| "never".to_owned());
+    let mut message = format!(
+        "UZ page: circuit {:?}, {} consecutive failures, last success {last_success}",
+        health.circuit, health.consecutive_failures
+    );
+    if let Some(error) = &health.last_error {
+        message.push_str(&format!("\nLast error: {error}"));
+    }
+    if let Some(snapshot) = delays.last().await {
+        message.push_str(&format!(
+            "\nLast data: {} delayed trains, fetched {}",
+            snapshot.trains.0.len(),
+            snapshot.fetched_at.format("%d.%m %H:%M:%S UTC")
+        ));
+    }
+    message
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{TimeDelta, TimeZone};
+    use chrono_tz::Europe::Kyiv;
+    use database::Database;
+    use pdf_parser::TicketData;
+    use telegram::ChatId;
+    use tokio_util::sync::CancellationToken;
+    use train_number::TrainNumber;
+
+    use super::{stats, user_info, Admin};
+    use crate::{config::AdminConfig, mydb::MyDb, test_util, watch::Watch};
+
+    fn ticket(train_number: &str, hours_from_now: i64) -> TicketData {
+        test_util::ticket(
+            train_number,
+            Kyiv.with_ymd_and_hms(2024, 4, 9, 12, 0, 0).unwrap() + TimeDelta::hours(hours_from_now),
+        )
+    }
+
+    #[test]
+    fn test_admin_reports() {
+        let now = Kyiv.with_ymd_and_hms(2024, 4, 9, 12, 0, 0).unwrap();
+        let db = MyDb::new();
+        let owner = ChatId(1);
+        let subscriber = ChatId(2);
+        let watcher = ChatId(3);
+        db.insert_ticket_data(owner, ticket("43", 2)).unwrap();
+        db.insert_ticket_data(owner, ticket("91", 48)).unwrap();
+        db.subscribe(owner, ticket("43", 2), subscriber).unwrap();
+        db.insert_watch(
+            watcher,
+            Watch {
+                train_number: "705".parse::<TrainNumber>().unwrap(),
+                until: now + TimeDelta::hours(3),
+            },
+        )
+        .unwrap();
+
+        let report = stats(&db, now);
+        assert!(report.starts_with("Users: 1\nTickets: 2\n"), "{report}");
+        assert!(
+            report.ends_with("Departures within 24 hours: 1\n№043 09.04 14:00"),
+            "{report}"
+        );
+
+        let report = user_info(&db, "1").unwrap();
+        assert!(report.starts_with("Chat 1: 2 tickets"), "{report}");
+        assert!(
+            report.contains("№043 09.04 14:00, 1 subscribers"),
+            "{report}"
+        );
+        assert!(user_info(&db, "me").is_err());
+    }
+
+    #[test]
+    fn test_admin_authorization() {
+        let admin = Admin::new(
+            AdminConfig {
+                user_ids: vec![144441960],
+                ..AdminConfig::default()
+            },
+            CancellationToken::new(),
+        );
+        assert!(admin.is_admin(ChatId(144441960)));
+        assert!(!admin.is_admin(ChatId(144441961)));
+        assert!(!admin.is_admin(ChatId(-144441960)), "group chat");
+
+        let now = Kyiv.with_ymd_and_hms(2024, 4, 9, 12, 0, 0).unwrap();
+        for index in 0..30 {
+            admin.record_parse_failure(ChatId(index), "TimeParse", "bad".to_owned(), now);
+        }
+        let failures = admin.parse_failures();
+        assert_eq!(failures.lines().count(), 21);
+        assert!(failures.contains("chat 29: TimeParse, bad"), "{failures}");
+        assert!(!failures.contains("chat 9:"), "only the last ones are kept");
+    }
+}
@@ -0,0 +1,393 @@
+use std::{
+    collections::BTreeSet,
+    fmt::{self, Display},
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::Duration,
+};
+
+use chrono::NaiveDate;
+use database::Database;
+use serde::{Deserialize, Serialize};
+use telegram::{ChatId, MessageId, TelegramClient};
+use thiserror::Error;
+use tokio_util::sync::CancellationToken;
+use tracing::{error, info, warn};
+use train_number::TrainNumber;
+
+use crate::{consts::BROADCAST_PROGRESS_EVERY, mydb::MyDb};
+
+pub const AUDIENCE_USAGE_MESSAGE: &str =
+    "Audience must be all, train <number> or dates <dd.mm.yyyy> [dd.mm.yyyy].";
+
+/// Chats a broadcast is sent to. Shared tickets reach their subscribers as well as owners.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Audience {
+    /// Every chat with a ticket, a shared ticket or a watched train.
+    All,
+    /// Chats with a ticket on the train.
+    Train(TrainNumber),
+    /// Chats with a ticket departing on these days, both included, by Kyiv date.
+    Departures { from: NaiveDate, to: NaiveDate },
+}
+
+impl Audience {
+    /// Sorted, so that a resumed broadcast continues in the same order.
+    pub fn chats(&self, db: &MyDb) -> Vec<ChatId> {
+        let mut chats = BTreeSet::new();
+        for (owner, tickets) in db.users() {
+            for ticket in tickets {
+                let targeted = match self {
+                    Audience::All => true,
+                    Audience::Train(train_number) => train_number.matches(&ticket.train_number),
+                    Audience::Departures { from, to } => {
+                        (*from..=*to).contains(&ticket.departure_datetime.date_naive())
+                    }
+                };
+                if targeted {
+                    chats.insert(owner);
+                    chats.extend(db.subscribers(owner, &ticket));
+                }
+            }
+        }
+        if *self == Audience::All {
+            chats.extend(db.watches().map(|(user, _)| user));
+        }
+        chats.into_iter().collect()
+    }
+}
+
+impl FromStr for Audience {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parse_date = |date: &str| {
+            NaiveDate::parse_from_str(date, "%d.%m.%Y").map_err(|_| AUDIENCE_USAGE_MESSAGE)
+        };
+        let words = s.split_whitespace().collect::<Vec<_>>();
+        let audience = match words.as_slice() {
+            ["all"] => Audience::All,
+            ["train", number] => {
+                Audience::Train(number.parse().map_err(|_| AUDIENCE_USAGE_MESSAGE)?)
+            }
+            ["dates", day] => {
+                let day = parse_date(day)?;
+                Audience::Departures { from: day, to: day }
+            }
+            ["dates", from, to] => Audience::Departures {
+                from: parse_date(from)?,
+                to: parse_date(to)?,
+            },
+            _ => return Err(AUDIENCE_USAGE_MESSAGE.to_owned()),
+        };
+        match audience {
+            Audience::Departures { from, to } if from > to => {
+                Err("First date must not be after the second.".to_owned())
+            }
+            audience => Ok(audience),
+        }
+    }
+}
+
+impl Display for Audience {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Audience::All => write!(f, "all chats"),
+            Audience::Train(train_number) => write!(f, "tickets on train №{train_number}"),
+            Audience::Departures { from, to } if from == to => {
+                write!(f, "departures on {}", from.format("%d.%m.%Y"))
+            }
+            Audience::Departures { from, to } => write!(
+                f,
+                "departures from {} to {}",
+                from.format("%d.%m.%Y"),
+                to.format("%d.%m.%Y")
+            ),
+        }
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum BroadcastError {
+    #[error("Error accessing broadcast state file: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Error (de)serializing broadcast state: {0}")]
+    Json(#[from] serde_json::Error),
+}
+
+/// Broadcast being delivered. Saved while it goes, so that a restarted bot continues it,
+/// recipients after the last save may get the message twice.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BroadcastJob {
+    pub admin: ChatId,
+    pub text: String,
+    pub audience: Audience,
+    pub recipients: Vec<ChatId>,
+    /// Recipients before this index are done.
+    pub next: usize,
+    pub sent: usize,
+    pub failed: usize,
+    /// Message to the admin, edited with the progress.
+    pub progress_message: Option<MessageId>,
+}
+
+impl BroadcastJob {
+    pub fn new(admin: ChatId, text: String, audience: Audience, recipients: Vec<ChatId>) -> Self {
+        Self {
+            admin,
+            text,
+            audience,
+            recipients,
+            next: 0,
+            sent: 0,
+            failed: 0,
+            progress_message: None,
+        }
+    }
+
+    /// `None` if no broadcast was interrupted.
+    pub fn load(path: &Path) -> Result<Option<Self>, BroadcastError> {
+        match std::fs::read_to_string(path) {
+            Ok(content) => Ok(Some(serde_json::from_str(&content)?)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Writes to a temporary file first, so that a failed write keeps the previous progress.
+    pub fn save(&self, path: &Path) -> Result<(), BroadcastError> {
+        let temporary = path.with_extension("tmp");
+        std::fs::write(&temporary, serde_json::to_vec(self)?)?;
+        std::fs::rename(&temporary, path)?;
+        Ok(())
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.next >= self.recipients.len()
+    }
+
+    fn progress(&self) -> String {
+        let state = match self.is_done() {
+            true => "finished",
+            false => "in progress",
+        };
+        format!(
+            "Broadcast to {audience} {state}: {done} of {total} chats, {sent} sent, {failed} failed.",
+            audience = self.audience,
+            done = self.next,
+            total = self.recipients.len(),
+            sent = self.sent,
+            failed = self.failed
+        )
+    }
+
+    /// Records the result of sending to the next recipient.
+    fn advance(&mut self, delivered: bool) {
+        self.next += 1;
+        match delivered {
+            true => self.sent += 1,
+            false => self.failed += 1,
+        }
+    }
+}
+
+/// Sends at most `per_second` messages a second, below the Bot API limit of 30,
+/// and reports progress to the admin every [`BROADCAST_PROGRESS_EVERY`] recipients.
+/// Stops after the message being sent, once `shutdown` is cancelled, and saves the job.
+pub async fn deliver(
+    mut job: BroadcastJob,
+    tg: TelegramClient,
+    path: PathBuf,
+    per_second: u32,
+    shutdown: CancellationToken,
+) {
+    info!(
+        admin = %job.admin,
+        audience = %job.audience,
+        next = job.next,
+        total = job.recipients.len(),
+        "broadcast started"
+    );
+    if job.progress_message.is_none() {
+        match tg.send_editable(job.admin, job.progress()).await {
+            Ok(message_id) => job.progress_message = Some(message_id),
+            Err(e) => warn!(%e, "Error sending broadcast progress to admin telegram"),
+        }
+    }
+    save(&job, &path);
+
+    let mut interval = tokio::time::interval(Duration::from_secs(1) / per_second);
+    while let Some(&recipient) = job.recipients.get(job.next) {
+        tokio::select! {
+            biased;
+            _ = shutdown.cancelled() => {
+                save(&job, &path);
+                info!(next = job.next, "broadcast interrupted by shutdown");
+                return;
+            }
+            _ = interval.tick() => {}
+        }
+        let delivered = match tg.send_to_user(recipient, job.text.clone()).await {
+            Ok(()) => true,
+            Err(e) => {
+                warn!(%e, %recipient, "Error sending broadcast to user telegram");
+                false
+            }
+        };
+        job.advance(delivered);
+
+        if job.next.is_multiple_of(BROADCAST_PROGRESS_EVERY) && !job.is_done() {
+            save(&job, &path);
+            report_progress(&tg, &job).await;
+        }
+    }
+
+    info!(sent = job.sent, failed = job.failed, "broadcast finished");
+    if let Err(e) = std::fs::remove_file(&path) {
+        error!(%e, path = %path.display(), "removing finished broadcast state");
+    }
+    report_progress(&tg, &job).await;
+    let report = format!(
+        "Broadcast finished: {} sent, {} failed.",
+        job.sent, job.failed
+    );
+    if let Err(e) = tg.send_to_user(job.admin, report).await {
+        warn!(%e, "Error sending broadcast report to admin telegram");
+    }
+}
+
+fn save(job: &BroadcastJob, path: &Path) {
+    if let Err(e) = job.save(path) {
+        error!(%e, path = %path.display(), "saving broadcast state");
+    }
+}
+
+async fn report_progress(tg: &TelegramClient, job: &BroadcastJob) {
+    let Some(message_id) = job.progress_message else {
+        return;
+    };
+    if let Err(e) = tg.edit_message(job.admin, message_id, job.progress()).await {
+        warn!(%e, "Error editing broadcast progress telegram");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{NaiveDate, TimeDelta, TimeZone};
+    use chrono_tz::Europe::Kyiv;
+    use database::Database;
+    use pdf_parser::TicketData;
+    use reqwest::header::HeaderMap;
+    use telegram::{ChatId, TelegramClient};
+    use tokio_util::sync::CancellationToken;
+
+    use super::{deliver, Audience, BroadcastJob};
+    use crate::{mydb::MyDb, test_util, watch::Watch};
+
+    fn ticket(train_number: &str, day: u32) -> TicketData {
+        test_util::ticket(
+            train_number,
+            Kyiv.with_ymd_and_hms(2024, 4, day, 20, 36, 0).unwrap(),
+        )
+    }
+
+    #[test]
+    fn test_audience() {
+        let db = MyDb::new();
+        let (owner, subscriber, other, watcher) = (ChatId(1), ChatId(2), ChatId(3), ChatId(4));
+        db.insert_ticket_data(owner, ticket("043К", 9)).unwrap();
+        db.subscribe(owner, ticket("043К", 9), subscriber).unwrap();
+        db.insert_ticket_data(other, ticket("91", 12)).unwrap();
+        db.insert_watch(
+            watcher,
+            Watch {
+                train_number: "705".parse().unwrap(),
+                until: Kyiv.with_ymd_and_hms(2024, 4, 9, 0, 0, 0).unwrap() + TimeDelta::hours(3),
+            },
+        )
+        .unwrap();
+
+        let chats = |audience: &str| audience.parse::<Audience>().unwrap().chats(&db);
+        assert_eq!(chats("all"), vec![owner, subscriber, other, watcher]);
+        assert_eq!(chats("train 43"), vec![owner, subscriber]);
+        assert_eq!(chats("train 743"), vec![]);
+        assert_eq!(chats("dates 10.04.2024 12.04.2024"), vec![other]);
+        assert_eq!(chats("dates 09.04.2024"), vec![owner, subscriber]);
+
+        for wrong in [
+            "",
+            "everyone",
+            "train",
+            "dates 9 April",
+            "dates 12.04.2024 10.04.2024",
+        ] {
+            assert!(wrong.parse::<Audience>().is_err(), "{wrong}");
+        }
+        assert_eq!(
+            "dates 10.04.2024 12.04.2024"
+                .parse::<Audience>()
+                .unwrap()
+                .to_string(),
+            "departures from 10.04.2024 to 12.04.2024"
+        );
+    }
+
+    #[test]
+    fn test_broadcast_job_resume() {
+        let audience = Audience::Departures {
+            from: NaiveDate::from_ymd_opt(2024, 4, 9).unwrap(),
+            to: NaiveDate::from_ymd_opt(2024, 4, 10).unwrap(),
+        };
+        let mut job = BroadcastJob::new(
+            ChatId(144441960),
+            "Ticket layout changed".to_owned(),
+            audience,
+            vec![ChatId(1), ChatId(2), ChatId(3)],
+        );
+        job.advance(true);
+        job.advance(false);
+        assert!(!job.is_done());
+        assert_eq!(
+            job.progress(),
+            "Broadcast to departures from 09.04.2024 to 10.04.2024 in progress: 2 of 3 chats, 1 sent, 1 failed."
+        );
+
+        let path = std::env::temp_dir().join(format!(
+            "uz_bot_broadcast_state_{}.json",
+            std::process::id()
+        ));
+        assert_eq!(BroadcastJob::load(&path).unwrap(), None);
+        job.save(&path).unwrap();
+        let loaded = BroadcastJob::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(loaded, Some(job));
+    }
+
+    #[tokio::test]
+    async fn test_broadcast_saved_on_shutdown() {
+        // never reached, nothing is sent after the shutdown
+        let tg = TelegramClient::new(
+            "1:token",
+            "http://127.0.0.1:9".parse().unwrap(),
+            HeaderMap::new(),
+        );
+        let job = BroadcastJob::new(
+            ChatId(144441960),
+            "Ticket layout changed".to_owned(),
+            Audience::All,
+            vec![ChatId(1), ChatId(2)],
+        );
+        let path = std::env::temp_dir().join(format!(
+            "uz_bot_broadcast_shutdown_{}.json",
+            std::process::id()
+        ));
+        let shutdown = CancellationToken::new();
+        shutdown.cancel();
+
+        deliver(job.clone(), tg, path.clone(), 25, shutdown).await;
+        let loaded = BroadcastJob::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(loaded, Some(job));
+    }
+}
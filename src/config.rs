@@ -0,0 +1,798 @@
+use std::{
+    collections::BTreeMap,
+    fmt::Debug,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use chrono::TimeDelta;
+use clap::Parser;
+use reqwest::{
+    header::{HeaderMap, HeaderName, HeaderValue},
+    Url,
+};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use tracing::level_filters::LevelFilter;
+use ukrzaliznytsia_parser::{
+    FetchPolicy, SeverityThresholds, UZ_ACCEPT, UZ_DELAYS_URL_STR, UZ_USER_AGENT,
+};
+
+/// Config file read when `--config` is not given, it is fine if it is absent.
+const DEFAULT_CONFIG_PATH: &str = "config.toml";
+/// Env vars like `UZ_BOT__SCHEDULER__POLL_INTERVAL_SECS=5` override `scheduler.poll_interval_secs`.
+const ENV_PREFIX: &str = "UZ_BOT__";
+/// A week, earlier reminders are of no use and huge values would overflow `TimeDelta`.
+const MAX_REMINDER_OFFSET_MINUTES: i64 = 7 * 24 * 60;
+/// Env vars used before the config file existed, kept working for old deployments.
+const LEGACY_ENV_VARS: [(&str, &str); 2] = [
+    ("TELEGRAM_BOT_API_KEY", "telegram.bot_api_key"),
+    ("LOG_FILE_PREFIX", "log.file_prefix"),
+];
+
+#[derive(Debug, Parser)]
+#[command(about = "Ukrzaliznytsia train delay reminder Telegram bot")]
+pub struct Cli {
+    /// TOML config file, `config.toml` is read if it exists.
+    #[arg(long, short, env = "UZ_BOT_CONFIG")]
+    pub config: Option<PathBuf>,
+    /// Overrides a config value, e.g. `--set scheduler.poll_interval_secs=5`. Repeatable.
+    #[arg(long = "set", value_name = "KEY=VALUE")]
+    pub overrides: Vec<String>,
+    /// Validates the config and exits.
+    #[arg(long)]
+    pub check_config: bool,
+}
+
+#[derive(Debug, Error)]
+pub enum ConfigError {
+    #[error("Error reading config file {0}: {1}")]
+    Read(PathBuf, std::io::Error),
+    #[error("Error parsing config file {0}: {1}")]
+    Parse(PathBuf, toml::de::Error),
+    #[error("Wrong override {0}: {1}")]
+    Override(String, &'static str),
+    #[error("Wrong config: {0}")]
+    Deserialize(toml::de::Error),
+    #[error("Wrong {0}: {1}")]
+    Invalid(&'static str, String),
+}
+
+/// Settings of the bot. Defaults are overridden by the config file, then by env vars,
+/// then by `--set` flags, see [`Config::load`].
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub telegram: TelegramConfig,
+    pub scheduler: SchedulerConfig,
+    pub alerts: AlertsConfig,
+    pub uz: UzConfig,
+    pub storage: StorageConfig,
+    pub log: LogConfig,
+    pub http: HttpConfig,
+    pub health: HealthConfig,
+    pub shutdown: ShutdownConfig,
+    pub admin: AdminConfig,
+}
+
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TelegramConfig {
+    pub bot_api_key: String,
+    pub api_url: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SchedulerConfig {
+    /// Pause between checks of the UZ page and reminders.
+    pub poll_interval_secs: u64,
+    /// Reminders are sent this many minutes before departure.
+    pub reminder_offsets_minutes: Vec<i64>,
+    /// Chats with a live status message get a new message only for a delay change this large.
+    pub live_status_delay_change_minutes: usize,
+}
+
+/// Disruption alerts, sent as soon as a ticket train is listed with a long delay.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AlertsConfig {
+    pub major_delay_minutes: usize,
+    pub severe_delay_minutes: usize,
+    /// Alerts mention refund of an unused ticket from this delay, check the current UZ rules.
+    pub refund_delay_minutes: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct UzConfig {
+    pub delays_url: String,
+    /// Sent to the delays page and when downloading tickets from UZ app links.
+    /// Names are lowercased, so that a file, env var or flag replaces a default header.
+    pub headers: BTreeMap<String, String>,
+    pub request_timeout_secs: u64,
+    pub base_backoff_secs: u64,
+    pub max_backoff_secs: u64,
+    pub failure_threshold: u32,
+    pub open_duration_secs: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct StorageConfig {
+    pub backend: StorageBackend,
+}
+
+/// Where tickets are kept. Only memory is implemented, tickets are lost on restart.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StorageBackend {
+    #[default]
+    Memory,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LogConfig {
+    pub directory: PathBuf,
+    pub file_prefix: String,
+    /// Level of the log file, `RUST_LOG` directives are applied on top.
+    pub file_level: String,
+    pub stdout_level: String,
+}
+
+/// Limits of `/healthz` and `/readyz`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HealthConfig {
+    /// Bot is not alive, when the scheduler has not started a check for longer.
+    pub scheduler_max_tick_age_secs: u64,
+    /// Bot is not ready, when the UZ page was last fetched longer ago.
+    pub uz_max_success_age_secs: u64,
+    /// Bot is not ready, when the Bot API does not answer in time.
+    pub telegram_timeout_secs: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ShutdownConfig {
+    /// Time for answering updates being handled, after a stop signal.
+    pub drain_timeout_secs: u64,
+    /// Sent reminders are saved there on shutdown and read on start.
+    pub state_file: PathBuf,
+}
+
+/// Operators allowed to use `/admin` commands.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AdminConfig {
+    /// Telegram user ids, commands are accepted only in private chats with them.
+    pub user_ids: Vec<i64>,
+    /// Messages a second sent by `/admin broadcast`, the Bot API allows 30.
+    pub broadcast_per_second: u32,
+    /// Progress of a broadcast, so that it continues after restart.
+    pub broadcast_state_file: PathBuf,
+}
+
+/// Server of `/metrics` for Prometheus and of health endpoints.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HttpConfig {
+    pub enabled: bool,
+    pub listen: SocketAddr,
+}
+
+impl Default for TelegramConfig {
+    fn default() -> Self {
+        Self {
+            bot_api_key: String::new(),
+            api_url: "https://api.telegram.org".to_owned(),
+        }
+    }
+}
+
+/// Hides the bot key, as the config is logged on startup.
+impl Debug for TelegramConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TelegramConfig")
+            .field("bot_api_key", &"***")
+            .field("api_url", &self.api_url)
+            .finish()
+    }
+}
+
+impl Default for SchedulerConfig {
+    fn default() -> Self {
+        Self {
+            poll_interval_secs: 10,
+            reminder_offsets_minutes: vec![60, 30, 15],
+            live_status_delay_change_minutes: 15,
+        }
+    }
+}
+
+impl Default for AlertsConfig {
+    fn default() -> Self {
+        let thresholds = SeverityThresholds::default();
+        Self {
+            major_delay_minutes: thresholds.major_minutes,
+            severe_delay_minutes: thresholds.severe_minutes,
+            refund_delay_minutes: 180,
+        }
+    }
+}
+
+impl Default for UzConfig {
+    fn default() -> Self {
+        let policy = FetchPolicy::default();
+        Self {
+            delays_url: UZ_DELAYS_URL_STR.to_owned(),
+            headers: BTreeMap::from([
+                ("accept".to_owned(), UZ_ACCEPT.to_owned()),
+                ("user-agent".to_owned(), UZ_USER_AGENT.to_owned()),
+            ]),
+            request_timeout_secs: policy.request_timeout.as_secs(),
+            base_backoff_secs: policy.base_backoff.as_secs(),
+            max_backoff_secs: policy.max_backoff.as_secs(),
+            failure_threshold: policy.failure_threshold,
+            open_duration_secs: policy.open_duration.as_secs(),
+        }
+    }
+}
+
+impl Default for LogConfig {
+    fn default() -> Self {
+        Self {
+            directory: PathBuf::from("logs"),
+            file_prefix: "uzbot.log".to_owned(),
+            file_level: "debug".to_owned(),
+            stdout_level: "info".to_owned(),
+        }
+    }
+}
+
+impl Default for HttpConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            listen: SocketAddr::from(([127, 0, 0, 1], 9100)),
+        }
+    }
+}
+
+impl Default for HealthConfig {
+    fn default() -> Self {
+        Self {
+            scheduler_max_tick_age_secs: 120,
+            uz_max_success_age_secs: 15 * 60,
+            telegram_timeout_secs: 5,
+        }
+    }
+}
+
+impl Default for ShutdownConfig {
+    fn default() -> Self {
+        Self {
+            drain_timeout_secs: 20,
+            state_file: PathBuf::from("scheduler_state.json"),
+        }
+    }
+}
+
+impl Default for AdminConfig {
+    fn default() -> Self {
+        Self {
+            user_ids: Vec::new(),
+            broadcast_per_second: 20,
+            broadcast_state_file: PathBuf::from("broadcast_state.json"),
+        }
+    }
+}
+
+impl Config {
+    /// Reads the config file of `cli`, applies env var and `--set` overrides and validates the result.
+    pub fn load(
+        cli: &Cli,
+        env: impl IntoIterator<Item = (String, String)>,
+    ) -> Result<Self, ConfigError> {
+        let mut table = toml::Table::try_from(Config::default())
+            .expect("default config is serializable to a table");
+
+        let path = cli
+            .config
+            .clone()
+            .or_else(|| Some(PathBuf::from(DEFAULT_CONFIG_PATH)).filter(|path| path.exists()));
+        if let Some(path) = path {
+            let mut file = read_file(&path)?;
+            lowercase_header_names(&mut file);
+            merge(&mut table, file);
+        }
+
+        // legacy vars go first, so that prefixed ones win
+        let env = env.into_iter().collect::<Vec<_>>();
+        let legacy = LEGACY_ENV_VARS.iter().filter_map(|(legacy, key)| {
+            let (_, value) = env.iter().find(|(name, _)| name == legacy)?;
+            Some((key.to_string(), toml::Value::String(value.clone())))
+        });
+        let prefixed = env.iter().filter_map(|(name, value)| {
+            let key = name.strip_prefix(ENV_PREFIX)?.to_lowercase();
+            Some((key.replace("__", "."), parse_value(value)))
+        });
+        for (key, value) in legacy.chain(prefixed) {
+            apply_override(&mut table, &key, value)?;
+        }
+
+        for flag in &cli.overrides {
+            let (key, value) = flag
+                .split_once('=')
+                .ok_or(ConfigError::Override(flag.clone(), "expected KEY=VALUE"))?;
+            apply_override(&mut table, key.trim(), parse_value(value.trim()))?;
+        }
+
+        let config: Config = table.try_into().map_err(ConfigError::Deserialize)?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if self.telegram.bot_api_key.trim().is_empty() {
+            return Err(ConfigError::Invalid(
+                "telegram.bot_api_key",
+                "not set, use TELEGRAM_BOT_API_KEY env var".to_owned(),
+            ));
+        }
+        self.telegram.api_url()?;
+
+        if self.scheduler.poll_interval_secs == 0 {
+            return Err(ConfigError::Invalid(
+                "scheduler.poll_interval_secs",
+                "must be positive".to_owned(),
+            ));
+        }
+        if self.scheduler.live_status_delay_change_minutes == 0 {
+            return Err(ConfigError::Invalid(
+                "scheduler.live_status_delay_change_minutes",
+                "must be positive".to_owned(),
+            ));
+        }
+        let offsets = &self.scheduler.reminder_offsets_minutes;
+        if offsets.is_empty()
+            || offsets
+                .iter()
+                .any(|minutes| !(1..=MAX_REMINDER_OFFSET_MINUTES).contains(minutes))
+        {
+            return Err(ConfigError::Invalid(
+                "scheduler.reminder_offsets_minutes",
+                format!(
+                    "must be a non-empty list of minutes from 1 to {MAX_REMINDER_OFFSET_MINUTES}"
+                ),
+            ));
+        }
+        if self
+            .scheduler
+            .reminder_offsets()
+            .windows(2)
+            .any(|w| w[0] == w[1])
+        {
+            return Err(ConfigError::Invalid(
+                "scheduler.reminder_offsets_minutes",
+                "must not repeat".to_owned(),
+            ));
+        }
+
+        self.uz.url()?;
+        self.uz.headers()?;
+        let uz = &self.uz;
+        if uz.request_timeout_secs == 0 || uz.base_backoff_secs == 0 || uz.failure_threshold == 0 {
+            return Err(ConfigError::Invalid(
+                "uz",
+                "request_timeout_secs, base_backoff_secs and failure_threshold must be positive"
+                    .to_owned(),
+            ));
+        }
+        if uz.max_backoff_secs < uz.base_backoff_secs {
+            return Err(ConfigError::Invalid(
+                "uz.max_backoff_secs",
+                "must not be less than base_backoff_secs".to_owned(),
+            ));
+        }
+
+        if self.log.file_prefix.trim().is_empty() {
+            return Err(ConfigError::Invalid(
+                "log.file_prefix",
+                "must not be empty".to_owned(),
+            ));
+        }
+        self.log.file_level()?;
+        self.log.stdout_level()?;
+
+        let alerts = &self.alerts;
+        if alerts.major_delay_minutes == 0
+            || alerts.major_delay_minutes >= alerts.severe_delay_minutes
+        {
+            return Err(ConfigError::Invalid(
+                "alerts",
+                "major_delay_minutes must be positive and below severe_delay_minutes".to_owned(),
+            ));
+        }
+
+        let health = &self.health;
+        if health.scheduler_max_tick_age_secs <= self.scheduler.poll_interval_secs {
+            return Err(ConfigError::Invalid(
+                "health.scheduler_max_tick_age_secs",
+                "must be longer than scheduler.poll_interval_secs".to_owned(),
+            ));
+        }
+        if health.uz_max_success_age_secs == 0 || health.telegram_timeout_secs == 0 {
+            return Err(ConfigError::Invalid(
+                "health",
+                "uz_max_success_age_secs and telegram_timeout_secs must be positive".to_owned(),
+            ));
+        }
+
+        if self.shutdown.drain_timeout_secs == 0 {
+            return Err(ConfigError::Invalid(
+                "shutdown.drain_timeout_secs",
+                "must be positive".to_owned(),
+            ));
+        }
+
+        if !(1..=30).contains(&self.admin.broadcast_per_second) {
+            return Err(ConfigError::Invalid(
+                "admin.broadcast_per_second",
+                "must be from 1 to 30".to_owned(),
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+impl TelegramConfig {
+    pub fn api_url(&self) -> Result<Url, ConfigError> {
+        parse_url("telegram.api_url", &self.api_url)
+    }
+}
+
+impl SchedulerConfig {
+    pub fn poll_interval(&self) -> Duration {
+        Duration::from_secs(self.poll_interval_secs)
+    }
+
+    /// Longest first, as the scheduler expects.
+    pub fn reminder_offsets(&self) -> Vec<TimeDelta> {
+        let mut offsets = self
+            .reminder_offsets_minutes
+            .iter()
+            .map(|minutes| TimeDelta::minutes(*minutes))
+            .collect::<Vec<_>>();
+        offsets.sort_by(|a, b| b.cmp(a));
+        offsets
+    }
+}
+
+impl UzConfig {
+    pub fn url(&self) -> Result<Url, ConfigError> {
+        parse_url("uz.delays_url", &self.delays_url)
+    }
+
+    pub fn headers(&self) -> Result<HeaderMap, ConfigError> {
+        self.headers
+            .iter()
+            .map(|(name, value)| {
+                let name = name
+                    .parse::<HeaderName>()
+                    .map_err(|e| ConfigError::Invalid("uz.headers", format!("{name}: {e}")))?;
+                let value = value
+                    .parse::<HeaderValue>()
+                    .map_err(|e| ConfigError::Invalid("uz.headers", format!("{name}: {e}")))?;
+                Ok((name, value))
+            })
+            .collect()
+    }
+
+    pub fn fetch_policy(&self) -> FetchPolicy {
+        FetchPolicy {
+            request_timeout: Duration::from_secs(self.request_timeout_secs),
+            base_backoff: Duration::from_secs(self.base_backoff_secs),
+            max_backoff: Duration::from_secs(self.max_backoff_secs),
+            failure_threshold: self.failure_threshold,
+            open_duration: Duration::from_secs(self.open_duration_secs),
+        }
+    }
+}
+
+impl AlertsConfig {
+    pub fn thresholds(&self) -> SeverityThresholds {
+        SeverityThresholds {
+            major_minutes: self.major_delay_minutes,
+            severe_minutes: self.severe_delay_minutes,
+        }
+    }
+}
+
+impl HealthConfig {
+    pub fn scheduler_max_tick_age(&self) -> TimeDelta {
+        TimeDelta::seconds(self.scheduler_max_tick_age_secs as i64)
+    }
+
+    pub fn uz_max_success_age(&self) -> TimeDelta {
+        TimeDelta::seconds(self.uz_max_success_age_secs as i64)
+    }
+
+    pub fn telegram_timeout(&self) -> Duration {
+        Duration::from_secs(self.telegram_timeout_secs)
+    }
+}
+
+impl ShutdownConfig {
+    pub fn drain_timeout(&self) -> Duration {
+        Duration::from_secs(self.drain_timeout_secs)
+    }
+}
+
+impl LogConfig {
+    pub fn file_level(&self) -> Result<LevelFilter, ConfigError> {
+        parse_level("log.file_level", &self.file_level)
+    }
+
+    pub fn stdout_level(&self) -> Result<LevelFilter, ConfigError> {
+        parse_level("log.stdout_level", &self.stdout_level)
+    }
+}
+
+fn read_file(path: &Path) -> Result<toml::Table, ConfigError> {
+    let content =
+        std::fs::read_to_string(path).map_err(|e| ConfigError::Read(path.to_owned(), e))?;
+    content
+        .parse::<toml::Table>()
+        .map_err(|e| ConfigError::Parse(path.to_owned(), e))
+}
+
+/// Values of `overrides` replace the ones of `table`, nested tables are merged key by key.
+fn merge(table: &mut toml::Table, overrides: toml::Table) {
+    for (key, value) in overrides {
+        match (table.get_mut(&key), value) {
+            (Some(toml::Value::Table(existing)), toml::Value::Table(value)) => {
+                merge(existing, value)
+            }
+            (_, value) => {
+                table.insert(key, value);
+            }
+        }
+    }
+}
+
+/// Header names of a layer are case-insensitive, e.g. `User-Agent` replaces the default
+/// `user-agent` instead of being sent as a second header.
+fn lowercase_header_names(layer: &mut toml::Table) {
+    let Some(headers) = layer
+        .get_mut("uz")
+        .and_then(|uz| uz.get_mut("headers"))
+        .and_then(|headers| headers.as_table_mut())
+    else {
+        return;
+    };
+    *headers = std::mem::take(headers)
+        .into_iter()
+        .map(|(name, value)| (name.to_lowercase(), value))
+        .collect();
+}
+
+/// Value written in TOML, e.g. `[60, 30]`. Anything that is not valid TOML is taken as a string,
+/// so that `--set log.file_level=info` needs no quotes.
+fn parse_value(value: &str) -> toml::Value {
+    format!("value = {value}")
+        .parse::<toml::Table>()
+        .ok()
+        .and_then(|mut parsed| parsed.remove("value"))
+        .unwrap_or_else(|| toml::Value::String(value.to_owned()))
+}
+
+/// Sets dotted `key`, like `scheduler.poll_interval_secs`, to `value`.
+fn apply_override(
+    table: &mut toml::Table,
+    key: &str,
+    value: toml::Value,
+) -> Result<(), ConfigError> {
+    let override_error = |reason| ConfigError::Override(key.to_owned(), reason);
+
+    let mut path = key.split('.').collect::<Vec<_>>();
+    let last = path.pop().filter(|last| !last.is_empty());
+    let last = last.ok_or(override_error("empty key"))?;
+
+    let mut override_table = toml::Table::new();
+    override_table.insert(last.to_owned(), value);
+    for section in path.into_iter().rev() {
+        if section.is_empty() {
+            return Err(override_error("empty key"));
+        }
+        let mut parent = toml::Table::new();
+        parent.insert(section.to_owned(), toml::Value::Table(override_table));
+        override_table = parent;
+    }
+
+    lowercase_header_names(&mut override_table);
+    merge(table, override_table);
+    Ok(())
+}
+
+fn parse_url(field: &'static str, url: &str) -> Result<Url, ConfigError> {
+    let parsed = url
+        .parse::<Url>()
+        .map_err(|e| ConfigError::Invalid(field, format!("{url}: {e}")))?;
+    match parsed.scheme() {
+        "http" | "https" => Ok(parsed),
+        scheme => Err(ConfigError::Invalid(
+            field,
+            format!("{url}: unsupported scheme {scheme}"),
+        )),
+    }
+}
+
+fn parse_level(field: &'static str, level: &str) -> Result<LevelFilter, ConfigError> {
+    level
+        .parse()
+        .map_err(|e| ConfigError::Invalid(field, format!("{level}: {e}")))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use chrono::TimeDelta;
+    use reqwest::header::{ACCEPT, USER_AGENT};
+
+    use super::{Cli, Config, ConfigError, StorageBackend};
+
+    fn cli(config: Option<PathBuf>, overrides: &[&str]) -> Cli {
+        Cli {
+            config,
+            overrides: overrides.iter().map(|o| o.to_string()).collect(),
+            check_config: false,
+        }
+    }
+
+    fn env(vars: &[(&str, &str)]) -> Vec<(String, String)> {
+        vars.iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_config_layers() {
+        let path = std::env::temp_dir().join(format!("uz_bot_config_{}.toml", std::process::id()));
+        std::fs::write(
+            &path,
+            r#"
+            [scheduler]
+            poll_interval_secs = 30
+            reminder_offsets_minutes = [15, 120]
+
+            [uz.headers]
+            user-agent = "file agent"
+
+            [log]
+            file_prefix = "file.log"
+            "#,
+        )
+        .unwrap();
+
+        let config = Config::load(
+            &cli(
+                Some(path.clone()),
+                &["scheduler.poll_interval_secs=5", "log.stdout_level=warn"],
+            ),
+            env(&[
+                ("TELEGRAM_BOT_API_KEY", "legacy key"),
+                ("UZ_BOT__TELEGRAM__BOT_API_KEY", "new key"),
+                ("UZ_BOT__SCHEDULER__POLL_INTERVAL_SECS", "20"),
+                ("LOG_FILE_PREFIX", "env.log"),
+                ("UNRELATED", "value"),
+            ]),
+        )
+        .unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(config.telegram.bot_api_key, "new key");
+        assert_eq!(config.scheduler.poll_interval_secs, 5, "flag wins");
+        assert_eq!(
+            config.scheduler.reminder_offsets(),
+            vec![TimeDelta::minutes(120), TimeDelta::minutes(15)]
+        );
+        assert_eq!(config.uz.headers["user-agent"], "file agent");
+        assert!(config.uz.headers.contains_key("accept"), "default kept");
+        assert_eq!(config.log.file_prefix, "env.log", "env wins over file");
+        assert_eq!(config.log.stdout_level, "warn");
+        assert_eq!(config.storage.backend, StorageBackend::Memory);
+    }
+
+    #[test]
+    fn test_header_names_are_case_insensitive() {
+        let path = std::env::temp_dir().join(format!("uz_bot_headers_{}.toml", std::process::id()));
+        std::fs::write(
+            &path,
+            r#"
+            [uz.headers]
+            User-Agent = "file agent"
+            "#,
+        )
+        .unwrap();
+
+        let config = Config::load(
+            &cli(Some(path.clone()), &["uz.headers.ACCEPT=text/html"]),
+            env(&[("TELEGRAM_BOT_API_KEY", "key")]),
+        )
+        .unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(config.uz.headers["user-agent"], "file agent");
+        assert_eq!(config.uz.headers["accept"], "text/html");
+        let headers = config.uz.headers().unwrap();
+        assert_eq!(headers.get_all(USER_AGENT).iter().count(), 1);
+        assert_eq!(headers.get_all(ACCEPT).iter().count(), 1);
+    }
+
+    #[test]
+    fn test_config_validation() {
+        let load = |overrides: &[&str]| {
+            Config::load(
+                &cli(None, overrides),
+                env(&[("TELEGRAM_BOT_API_KEY", "key")]),
+            )
+        };
+
+        assert!(load(&[]).is_ok());
+        assert!(matches!(
+            Config::load(&cli(None, &[]), env(&[])),
+            Err(ConfigError::Invalid("telegram.bot_api_key", _))
+        ));
+
+        let invalid = [
+            "scheduler.poll_interval_secs=0",
+            "scheduler.reminder_offsets_minutes=[]",
+            "scheduler.reminder_offsets_minutes=[30, 30]",
+            "scheduler.reminder_offsets_minutes=[-5]",
+            "scheduler.reminder_offsets_minutes=[9223372036854775807]",
+            "scheduler.live_status_delay_change_minutes=0",
+            "uz.delays_url=not a url",
+            "uz.delays_url=ftp://uz.gov.ua",
+            "uz.headers.user-agent=\"line\\nbreak\"",
+            "uz.max_backoff_secs=1",
+            "log.file_level=loud",
+            "health.scheduler_max_tick_age_secs=5",
+            "shutdown.drain_timeout_secs=0",
+            "alerts.major_delay_minutes=0",
+            "alerts.severe_delay_minutes=30",
+            "admin.broadcast_per_second=0",
+            "admin.broadcast_per_second=100",
+        ];
+        for flag in invalid {
+            assert!(
+                matches!(load(&[flag]), Err(ConfigError::Invalid(..))),
+                "{flag}"
+            );
+        }
+
+        assert!(matches!(
+            load(&["storage.backend=postgres"]),
+            Err(ConfigError::Deserialize(_))
+        ));
+        assert!(matches!(
+            load(&["scheduler.poll_intreval_secs=5"]),
+            Err(ConfigError::Deserialize(_))
+        ));
+        assert!(matches!(
+            load(&["poll_interval_secs"]),
+            Err(ConfigError::Override(..))
+        ));
+        assert!(matches!(
+            Config::load(
+                &cli(Some(PathBuf::from("/nonexistent/config.toml")), &[]),
+                env(&[])
+            ),
+            Err(ConfigError::Read(..))
+        ));
+    }
+}
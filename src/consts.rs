@@ -1,24 +1,64 @@
 use chrono::TimeDelta;
-use tokio::time::Duration;
 
-pub const SLEEP_BEFORE_FETCH_TRAINS: Duration = Duration::from_secs(10);
-pub const NOTIFY_BEFORE_TRAIN: [TimeDelta; 3] = [
-    TimeDelta::minutes(15),
-    TimeDelta::minutes(30),
-    TimeDelta::minutes(60),
-];
+/// Delay of a ticket train departing later than this is assumed to be of an earlier run.
+pub const SAME_RUN_MAX_LEAD: TimeDelta = TimeDelta::hours(24);
+/// Trains departing sooner than this are surely on the same run as a delay listed now.
+pub const SAME_RUN_CERTAIN_LEAD: TimeDelta = TimeDelta::hours(12);
+/// Time after the ticket arrival, or departure if unknown, while the train can still be listed.
+pub const SAME_RUN_MAX_TRAVEL: TimeDelta = TimeDelta::hours(12);
+/// Connection is reported at risk, when a delay leaves less time than this for the transfer.
+pub const CONNECTION_AT_RISK_BUFFER: TimeDelta = TimeDelta::minutes(15);
 
-// pub const NOTIFY_BEFORE_TRAIN: [TimeDelta; 3] = [
-//     TimeDelta::seconds(60),
-//     TimeDelta::seconds(300),
-//     TimeDelta::seconds(150),
-// ];
+/// `/status` reuses delays fetched by the scheduler or another `/status` within this time.
+pub const STATUS_MAX_AGE: TimeDelta = TimeDelta::seconds(30);
+/// `/readyz` reuses the Bot API answer within this time.
+pub const TELEGRAM_CHECK_MAX_AGE: TimeDelta = TimeDelta::seconds(30);
 
-// pub const NOTIFY_BEFORE_TRAIN: [TimeDelta; 3] = [
-//     TimeDelta::seconds(15),
-//     TimeDelta::seconds(30),
-//     TimeDelta::seconds(60),
-// ];
+/// How long `/watch` reports delays of a train, unless other number of hours is given.
+pub const WATCH_DEFAULT_DURATION: TimeDelta = TimeDelta::hours(6);
+pub const WATCH_MAX_DURATION: TimeDelta = TimeDelta::hours(24);
+pub const DELAY_HISTORY_RETENTION: TimeDelta = TimeDelta::weeks(12);
+pub const STATS_DEFAULT_WEEKS: i64 = 4;
+/// Delayed days on the same weekday needed to predict the delay of a new ticket.
+pub const PREDICTION_MIN_SAMPLES: usize = 2;
 
 pub const EXTRACT_TICKET_ERROR_MESSAGE: &str = "Error extracting pdf data.";
 pub const LAYOUT_CHANGED_MESSAGE: &str = "Possibly, ticket layout has changed.";
+pub const DELAYS_UNAVAILABLE_MESSAGE: &str =
+    "Delay information is currently unavailable, please try again later.";
+pub const UNKNOWN_COMMAND_MESSAGE: &str = "Unknown command.";
+pub const UNKNOWN_BUTTON_MESSAGE: &str = "This button is no longer supported.";
+pub const NO_TICKETS_MESSAGE: &str = "You have no tickets yet. Send .pdf ticket to add one.";
+pub const NO_JOURNEYS_MESSAGE: &str =
+    "You have no journeys yet. Group tickets with a transfer using /journey <n> <n>, see /tickets.";
+pub const TICKET_INDEX_ERROR_MESSAGE: &str = "Wrong ticket number, see /tickets.";
+/// Buttons offered in `/add` for a station name not found exactly.
+pub const STATION_SUGGESTIONS: usize = 3;
+pub const QUIET_USAGE_MESSAGE: &str =
+    "Usage: /quiet 23:00-07:00 [silent|suppress|collapse] or /quiet off.";
+pub const TIMEZONE_USAGE_MESSAGE: &str =
+    "Usage: /timezone <Area/City>, e.g. /timezone Europe/Warsaw, or /timezone Europe/Kyiv to show only Kyiv time.";
+pub const LIVE_USAGE_MESSAGE: &str = "Usage: /live on, /live pin or /live off.";
+pub const TRAIN_NUMBER_ERROR_MESSAGE: &str =
+    "Train number must be digits with optional letters, e.g. 743 or 043К.";
+
+/// Prefix of `/start` payload in invite links, e.g. `t.me/bot?start=share_<code>`.
+pub const INVITE_PREFIX: &str = "share_";
+pub const INVITE_CODE_LEN: usize = 16;
+
+/// Exit code, when updates were still being handled at the shutdown deadline.
+pub const EXIT_DRAIN_TIMEOUT: u8 = 3;
+/// Exit code, when sent reminders could not be saved on shutdown.
+pub const EXIT_STATE_NOT_SAVED: u8 = 4;
+
+pub const ADMIN_USAGE_MESSAGE: &str =
+    "Usage: /admin stats | user <chat id> | parse-failures | upstream | broadcast <audience>, with the text on the next lines";
+pub const BROADCAST_RUNNING_MESSAGE: &str =
+    "Another broadcast is in progress, wait for its report.";
+/// Ticket parse failures kept for `/admin parse-failures`.
+pub const ADMIN_PARSE_FAILURES_KEPT: usize = 20;
+/// `/admin stats` counts departures within this time and lists the first few of them.
+pub const ADMIN_UPCOMING_WITHIN: TimeDelta = TimeDelta::hours(24);
+pub const ADMIN_UPCOMING_LISTED: usize = 5;
+/// Broadcast progress is saved and reported to the admin after this many recipients.
+pub const BROADCAST_PROGRESS_EVERY: usize = 50;
@@ -0,0 +1,633 @@
+use std::collections::VecDeque;
+
+use chrono::{DateTime, Datelike, Days, Months, NaiveDate, NaiveTime, TimeZone};
+use chrono_tz::{Europe::Kyiv, Tz};
+use dashmap::DashMap;
+use pdf_parser::{TicketData, TicketStation};
+use rclite::Arc;
+use stations::Station;
+use telegram::{Button, ChatId, Keyboard, Reply};
+use train_number::TrainNumber;
+
+use crate::consts::{STATION_SUGGESTIONS, UNKNOWN_BUTTON_MESSAGE};
+
+/// Prefix of callback data of the `/add` date picker buttons.
+pub const ADD_CALLBACK_PREFIX: &str = "add:";
+
+/// State of `/add` conversations, which build a ticket from answers instead of a pdf.
+#[derive(Clone, Default)]
+pub struct Conversations(Arc<DashMap<ChatId, AddTicketStep>>);
+
+#[derive(Debug, Clone)]
+enum AddTicketStep {
+    TrainNumber,
+    Date {
+        train_number: TrainNumber,
+    },
+    Time {
+        train_number: TrainNumber,
+        date: NaiveDate,
+    },
+    Arrival {
+        train_number: TrainNumber,
+        departure_datetime: DateTime<Tz>,
+    },
+    Stations {
+        train_number: TrainNumber,
+        departure_datetime: DateTime<Tz>,
+        arrival_datetime: Option<DateTime<Tz>>,
+    },
+    /// A station name is not in the directory exactly, one of the closest is to be picked.
+    ConfirmStation {
+        /// Stations are filled in as they are found, departure first.
+        ticket: TicketData,
+        /// Station names not looked up yet.
+        pending: VecDeque<String>,
+        /// Full names of the stations on the buttons.
+        suggestions: Vec<String>,
+    },
+}
+
+/// Result of a conversation step: either the next question or the complete ticket.
+pub enum Step {
+    Ask(Reply),
+    Done(TicketData),
+}
+
+impl Conversations {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn start(&self, user: ChatId) -> Reply {
+        self.0.insert(user, AddTicketStep::TrainNumber);
+        "Send train number, e.g. 743 or 043К.\n/cancel - stop adding ticket"
+            .to_owned()
+            .into()
+    }
+
+    /// Returns `false`, if there was no conversation to cancel.
+    pub fn cancel(&self, user: ChatId) -> bool {
+        self.0.remove(&user).is_some()
+    }
+
+    /// Handles a text answer. `None` means the chat has no conversation in progress.
+    pub fn answer(
+        &self,
+        user: ChatId,
+        text: &str,
+        now: DateTime<Tz>,
+    ) -> Option<Result<Step, String>> {
+        let step = self.0.get(&user)?.value().clone();
+        let text = text.trim();
+        let today = now.date_naive();
+
+        let result = match step {
+            AddTicketStep::TrainNumber => {
+                let Some(train_number) = parse_train_number(text) else {
+                    return Some(Err(
+                        "Train number must be up to 4 digits with optional letters, e.g. 743 or 043К."
+                            .to_owned(),
+                    ));
+                };
+                self.0.insert(user, AddTicketStep::Date { train_number });
+                Ok(Step::Ask(ask_date(today, today)))
+            }
+            AddTicketStep::Date { train_number } => {
+                let Ok(date) = NaiveDate::parse_from_str(text, "%d.%m.%Y") else {
+                    return Some(Err(
+                        "Pick departure date below or send it as dd.mm.yyyy.".to_owned()
+                    ));
+                };
+                self.choose_date(user, train_number, date, today)
+            }
+            AddTicketStep::Time { train_number, date } => {
+                let Some(time) = parse_time(text) else {
+                    return Some(Err("Departure time must look like 20:36.".to_owned()));
+                };
+                let Some(departure_datetime) =
+                    Kyiv.from_local_datetime(&date.and_time(time)).single()
+                else {
+                    return Some(Err("This time doesn't exist in Kyiv time zone.".to_owned()));
+                };
+                if departure_datetime <= now {
+                    return Some(Err("The train has already departed.".to_owned()));
+                }
+                self.0.insert(
+                    user,
+                    AddTicketStep::Arrival {
+                        train_number,
+                        departure_datetime,
+                    },
+                );
+                Ok(Step::Ask(
+                    "Send arrival time, e.g. 07:05, to receive delay updates until arrival and warnings about transfers.\n/skip - arrival time is unknown"
+                        .to_owned()
+                        .into(),
+                ))
+            }
+            AddTicketStep::Arrival {
+                train_number,
+                departure_datetime,
+            } => {
+                let Some(arrival_datetime) = parse_arrival(text, departure_datetime) else {
+                    return Some(Err(
+                        "Arrival time must look like 07:05 or 20.05.2024 07:05.".to_owned(),
+                    ));
+                };
+                Ok(self.ask_stations(
+                    user,
+                    train_number,
+                    departure_datetime,
+                    Some(arrival_datetime),
+                ))
+            }
+            AddTicketStep::Stations {
+                train_number,
+                departure_datetime,
+                arrival_datetime,
+            } => {
+                let ticket = TicketData {
+                    departure_datetime,
+                    train_number,
+                    arrival_datetime,
+                    departure_station: None,
+                    arrival_station: None,
+                };
+                self.answer_stations(user, ticket, text)
+            }
+            // stations sent again instead of picking a suggestion
+            AddTicketStep::ConfirmStation { ticket, .. } => {
+                let ticket = TicketData {
+                    departure_station: None,
+                    arrival_station: None,
+                    ..ticket
+                };
+                self.answer_stations(user, ticket, text)
+            }
+        };
+
+        Some(result)
+    }
+
+    /// Skips arrival time or stations, if they are being asked.
+    pub fn skip(&self, user: ChatId) -> Option<Step> {
+        let step = self.0.get(&user)?.value().clone();
+        match step {
+            AddTicketStep::Arrival {
+                train_number,
+                departure_datetime,
+            } => Some(self.ask_stations(user, train_number, departure_datetime, None)),
+            AddTicketStep::Stations {
+                train_number,
+                departure_datetime,
+                arrival_datetime,
+            } => {
+                self.0.remove(&user);
+                Some(Step::Done(TicketData {
+                    departure_datetime,
+                    train_number,
+                    arrival_datetime,
+                    departure_station: None,
+                    arrival_station: None,
+                }))
+            }
+            AddTicketStep::ConfirmStation { ticket, .. } => {
+                self.0.remove(&user);
+                Some(Step::Done(TicketData {
+                    departure_station: None,
+                    arrival_station: None,
+                    ..ticket
+                }))
+            }
+            _ => None,
+        }
+    }
+
+    fn ask_stations(
+        &self,
+        user: ChatId,
+        train_number: TrainNumber,
+        departure_datetime: DateTime<Tz>,
+        arrival_datetime: Option<DateTime<Tz>>,
+    ) -> Step {
+        self.0.insert(
+            user,
+            AddTicketStep::Stations {
+                train_number,
+                departure_datetime,
+                arrival_datetime,
+            },
+        );
+        Step::Ask(
+            "Send departure and arrival stations, e.g. Київ - Львів or Kyiv - Lviv, to tell your train from other runs with the same number.\n/skip - add ticket without stations"
+                .to_owned()
+                .into(),
+        )
+    }
+
+    fn answer_stations(
+        &self,
+        user: ChatId,
+        ticket: TicketData,
+        text: &str,
+    ) -> Result<Step, String> {
+        let (departure, arrival) = parse_stations(text)?;
+        self.fill_stations(
+            user,
+            ticket,
+            VecDeque::from([departure.to_owned(), arrival.to_owned()]),
+        )
+    }
+
+    /// Fills in stations found in the directory exactly. For the first name which is not,
+    /// asks to pick one of the closest stations, instead of guessing.
+    fn fill_stations(
+        &self,
+        user: ChatId,
+        mut ticket: TicketData,
+        mut pending: VecDeque<String>,
+    ) -> Result<Step, String> {
+        while let Some(name) = pending.pop_front() {
+            let name = name.trim();
+            if let Some(station) = stations::find(name) {
+                fill_next_station(&mut ticket, station);
+                continue;
+            }
+
+            let suggestions = stations::search(name, STATION_SUGGESTIONS)
+                .into_iter()
+                .map(|station| station.name.clone())
+                .collect::<Vec<_>>();
+            if suggestions.is_empty() {
+                self.0.insert(
+                    user,
+                    AddTicketStep::Stations {
+                        train_number: ticket.train_number,
+                        departure_datetime: ticket.departure_datetime,
+                        arrival_datetime: ticket.arrival_datetime,
+                    },
+                );
+                return Err(format!(
+                    "Station {name} is not found, check the spelling or /skip."
+                ));
+            }
+
+            let keyboard = Keyboard(
+                suggestions
+                    .iter()
+                    .enumerate()
+                    .map(|(index, suggestion)| {
+                        vec![Button {
+                            text: suggestion.clone(),
+                            data: format!("{ADD_CALLBACK_PREFIX}station:{index}"),
+                        }]
+                    })
+                    .collect(),
+            );
+            self.0.insert(
+                user,
+                AddTicketStep::ConfirmStation {
+                    ticket,
+                    pending,
+                    suggestions,
+                },
+            );
+            return Ok(Step::Ask(Reply {
+                text: format!("Station {name} is not found exactly. Which one is it? Or send the stations again."),
+                keyboard: Some(keyboard),
+            }));
+        }
+
+        self.0.remove(&user);
+        Ok(Step::Done(ticket))
+    }
+
+    /// Handles date picker and station buttons, `data` is without [`ADD_CALLBACK_PREFIX`].
+    pub fn callback(&self, user: ChatId, data: &str, now: DateTime<Tz>) -> Result<Step, String> {
+        if let Some(index) = data.strip_prefix("station:") {
+            return self.choose_station(user, index);
+        }
+
+        let today = now.date_naive();
+        let Some(AddTicketStep::Date { train_number }) =
+            self.0.get(&user).map(|step| step.value().clone())
+        else {
+            return Err("Date is already chosen, see /add to start again.".to_owned());
+        };
+
+        if let Some(month) = data.strip_prefix("month:") {
+            let month = NaiveDate::parse_from_str(&format!("{month}-01"), "%Y-%m-%d")
+                .map_err(|_| "Wrong month.".to_owned())?;
+            return Ok(Step::Ask(ask_date(month, today)));
+        }
+        if let Some(date) = data.strip_prefix("date:") {
+            let date = NaiveDate::parse_from_str(date, "%Y-%m-%d")
+                .map_err(|_| "Wrong date.".to_owned())?;
+            return self.choose_date(user, train_number, date, today);
+        }
+
+        Err(UNKNOWN_BUTTON_MESSAGE.to_owned())
+    }
+
+    fn choose_station(&self, user: ChatId, index: &str) -> Result<Step, String> {
+        let Some(AddTicketStep::ConfirmStation {
+            mut ticket,
+            pending,
+            suggestions,
+        }) = self.0.get(&user).map(|step| step.value().clone())
+        else {
+            return Err("Station is already chosen, see /add to start again.".to_owned());
+        };
+
+        let station = index
+            .parse::<usize>()
+            .ok()
+            .and_then(|index| suggestions.get(index))
+            .and_then(|name| stations::find(name))
+            .ok_or_else(|| UNKNOWN_BUTTON_MESSAGE.to_owned())?;
+        fill_next_station(&mut ticket, station);
+        self.fill_stations(user, ticket, pending)
+    }
+
+    fn choose_date(
+        &self,
+        user: ChatId,
+        train_number: TrainNumber,
+        date: NaiveDate,
+        today: NaiveDate,
+    ) -> Result<Step, String> {
+        if date < today {
+            return Err("Departure date can't be in the past.".to_owned());
+        }
+        self.0
+            .insert(user, AddTicketStep::Time { train_number, date });
+
+        Ok(Step::Ask(
+            format!(
+                "Departure date is {}. Send departure time, e.g. 20:36.",
+                date.format("%d.%m.%Y")
+            )
+            .into(),
+        ))
+    }
+}
+
+/// Up to 4 digits with optional letter suffix, as printed on tickets, e.g. `043К`.
+fn parse_train_number(text: &str) -> Option<TrainNumber> {
+    let train_number = text.parse::<TrainNumber>().ok()?;
+    let suffix_len = train_number.suffix().map(|suffix| suffix.chars().count());
+
+    (train_number.number() < 10_000 && suffix_len.unwrap_or(0) <= 2).then_some(train_number)
+}
+
+/// Two station names separated by a dash or an arrow.
+fn parse_stations(text: &str) -> Result<(&str, &str), String> {
+    ["→", "->", "—", "–", " - ", ","]
+        .iter()
+        .find_map(|separator| text.split_once(separator))
+        .or_else(|| stations::split_route(text))
+        .ok_or_else(|| "Separate stations with a dash, e.g. Київ - Львів.".to_owned())
+}
+
+/// Departure station first, then arrival.
+fn fill_next_station(ticket: &mut TicketData, station: &Station) {
+    let station = Some(TicketStation {
+        code: None,
+        name: station.name.clone(),
+    });
+    match ticket.departure_station {
+        None => ticket.departure_station = station,
+        Some(_) => ticket.arrival_station = station,
+    }
+}
+
+fn parse_time(text: &str) -> Option<NaiveTime> {
+    NaiveTime::parse_from_str(&text.replace('.', ":"), "%H:%M").ok()
+}
+
+/// Time without date is on the departure day, or on the next one, if it is not after departure.
+fn parse_arrival(text: &str, departure_datetime: DateTime<Tz>) -> Option<DateTime<Tz>> {
+    let naive = match chrono::NaiveDateTime::parse_from_str(text, "%d.%m.%Y %H:%M") {
+        Ok(naive) => naive,
+        Err(_) => {
+            let time = parse_time(text)?;
+            let departure_date = departure_datetime.date_naive();
+            if time > departure_datetime.time() {
+                departure_date.and_time(time)
+            } else {
+                departure_date
+                    .checked_add_days(Days::new(1))?
+                    .and_time(time)
+            }
+        }
+    };
+
+    let arrival_datetime = Kyiv.from_local_datetime(&naive).single()?;
+    (arrival_datetime > departure_datetime).then_some(arrival_datetime)
+}
+
+fn ask_date(month: NaiveDate, today: NaiveDate) -> Reply {
+    Reply {
+        text: "Pick departure date or send it as dd.mm.yyyy.".to_owned(),
+        keyboard: Some(date_picker(month, today)),
+    }
+}
+
+/// Calendar of the month containing `month`, days before `today` can't be picked.
+fn date_picker(month: NaiveDate, today: NaiveDate) -> Keyboard {
+    let first_day = month.with_day(1).expect("first day exists");
+    // buttons without action redraw the same month, which leaves the message unchanged
+    let noop = |text: &str| Button {
+        text: text.to_owned(),
+        data: format!("{ADD_CALLBACK_PREFIX}month:{}", first_day.format("%Y-%m")),
+    };
+    let next_month = first_day + Months::new(1);
+    let previous_month = first_day - Months::new(1);
+
+    let mut rows = vec![vec![noop(&first_day.format("%B %Y").to_string())]];
+    rows.push(
+        ["Mo", "Tu", "We", "Th", "Fr", "Sa", "Su"]
+            .into_iter()
+            .map(noop)
+            .collect(),
+    );
+
+    let mut week = vec![];
+    for _ in 0..first_day.weekday().num_days_from_monday() {
+        week.push(noop(" "));
+    }
+    for day in first_day.iter_days().take_while(|day| *day < next_month) {
+        if day < today {
+            week.push(noop(" "));
+        } else {
+            week.push(Button {
+                text: day.day().to_string(),
+                data: format!("{ADD_CALLBACK_PREFIX}date:{}", day.format("%Y-%m-%d")),
+            });
+        }
+        if week.len() == 7 {
+            rows.push(std::mem::take(&mut week));
+        }
+    }
+    if !week.is_empty() {
+        while week.len() < 7 {
+            week.push(noop(" "));
+        }
+        rows.push(week);
+    }
+
+    let month_button = |text: &str, month: NaiveDate| Button {
+        text: text.to_owned(),
+        data: format!("{ADD_CALLBACK_PREFIX}month:{}", month.format("%Y-%m")),
+    };
+    let previous = if previous_month.with_day(1) >= today.with_day(1) {
+        month_button("<", previous_month)
+    } else {
+        noop(" ")
+    };
+    rows.push(vec![previous, month_button(">", next_month)]);
+
+    Keyboard(rows)
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{Datelike, Days, TimeZone};
+    use chrono_tz::Europe::Kyiv;
+    use pdf_parser::TicketStation;
+    use telegram::ChatId;
+
+    use super::{date_picker, parse_train_number, Conversations, Step};
+
+    #[test]
+    fn test_add_ticket_conversation() {
+        let conversations = Conversations::new();
+        let user = ChatId(144441960);
+        let now = Kyiv.with_ymd_and_hms(2024, 4, 9, 12, 0, 0).unwrap();
+        let tomorrow = now.date_naive().checked_add_days(Days::new(1)).unwrap();
+
+        assert!(conversations.answer(user, "743", now).is_none());
+
+        conversations.start(user);
+        assert!(conversations.answer(user, "train", now).unwrap().is_err());
+        assert!(matches!(
+            conversations.answer(user, "043к", now),
+            Some(Ok(Step::Ask(_)))
+        ));
+        assert!(matches!(
+            conversations.callback(user, &format!("date:{}", tomorrow.format("%Y-%m-%d")), now),
+            Ok(Step::Ask(_))
+        ));
+        assert!(conversations.answer(user, "25:00", now).unwrap().is_err());
+        assert!(matches!(
+            conversations.answer(user, "20:36", now),
+            Some(Ok(Step::Ask(_)))
+        ));
+
+        assert!(matches!(
+            conversations.answer(user, "07:05", now),
+            Some(Ok(Step::Ask(_)))
+        ));
+        assert!(conversations
+            .answer(user, "Атлантида - Львів", now)
+            .unwrap()
+            .is_err());
+        let Some(Ok(Step::Done(ticket))) =
+            conversations.answer(user, "Kyiv - Ivano-Frankivsk", now)
+        else {
+            panic!("ticket must be complete");
+        };
+        assert_eq!(ticket.train_number.to_string(), "043К");
+        assert_eq!(
+            ticket.departure_station.map(|station| station.name),
+            Some("Київ-Пасажирський".to_owned())
+        );
+        assert_eq!(
+            ticket.arrival_station,
+            Some(TicketStation {
+                code: None,
+                name: "Івано-Франківськ".to_owned()
+            })
+        );
+        assert_eq!(
+            ticket.departure_datetime,
+            Kyiv.from_local_datetime(&tomorrow.and_hms_opt(20, 36, 0).unwrap())
+                .unwrap()
+        );
+        assert_eq!(
+            ticket.arrival_datetime.map(|arrival| arrival.day()),
+            tomorrow.checked_add_days(Days::new(1)).map(|day| day.day()),
+            "arrival earlier than departure time is on the next day"
+        );
+        assert!(conversations.answer(user, "743", now).is_none());
+    }
+
+    #[test]
+    fn test_station_suggestions() {
+        let conversations = Conversations::new();
+        let user = ChatId(144441960);
+        let now = Kyiv.with_ymd_and_hms(2024, 4, 9, 12, 0, 0).unwrap();
+        conversations.start(user);
+        for answer in ["743", "10.04.2024", "20:36"] {
+            assert!(matches!(
+                conversations.answer(user, answer, now),
+                Some(Ok(Step::Ask(_)))
+            ));
+        }
+        conversations.skip(user);
+
+        // misspelled names are not taken for the closest station silently
+        let Some(Ok(Step::Ask(reply))) = conversations.answer(user, "Kyiv - Frankivsk", now) else {
+            panic!("station must be confirmed");
+        };
+        let keyboard = reply.keyboard.unwrap();
+        assert_eq!(keyboard.0[0][0].text, "Івано-Франківськ");
+        assert!(conversations.callback(user, "station:9", now).is_err());
+
+        let Ok(Step::Done(ticket)) = conversations.callback(user, "station:0", now) else {
+            panic!("ticket must be complete");
+        };
+        assert_eq!(
+            ticket.departure_station.map(|station| station.name),
+            Some("Київ-Пасажирський".to_owned())
+        );
+        assert_eq!(
+            ticket.arrival_station,
+            Some(TicketStation {
+                code: None,
+                name: "Івано-Франківськ".to_owned()
+            })
+        );
+        assert!(conversations.callback(user, "station:0", now).is_err());
+    }
+
+    #[test]
+    fn test_train_number_validation() {
+        for valid in ["743", "043К", "6401", "92КБ"] {
+            assert!(parse_train_number(valid).is_some(), "{valid}");
+        }
+        for invalid in ["", "К43", "12345", "74 3", "743КБВ"] {
+            assert!(parse_train_number(invalid).is_none(), "{invalid}");
+        }
+    }
+
+    #[test]
+    fn test_date_picker() {
+        let today = chrono::NaiveDate::from_ymd_opt(2024, 4, 9).unwrap();
+
+        let keyboard = date_picker(today, today);
+        // title, weekdays, 5 weeks of April 2024, navigation
+        assert_eq!(keyboard.0.len(), 8);
+        assert!(keyboard.0[2..7].iter().all(|week| week.len() == 7));
+
+        let days = keyboard
+            .0
+            .iter()
+            .flatten()
+            .filter(|button| button.data.contains(":date:"))
+            .collect::<Vec<_>>();
+        assert_eq!(days.len(), 22, "only 9-30 April can be picked");
+        assert_eq!(days[0].data, "add:date:2024-04-09");
+    }
+}
@@ -0,0 +1,49 @@
+use chrono::TimeDelta;
+use rclite::Arc;
+use tokio::sync::Mutex;
+use ukrzaliznytsia_parser::{DelaysSnapshot, UpstreamHealth, UzParseError, UzParserClient};
+
+/// Delayed trains shared between the scheduler and `/status`, so that requests
+/// arriving together cause a single fetch of the UZ page.
+#[derive(Clone)]
+pub struct SharedDelays(Arc<SharedDelaysInner>);
+
+struct SharedDelaysInner {
+    uz_parser: UzParserClient,
+    last: Mutex<Option<DelaysSnapshot>>,
+}
+
+impl SharedDelays {
+    pub fn new(uz_parser: UzParserClient) -> Self {
+        Self(Arc::new(SharedDelaysInner {
+            uz_parser,
+            last: Mutex::new(None),
+        }))
+    }
+
+    /// Returns delays fetched not earlier than `max_age` ago, fetching them if needed.
+    /// Callers wait for a fetch already in progress instead of starting their own.
+    pub async fn get(&self, max_age: TimeDelta) -> Result<DelaysSnapshot, UzParseError> {
+        let mut last = self.0.last.lock().await;
+        if let Some(snapshot) = last
+            .as_ref()
+            .filter(|snapshot| chrono::Utc::now() - snapshot.fetched_at < max_age)
+        {
+            return Ok(snapshot.clone());
+        }
+
+        let snapshot = self.0.uz_parser.delayed_trains().await?;
+        *last = Some(snapshot.clone());
+        Ok(snapshot)
+    }
+
+    /// Last fetched delays, however old, without fetching.
+    pub async fn last(&self) -> Option<DelaysSnapshot> {
+        self.0.last.lock().await.clone()
+    }
+
+    /// Whether the UZ page is currently failing, see [`UzParserClient::health`].
+    pub fn health(&self) -> UpstreamHealth {
+        self.0.uz_parser.health()
+    }
+}
@@ -0,0 +1,289 @@
+use std::{
+    collections::BTreeMap,
+    sync::atomic::{AtomicI64, Ordering},
+};
+
+use chrono::{DateTime, TimeDelta, Utc};
+use database::Database;
+use rclite::Arc;
+use serde::Serialize;
+use telegram::TelegramClient;
+use tokio::sync::Mutex;
+use ukrzaliznytsia_parser::UpstreamHealth;
+
+use crate::{config::HealthConfig, consts::TELEGRAM_CHECK_MAX_AGE, mydb::MyDb};
+
+/// Time the scheduler loop last started a check, shared with the health endpoints.
+#[derive(Clone)]
+pub struct Heartbeat(Arc<AtomicI64>);
+
+impl Heartbeat {
+    /// Counts as a beat, so that the bot is alive while it starts.
+    pub fn new() -> Self {
+        Self(Arc::new(AtomicI64::new(Utc::now().timestamp_millis())))
+    }
+
+    pub fn beat(&self) {
+        self.0
+            .store(Utc::now().timestamp_millis(), Ordering::Relaxed);
+    }
+
+    pub fn last(&self) -> DateTime<Utc> {
+        DateTime::from_timestamp_millis(self.0.load(Ordering::Relaxed)).unwrap_or_default()
+    }
+}
+
+/// Last answer of the Bot API, reused by probes arriving within [`TELEGRAM_CHECK_MAX_AGE`],
+/// so that frequent probes don't call `getMe` each time.
+#[derive(Clone)]
+pub struct TelegramCheck {
+    tg: TelegramClient,
+    last: Arc<Mutex<Option<CheckedReport>>>,
+}
+
+type CheckedReport = (DateTime<Utc>, ComponentReport);
+
+impl TelegramCheck {
+    pub fn new(tg: TelegramClient) -> Self {
+        Self {
+            tg,
+            last: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    /// Probes arriving together wait for a check already in progress instead of starting their own.
+    async fn report(&self, config: &HealthConfig, now: DateTime<Utc>) -> ComponentReport {
+        let mut last = self.last.lock().await;
+        let (checked_at, report) = match last
+            .as_ref()
+            .filter(|(checked_at, _)| now - *checked_at < TELEGRAM_CHECK_MAX_AGE)
+        {
+            Some(cached) => cached.clone(),
+            None => {
+                let report = telegram_report(&self.tg, config).await;
+                last.insert((now, report)).clone()
+            }
+        };
+        ComponentReport {
+            detail: format!(
+                "{}, checked {}s ago",
+                report.detail,
+                (now - checked_at).num_seconds()
+            ),
+            ..report
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Status {
+    Ok,
+    Fail,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ComponentReport {
+    pub status: Status,
+    pub detail: String,
+}
+
+/// Answer of `/healthz` and `/readyz`, failed when any component failed.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Report {
+    pub status: Status,
+    pub components: BTreeMap<&'static str, ComponentReport>,
+}
+
+impl ComponentReport {
+    fn ok(detail: impl Into<String>) -> Self {
+        Self {
+            status: Status::Ok,
+            detail: detail.into(),
+        }
+    }
+
+    fn fail(detail: impl Into<String>) -> Self {
+        Self {
+            status: Status::Fail,
+            detail: detail.into(),
+        }
+    }
+}
+
+impl Report {
+    fn new(components: impl IntoIterator<Item = (&'static str, ComponentReport)>) -> Self {
+        let components = components.into_iter().collect::<BTreeMap<_, _>>();
+        let status = match components.values().all(|c| c.status == Status::Ok) {
+            true => Status::Ok,
+            false => Status::Fail,
+        };
+        Self { status, components }
+    }
+}
+
+/// Alive while the scheduler keeps ticking, a stuck loop sends no reminders.
+pub fn liveness(heartbeat: &Heartbeat, config: &HealthConfig, now: DateTime<Utc>) -> Report {
+    Report::new([(
+        "scheduler",
+        scheduler_report(heartbeat.last(), config.scheduler_max_tick_age(), now),
+    )])
+}
+
+/// Ready when tickets can be stored, the Bot API answers and delay data is fresh.
+pub async fn readiness(
+    db: &MyDb,
+    telegram: &TelegramCheck,
+    uz: &UpstreamHealth,
+    config: &HealthConfig,
+    now: DateTime<Utc>,
+) -> Report {
+    let stats = db.stats();
+    let storage = ComponentReport::ok(format!(
+        "memory, {} users, {} tickets",
+        stats.users, stats.tickets
+    ));
+
+    Report::new([
+        ("storage", storage),
+        ("telegram", telegram.report(config, now).await),
+        ("uz", uz_report(uz, config.uz_max_success_age(), now)),
+    ])
+}
+
+async fn telegram_report(tg: &TelegramClient, config: &HealthConfig) -> ComponentReport {
+    match tokio::time::timeout(config.telegram_timeout(), tg.bot_username()).await {
+        Ok(Ok(username)) => ComponentReport::ok(format!("@{username}")),
+        Ok(Err(e)) => ComponentReport::fail(e.to_string()),
+        Err(_) => ComponentReport::fail(format!(
+            "no answer within {}s",
+            config.telegram_timeout_secs
+        )),
+    }
+}
+
+fn scheduler_report(
+    last_tick: DateTime<Utc>,
+    max_age: TimeDelta,
+    now: DateTime<Utc>,
+) -> ComponentReport {
+    let age = now - last_tick;
+    let detail = format!("last tick {}s ago", age.num_seconds());
+    match age <= max_age {
+        true => ComponentReport::ok(detail),
+        false => ComponentReport::fail(detail),
+    }
+}
+
+fn uz_report(uz: &UpstreamHealth, max_age: TimeDelta, now: DateTime<Utc>) -> ComponentReport {
+    let Some(last_success) = uz.last_success else {
+        return ComponentReport::fail(match &uz.last_error {
+            Some(e) => format!("never fetched, last error: {e}"),
+            None => "never fetched".to_owned(),
+        });
+    };
+
+    let age = now - last_success;
+    let mut detail = format!(
+        "last success {}s ago, circuit {:?}",
+        age.num_seconds(),
+        uz.circuit
+    );
+    if let Some(e) = uz.last_error.as_ref().filter(|_| uz.is_degraded()) {
+        detail.push_str(&format!(", last error: {e}"));
+    }
+    match age <= max_age {
+        true => ComponentReport::ok(detail),
+        false => ComponentReport::fail(detail),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{TimeDelta, TimeZone, Utc};
+    use reqwest::header::HeaderMap;
+    use telegram::TelegramClient;
+    use ukrzaliznytsia_parser::{CircuitState, UpstreamHealth};
+
+    use super::{liveness, uz_report, Heartbeat, Status, TelegramCheck};
+    use crate::config::HealthConfig;
+
+    #[test]
+    fn test_liveness() {
+        let config = HealthConfig::default();
+        let heartbeat = Heartbeat::new();
+        heartbeat.beat();
+        let now = heartbeat.last();
+
+        let report = liveness(&heartbeat, &config, now + TimeDelta::seconds(30));
+        assert_eq!(report.status, Status::Ok);
+        assert_eq!(report.components["scheduler"].detail, "last tick 30s ago");
+
+        let report = liveness(&heartbeat, &config, now + TimeDelta::minutes(10));
+        assert_eq!(report.status, Status::Fail);
+        assert_eq!(
+            serde_json::to_value(&report).unwrap(),
+            serde_json::json!({
+                "status": "fail",
+                "components": {
+                    "scheduler": { "status": "fail", "detail": "last tick 600s ago" }
+                }
+            })
+        );
+    }
+
+    #[test]
+    fn test_uz_readiness() {
+        let now = Utc.with_ymd_and_hms(2024, 4, 9, 12, 0, 0).unwrap();
+        let max_age = TimeDelta::minutes(15);
+        let health = |last_success: Option<i64>, failures| UpstreamHealth {
+            circuit: CircuitState::Closed,
+            consecutive_failures: failures,
+            last_success: last_success.map(|minutes_ago| now - TimeDelta::minutes(minutes_ago)),
+            last_error: Some("timeout".to_owned()),
+        };
+
+        let fresh = uz_report(&health(Some(1), 0), max_age, now);
+        assert_eq!(fresh.status, Status::Ok);
+        assert_eq!(fresh.detail, "last success 60s ago, circuit Closed");
+
+        let failing = uz_report(&health(Some(5), 2), max_age, now);
+        assert_eq!(failing.status, Status::Ok, "still fresh enough");
+        assert!(failing.detail.ends_with("last error: timeout"));
+
+        assert_eq!(
+            uz_report(&health(Some(20), 3), max_age, now).status,
+            Status::Fail
+        );
+        assert_eq!(
+            uz_report(&health(None, 1), max_age, now).detail,
+            "never fetched, last error: timeout"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_telegram_check_is_reused() {
+        let config = HealthConfig::default();
+        // nothing listens there, so every check fails
+        let check = TelegramCheck::new(TelegramClient::new(
+            "1:key",
+            "http://127.0.0.1:9".parse().unwrap(),
+            HeaderMap::new(),
+        ));
+        let now = Utc.with_ymd_and_hms(2024, 4, 9, 12, 0, 0).unwrap();
+
+        let first = check.report(&config, now).await;
+        assert_eq!(first.status, Status::Fail);
+        assert!(first.detail.ends_with("checked 0s ago"), "{}", first.detail);
+
+        let cached = check.report(&config, now + TimeDelta::seconds(10)).await;
+        assert!(
+            cached.detail.ends_with("checked 10s ago"),
+            "{}",
+            cached.detail
+        );
+
+        let fresh = check.report(&config, now + TimeDelta::minutes(1)).await;
+        assert!(fresh.detail.ends_with("checked 0s ago"), "{}", fresh.detail);
+    }
+}
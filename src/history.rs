@@ -0,0 +1,194 @@
+use std::collections::BTreeMap;
+
+use chrono::{DateTime, Datelike, NaiveDate, Weekday};
+use chrono_tz::Tz;
+use train_number::TrainNumber;
+use ukrzaliznytsia_parser::DelayedTrain;
+
+/// Delay of a train seen on the UZ page. Only the largest delay of a run is kept in the history.
+/// Trains are listed only when late, so the history has no days without delay.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct DelayObservation {
+    pub train_numbers: Vec<TrainNumber>,
+    pub direction: String,
+    pub delay_minutes: usize,
+    /// Kyiv date the run departed on, if a ticket on it is known, otherwise the observation date.
+    pub run_date: NaiveDate,
+    pub observed_at: DateTime<Tz>,
+}
+
+impl DelayObservation {
+    pub fn new(
+        delayed_train: &DelayedTrain,
+        run_date: NaiveDate,
+        observed_at: DateTime<Tz>,
+    ) -> Self {
+        Self {
+            train_numbers: delayed_train.numbers.0.clone(),
+            direction: delayed_train.direction.to_string(),
+            delay_minutes: delayed_train.delay.total_minutes(),
+            run_date,
+            observed_at,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct DelayStats {
+    pub samples: usize,
+    pub average: f64,
+    pub median: usize,
+    pub p90: usize,
+}
+
+impl DelayStats {
+    /// `None` for no delays.
+    pub fn new(mut delays: Vec<usize>) -> Option<Self> {
+        if delays.is_empty() {
+            return None;
+        }
+        delays.sort_unstable();
+
+        // nearest-rank percentile
+        let percentile = |p: f64| delays[((p * delays.len() as f64).ceil() as usize).max(1) - 1];
+        Some(Self {
+            samples: delays.len(),
+            average: delays.iter().sum::<usize>() as f64 / delays.len() as f64,
+            median: percentile(0.5),
+            p90: percentile(0.9),
+        })
+    }
+}
+
+/// Delays of one train number on days it was late, grouped by the weekday of the run and by route.
+#[derive(Debug)]
+pub struct TrainStats {
+    pub overall: Option<DelayStats>,
+    /// Ordered from Monday, days without observations are skipped.
+    pub by_weekday: Vec<(Weekday, DelayStats)>,
+    pub by_route: BTreeMap<String, DelayStats>,
+}
+
+impl TrainStats {
+    /// Uses observations made not earlier than `since`.
+    pub fn new(observations: impl Iterator<Item = DelayObservation>, since: DateTime<Tz>) -> Self {
+        let observations = observations
+            .filter(|observation| observation.observed_at >= since)
+            .collect::<Vec<_>>();
+
+        let mut weekdays: BTreeMap<u32, Vec<usize>> = BTreeMap::new();
+        let mut routes: BTreeMap<String, Vec<usize>> = BTreeMap::new();
+        for observation in observations.iter() {
+            weekdays
+                .entry(observation.run_date.weekday().num_days_from_monday())
+                .or_default()
+                .push(observation.delay_minutes);
+            routes
+                .entry(observation.direction.clone())
+                .or_default()
+                .push(observation.delay_minutes);
+        }
+
+        Self {
+            overall: DelayStats::new(
+                observations
+                    .iter()
+                    .map(|observation| observation.delay_minutes)
+                    .collect(),
+            ),
+            by_weekday: weekdays
+                .into_iter()
+                .filter_map(|(day, delays)| {
+                    let weekday = Weekday::try_from(day as u8).ok()?;
+                    Some((weekday, DelayStats::new(delays)?))
+                })
+                .collect(),
+            by_route: routes
+                .into_iter()
+                .filter_map(|(route, delays)| Some((route, DelayStats::new(delays)?)))
+                .collect(),
+        }
+    }
+
+    pub fn weekday(&self, weekday: Weekday) -> Option<&DelayStats> {
+        self.by_weekday
+            .iter()
+            .find(|(day, _)| *day == weekday)
+            .map(|(_, stats)| stats)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{NaiveDate, TimeZone, Weekday};
+    use chrono_tz::Europe::Kyiv;
+
+    use train_number::TrainNumber;
+
+    use super::{DelayObservation, DelayStats, TrainStats};
+
+    fn observation(day: u32, direction: &str, delay_minutes: usize) -> DelayObservation {
+        DelayObservation {
+            train_numbers: TrainNumber::parse_all("743/744").unwrap(),
+            direction: direction.to_owned(),
+            delay_minutes,
+            run_date: NaiveDate::from_ymd_opt(2024, 4, day).unwrap(),
+            observed_at: Kyiv.with_ymd_and_hms(2024, 4, day, 12, 0, 0).unwrap(),
+        }
+    }
+
+    #[test]
+    fn test_delay_stats() {
+        assert_eq!(DelayStats::new(vec![]), None);
+
+        let stats = DelayStats::new(vec![50, 10, 20, 30, 40, 60, 70, 80, 90, 100]).unwrap();
+        assert_eq!(stats.samples, 10);
+        assert_eq!(stats.average, 55.0);
+        assert_eq!(stats.median, 50);
+        assert_eq!(stats.p90, 90);
+
+        let single = DelayStats::new(vec![7]).unwrap();
+        assert_eq!((single.median, single.p90), (7, 7));
+    }
+
+    #[test]
+    fn test_train_stats() {
+        // 2024-04-05 and 2024-04-12 are Fridays, 2024-04-08 is Monday
+        let observations = vec![
+            observation(1, "Київ-Пас.-Харків-Пас.", 100),
+            observation(5, "Київ-Пас.-Харків-Пас.", 20),
+            observation(8, "Харків-Пас.-Київ-Пас.", 10),
+            observation(12, "Київ-Пас.-Харків-Пас.", 30),
+        ];
+        let since = Kyiv.with_ymd_and_hms(2024, 4, 2, 0, 0, 0).unwrap();
+
+        let stats = TrainStats::new(observations.into_iter(), since);
+        assert_eq!(stats.overall.as_ref().unwrap().samples, 3);
+        assert_eq!(
+            stats
+                .by_weekday
+                .iter()
+                .map(|(day, _)| *day)
+                .collect::<Vec<_>>(),
+            vec![Weekday::Mon, Weekday::Fri]
+        );
+        assert_eq!(stats.by_weekday[1].1.average, 25.0);
+        assert_eq!(stats.weekday(Weekday::Tue), None);
+        assert_eq!(stats.by_route["Київ-Пас.-Харків-Пас."].samples, 2);
+        assert_eq!(stats.by_route["Харків-Пас.-Київ-Пас."].median, 10);
+    }
+
+    #[test]
+    fn test_weekday_of_overnight_run() {
+        // departed on Friday 2024-04-12, seen late after midnight
+        let overnight = DelayObservation {
+            observed_at: Kyiv.with_ymd_and_hms(2024, 4, 13, 1, 30, 0).unwrap(),
+            ..observation(12, "Київ-Пас.-Харків-Пас.", 40)
+        };
+        let since = Kyiv.with_ymd_and_hms(2024, 4, 2, 0, 0, 0).unwrap();
+
+        let stats = TrainStats::new([overnight].into_iter(), since);
+        assert_eq!(stats.weekday(Weekday::Fri).unwrap().samples, 1);
+        assert_eq!(stats.weekday(Weekday::Sat), None);
+    }
+}
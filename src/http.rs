@@ -0,0 +1,150 @@
+use std::{convert::Infallible, net::SocketAddr};
+
+use hyper::{
+    header::CONTENT_TYPE,
+    service::{make_service_fn, service_fn},
+    Body, Method, Request, Response, Server, StatusCode,
+};
+use metrics_exporter_prometheus::PrometheusHandle;
+use serde::Serialize;
+
+use crate::{
+    config::HealthConfig,
+    delays::SharedDelays,
+    health::{self, Heartbeat, Report, Status, TelegramCheck},
+    mydb::MyDb,
+};
+
+/// Everything the endpoints report on.
+#[derive(Clone)]
+pub struct HttpState {
+    pub metrics: PrometheusHandle,
+    pub heartbeat: Heartbeat,
+    pub health: HealthConfig,
+    pub db: MyDb,
+    pub telegram: TelegramCheck,
+    pub delays: SharedDelays,
+}
+
+/// Serves `/metrics`, `/healthz` and `/readyz` until the server fails.
+pub async fn serve(listen: SocketAddr, state: HttpState) -> Result<(), hyper::Error> {
+    let make_service = make_service_fn(move |_| {
+        let state = state.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |request| {
+                let state = state.clone();
+                async move { Ok::<_, Infallible>(route(&state, &request).await) }
+            }))
+        }
+    });
+
+    Server::try_bind(&listen)?.serve(make_service).await
+}
+
+async fn route(state: &HttpState, request: &Request<Body>) -> Response<Body> {
+    let now = chrono::Utc::now();
+    match (request.method(), request.uri().path()) {
+        (&Method::GET, "/metrics") => response(
+            StatusCode::OK,
+            "text/plain; version=0.0.4",
+            state.metrics.render(),
+        ),
+        (&Method::GET, "/healthz") => {
+            report_response(health::liveness(&state.heartbeat, &state.health, now))
+        }
+        (&Method::GET, "/readyz") => {
+            let uz = state.delays.health();
+            let report =
+                health::readiness(&state.db, &state.telegram, &uz, &state.health, now).await;
+            report_response(report)
+        }
+        _ => response(StatusCode::NOT_FOUND, "text/plain", "Not found".to_owned()),
+    }
+}
+
+/// Failed report is answered with 503, so that orchestrators act on the status code alone.
+fn report_response(report: Report) -> Response<Body> {
+    let status = match report.status {
+        Status::Ok => StatusCode::OK,
+        Status::Fail => StatusCode::SERVICE_UNAVAILABLE,
+    };
+    response(status, "application/json", to_json(&report))
+}
+
+fn to_json(value: &impl Serialize) -> String {
+    serde_json::to_string(value).expect("reports serialize to json")
+}
+
+fn response(status: StatusCode, content_type: &'static str, body: String) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .header(CONTENT_TYPE, content_type)
+        .body(Body::from(body))
+        .expect("static response parts are valid")
+}
+
+#[cfg(test)]
+mod tests {
+    use hyper::{Body, Request, StatusCode};
+    use metrics_exporter_prometheus::PrometheusBuilder;
+    use reqwest::header::HeaderMap;
+    use telegram::TelegramClient;
+    use ukrzaliznytsia_parser::UzParserClient;
+
+    use super::{route, HttpState};
+    use crate::{
+        config::HealthConfig,
+        delays::SharedDelays,
+        health::{Heartbeat, TelegramCheck},
+        mydb::MyDb,
+    };
+
+    async fn get(state: &HttpState, path: &str) -> (StatusCode, String) {
+        let request = Request::get(path).body(Body::empty()).unwrap();
+        let response = route(state, &request).await;
+        let status = response.status();
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        (status, String::from_utf8(body.to_vec()).unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_endpoints() {
+        let recorder = PrometheusBuilder::new().build_recorder();
+        let state = HttpState {
+            metrics: recorder.handle(),
+            heartbeat: Heartbeat::new(),
+            health: HealthConfig::default(),
+            db: MyDb::new(),
+            // nothing listens there, so telegram is not ready
+            telegram: TelegramCheck::new(TelegramClient::new(
+                "1:key",
+                "http://127.0.0.1:9".parse().unwrap(),
+                HeaderMap::new(),
+            )),
+            delays: SharedDelays::new(UzParserClient::new()),
+        };
+        metrics::with_local_recorder(&recorder, || {
+            metrics::counter!("uz_bot_tickets_added_total").increment(2);
+        });
+
+        let (status, body) = get(&state, "/metrics").await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(body.contains("uz_bot_tickets_added_total 2"), "{body}");
+
+        let (status, body) = get(&state, "/healthz").await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(
+            body.starts_with(r#"{"status":"ok","components":{"scheduler""#),
+            "{body}"
+        );
+
+        let (status, body) = get(&state, "/readyz").await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        let report: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(report["components"]["storage"]["status"], "ok");
+        assert_eq!(report["components"]["telegram"]["status"], "fail");
+        assert_eq!(report["components"]["uz"]["detail"], "never fetched");
+
+        assert_eq!(get(&state, "/other").await.0, StatusCode::NOT_FOUND);
+    }
+}
@@ -0,0 +1,157 @@
+use chrono::{DateTime, TimeDelta};
+use chrono_tz::Tz;
+use pdf_parser::TicketData;
+use train_number::TrainNumber;
+
+/// Several tickets travelled one after another, with transfers between them.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Journey {
+    legs: Vec<TicketData>,
+}
+
+/// Change from `arriving` train to `departing` one.
+#[derive(Debug)]
+pub struct Transfer<'a> {
+    pub arriving: &'a TicketData,
+    pub departing: &'a TicketData,
+    /// Time between scheduled arrival of `arriving` and departure of `departing`.
+    pub slack: TimeDelta,
+}
+
+impl Journey {
+    /// Orders legs by departure and checks that every transfer is possible on schedule.
+    pub fn new(mut legs: Vec<TicketData>) -> Result<Self, JourneyError> {
+        if legs.len() < 2 {
+            return Err(JourneyError::NotEnoughLegs);
+        }
+        legs.sort_by_key(|leg| leg.departure_datetime);
+
+        for pair in legs.windows(2) {
+            let [arriving, departing] = pair else {
+                unreachable!("windows of 2")
+            };
+            let arrival = arriving
+                .arrival_datetime
+                .ok_or_else(|| JourneyError::ArrivalAbsent(arriving.train_number.clone()))?;
+            if arrival > departing.departure_datetime {
+                return Err(JourneyError::Overlapping(
+                    arriving.train_number.clone(),
+                    departing.train_number.clone(),
+                ));
+            }
+        }
+
+        Ok(Self { legs })
+    }
+
+    pub fn legs(&self) -> &[TicketData] {
+        &self.legs
+    }
+
+    pub fn contains(&self, ticket: &TicketData) -> bool {
+        self.legs.contains(ticket)
+    }
+
+    pub fn transfers(&self) -> impl Iterator<Item = Transfer<'_>> {
+        self.legs.windows(2).filter_map(|pair| {
+            let [arriving, departing] = pair else {
+                return None;
+            };
+            let slack = departing.departure_datetime - arriving.arrival_datetime?;
+            Some(Transfer {
+                arriving,
+                departing,
+                slack,
+            })
+        })
+    }
+
+    /// Journey is over once its last train has departed.
+    pub fn is_finished(&self, now: DateTime<Tz>) -> bool {
+        self.legs
+            .last()
+            .map(|leg| leg.departure_datetime < now)
+            .unwrap_or(true)
+    }
+}
+
+impl Transfer<'_> {
+    /// Time left for the transfer, if the arriving train is late by `delay`. Negative means missed connection.
+    pub fn remaining_buffer(&self, delay: TimeDelta) -> TimeDelta {
+        self.slack - delay
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum JourneyError {
+    #[error("Journey must contain at least two tickets")]
+    NotEnoughLegs,
+    #[error("Arrival time of train №{0} is unknown")]
+    ArrivalAbsent(TrainNumber),
+    #[error("Train №{0} arrives after train №{1} departs")]
+    Overlapping(TrainNumber, TrainNumber),
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{TimeDelta, TimeZone};
+    use chrono_tz::Europe::Kyiv;
+    use pdf_parser::TicketData;
+
+    use super::{Journey, JourneyError};
+    use crate::test_util;
+
+    fn ticket(
+        train_number: &str,
+        departure: (u32, u32),
+        arrival: Option<(u32, u32)>,
+    ) -> TicketData {
+        TicketData {
+            arrival_datetime: arrival
+                .map(|(hr, min)| Kyiv.with_ymd_and_hms(2024, 4, 9, hr, min, 0).unwrap()),
+            ..test_util::ticket(
+                train_number,
+                Kyiv.with_ymd_and_hms(2024, 4, 9, departure.0, departure.1, 0)
+                    .unwrap(),
+            )
+        }
+    }
+
+    #[test]
+    fn test_journey_transfers() {
+        let intercity = ticket("743", (6, 0), Some((11, 10)));
+        let regional = ticket("6401", (11, 45), Some((13, 0)));
+
+        let journey = Journey::new(vec![regional.clone(), intercity.clone()]).unwrap();
+        assert_eq!(journey.legs(), [intercity.clone(), regional.clone()]);
+
+        let transfers = journey.transfers().collect::<Vec<_>>();
+        assert_eq!(transfers.len(), 1);
+        assert_eq!(transfers[0].arriving, &intercity);
+        assert_eq!(transfers[0].slack, TimeDelta::minutes(35));
+        assert_eq!(
+            transfers[0].remaining_buffer(TimeDelta::minutes(50)),
+            TimeDelta::minutes(-15)
+        );
+    }
+
+    #[test]
+    fn test_journey_validation() {
+        let no_arrival = ticket("743", (6, 0), None);
+        let overlapping = ticket("743", (6, 0), Some((12, 0)));
+        let regional = ticket("6401", (11, 45), Some((13, 0)));
+
+        assert!(matches!(
+            Journey::new(vec![regional.clone()]),
+            Err(JourneyError::NotEnoughLegs)
+        ));
+        assert!(matches!(
+            Journey::new(vec![no_arrival, regional.clone()]),
+            Err(JourneyError::ArrivalAbsent(_))
+        ));
+        assert!(matches!(
+            Journey::new(vec![overlapping, regional]),
+            Err(JourneyError::Overlapping(_, _))
+        ));
+    }
+}
@@ -0,0 +1,426 @@
+use std::collections::HashMap;
+
+use chrono::{DateTime, TimeDelta};
+use chrono_tz::Tz;
+use database::Database;
+use pdf_parser::TicketData;
+use telegram::{ChatId, TelegramClient};
+use tracing::warn;
+use ukrzaliznytsia_parser::DelayedTrains;
+
+use crate::{
+    consts::SAME_RUN_MAX_TRAVEL,
+    local_time::{chat_timezone, format_datetime, format_time},
+    matching::{confidence_note, match_delayed_train},
+    mydb::MyDb,
+    notifier::Notifier,
+    settings::LiveStatus,
+    tg::build_train_notification_message,
+};
+
+/// Status messages of chats in live mode. Instead of a reminder for every window such a chat
+/// gets one message per ticket at the first window, edited as the departure nears. New
+/// messages are sent only when the delay changes by at least `significant_change` minutes.
+pub struct LiveStatuses {
+    db: MyDb,
+    tg: TelegramClient,
+    /// Delay in minutes, the chat was last sent a new message about.
+    notified_delays: HashMap<(ChatId, TicketData), usize>,
+    /// Text the status message was last edited to, unchanged text is not edited again.
+    texts: HashMap<(ChatId, TicketData), String>,
+    significant_change: usize,
+}
+
+pub fn is_live(db: &MyDb, chat: ChatId) -> bool {
+    db.user_settings(chat).live_status != LiveStatus::Off
+}
+
+impl LiveStatuses {
+    pub fn new(
+        db: MyDb,
+        tg: TelegramClient,
+        notified_delays: HashMap<(ChatId, TicketData), usize>,
+        significant_change: usize,
+    ) -> Self {
+        Self {
+            db,
+            tg,
+            notified_delays,
+            texts: HashMap::new(),
+            significant_change,
+        }
+    }
+
+    /// Kept over restarts in the scheduler state.
+    pub fn into_notified_delays(self) -> HashMap<(ChatId, TicketData), usize> {
+        self.notified_delays
+    }
+
+    /// Sends the status message of owner's ticket to `chat`, in place of the first reminder.
+    pub async fn start(
+        &mut self,
+        notifier: &Notifier,
+        owner: ChatId,
+        ticket: &TicketData,
+        chat: ChatId,
+        delays: Option<&DelayedTrains>,
+        now: DateTime<Tz>,
+    ) {
+        let text = build_live_status_message(ticket, delays, now, chat_timezone(&self.db, chat));
+        // a status message is not worth waking up for, whatever the quiet mode
+        let sent = match notifier.quiet_hours(chat, now) {
+            Some(_) => self.tg.send_silently(chat, text.clone()).await,
+            None => self.tg.send_editable(chat, text.clone()).await,
+        };
+        let message_id = match sent {
+            Ok(message_id) => {
+                metrics::counter!("uz_bot_reminders_sent_total", "kind" => "live_status")
+                    .increment(1);
+                message_id
+            }
+            Err(e) => {
+                metrics::counter!("uz_bot_reminders_failed_total", "kind" => "live_status")
+                    .increment(1);
+                warn!(%e, "Error sending status message to user telegram");
+                return;
+            }
+        };
+        if self.db.user_settings(chat).live_status == LiveStatus::Pinned {
+            if let Err(e) = self.tg.pin_message(chat, message_id).await {
+                warn!(%e, "Error pinning status message telegram");
+            }
+        }
+        if let Err(e) = self
+            .db
+            .set_status_message(owner, ticket.clone(), chat, message_id)
+        {
+            warn!(%e, "storing status message");
+        }
+
+        let key = (chat, ticket.clone());
+        self.texts.insert(key.clone(), text);
+        if let Some(delays) = delays {
+            self.notified_delays
+                .insert(key, delay_minutes(delays, ticket, now));
+        }
+    }
+
+    /// Reminder window of a chat in live mode. The first one sends the status message, later
+    /// ones a short reminder, as edits of the status message come without a notification.
+    pub async fn remind(
+        &mut self,
+        notifier: &mut Notifier,
+        owner: ChatId,
+        ticket: &TicketData,
+        chat: ChatId,
+        delays: Option<&DelayedTrains>,
+        now: DateTime<Tz>,
+    ) {
+        if self.db.status_message(owner, ticket, chat).is_none() {
+            self.start(notifier, owner, ticket, chat, delays, now).await;
+            return;
+        }
+
+        let zone = chat_timezone(&self.db, chat);
+        let message = build_live_reminder_message(ticket, delays, now, zone);
+        let urgency = notifier.delay_urgency(
+            delays
+                .map(|delays| delay_minutes(delays, ticket, now))
+                .unwrap_or(0),
+        );
+        if let Err(e) = notifier
+            .send_until(
+                "live_reminder",
+                chat,
+                message,
+                urgency,
+                ticket.departure_datetime,
+                now,
+            )
+            .await
+        {
+            warn!(%e, "Error sending live reminder to user telegram");
+        }
+    }
+
+    /// Edits status messages of owner's ticket in every chat, which has one and is still live.
+    pub async fn update(
+        &mut self,
+        notifier: &mut Notifier,
+        owner: ChatId,
+        ticket: &TicketData,
+        delays: Option<&DelayedTrains>,
+        now: DateTime<Tz>,
+    ) {
+        let chats = std::iter::once(owner)
+            .chain(self.db.subscribers(owner, ticket))
+            .collect::<Vec<_>>();
+        for chat in chats {
+            let Some(message_id) = self.db.status_message(owner, ticket, chat) else {
+                continue;
+            };
+            if !is_live(&self.db, chat) {
+                continue;
+            }
+
+            let key = (chat, ticket.clone());
+            let zone = chat_timezone(&self.db, chat);
+            let text = build_live_status_message(ticket, delays, now, zone);
+            if self.texts.get(&key) != Some(&text) {
+                match self.tg.edit_message(chat, message_id, text.clone()).await {
+                    Ok(()) => {
+                        self.texts.insert(key.clone(), text);
+                    }
+                    Err(e) => warn!(%e, "Error editing status message telegram"),
+                }
+            }
+
+            let Some(delays) = delays.filter(|_| ticket.departure_datetime > now) else {
+                continue;
+            };
+            let delay = delay_minutes(delays, ticket, now);
+            let notified = self.notified_delays.get(&key).copied().unwrap_or(0);
+            if delay.abs_diff(notified) < self.significant_change {
+                continue;
+            }
+            self.notified_delays.insert(key, delay);
+
+            let delay_match = match_delayed_train(&delays.0, ticket, now);
+            let message = build_train_notification_message(ticket.clone(), delay_match, zone);
+            let urgency = notifier.delay_urgency(delay);
+            if let Err(e) = notifier
+                .send_until(
+                    "live_change",
+                    chat,
+                    message,
+                    urgency,
+                    ticket.departure_datetime,
+                    now,
+                )
+                .await
+            {
+                warn!(%e, "Error sending delay change to user telegram");
+            }
+        }
+    }
+
+    /// Forgets tickets, which can no longer be listed on the delays page.
+    pub fn prune(&mut self, now: DateTime<Tz>) {
+        self.notified_delays
+            .retain(|(_, ticket), _| ticket.departure_datetime > now);
+        self.texts
+            .retain(|(_, ticket), _| ticket.departure_datetime + SAME_RUN_MAX_TRAVEL > now);
+    }
+}
+
+fn delay_minutes(delays: &DelayedTrains, ticket: &TicketData, now: DateTime<Tz>) -> usize {
+    match_delayed_train(&delays.0, ticket, now)
+        .map(|delay_match| delay_match.delayed_train.delay.total_minutes())
+        .unwrap_or(0)
+}
+
+/// Countdown, current delay and time of the check. Once departed, the text no longer
+/// changes with time, so the message is not edited every minute after departure.
+fn build_live_status_message(
+    ticket: &TicketData,
+    delays: Option<&DelayedTrains>,
+    now: DateTime<Tz>,
+    zone: Tz,
+) -> String {
+    let mut lines = vec![format!(
+        "Train №{train_number}, departing {departure}",
+        train_number = ticket.train_number,
+        departure = format_datetime(&ticket.departure_datetime, zone)
+    )];
+    if let (Some(departure), Some(arrival)) = (&ticket.departure_station, &ticket.arrival_station) {
+        lines.push(format!("{} → {}", departure.name, arrival.name));
+    }
+
+    let delay_match = delays.and_then(|delays| match_delayed_train(&delays.0, ticket, now));
+    let delay = delay_match
+        .as_ref()
+        .map(|delay_match| delay_match.delayed_train.delay.total_minutes())
+        .unwrap_or(0);
+    let probable_departure = ticket.departure_datetime + TimeDelta::minutes(delay as i64);
+    let departed = probable_departure <= now;
+    lines.push(match departed {
+        true => format!(
+            "Departed at about {}.",
+            format_time(&probable_departure, zone)
+        ),
+        false => format!(
+            "Departs in {}.",
+            describe_countdown(probable_departure - now)
+        ),
+    });
+
+    lines.push(match (delays, delay_match) {
+        (None, _) => "Delay information is currently unavailable.".to_owned(),
+        (Some(_), None) => "No delays.".to_owned(),
+        (Some(_), Some(delay_match)) => {
+            let line = format!(
+                "Delayed by {delay} minutes, probable departure at {}.",
+                format_time(&probable_departure, zone)
+            );
+            match confidence_note(delay_match.confidence) {
+                Some(note) => format!("{line}\n{note}"),
+                None => line,
+            }
+        }
+    });
+    if !departed {
+        lines.push(format!(
+            "Updated at {}.",
+            now.with_timezone(&zone).format("%H:%M")
+        ));
+    }
+    lines.join("\n")
+}
+
+/// Countdown and the delay in short, the details are in the status message.
+fn build_live_reminder_message(
+    ticket: &TicketData,
+    delays: Option<&DelayedTrains>,
+    now: DateTime<Tz>,
+    zone: Tz,
+) -> String {
+    let delay = delays.map(|delays| delay_minutes(delays, ticket, now));
+    let probable_departure =
+        ticket.departure_datetime + TimeDelta::minutes(delay.unwrap_or(0) as i64);
+    let delay = match delay {
+        None => "delay information is currently unavailable".to_owned(),
+        Some(0) => "no delays".to_owned(),
+        Some(delay) => format!(
+            "delayed by {delay} minutes, probable departure at {}",
+            format_time(&probable_departure, zone)
+        ),
+    };
+    format!(
+        "Train №{train_number} departs in {countdown}, {delay}. The status message is kept up to date.",
+        train_number = ticket.train_number,
+        countdown = describe_countdown(probable_departure - now)
+    )
+}
+
+fn describe_countdown(countdown: TimeDelta) -> String {
+    match (countdown.num_hours(), countdown.num_minutes() % 60) {
+        (0, 0) => "less than a minute".to_owned(),
+        (0, minutes) => format!("{minutes} min"),
+        (hours, minutes) => format!("{hours} h {minutes} min"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use chrono::{TimeDelta, TimeZone};
+    use chrono_tz::Europe::{Kyiv, Warsaw};
+    use database::Database;
+    use pdf_parser::{TicketData, TicketStation};
+    use reqwest::header::HeaderMap;
+    use telegram::{ChatId, MessageId, TelegramClient};
+    use ukrzaliznytsia_parser::{DelayedTrains, SeverityThresholds};
+
+    use super::{build_live_status_message, LiveStatuses};
+    use crate::{
+        mydb::MyDb,
+        notifier::{HeldNotification, Notifier},
+        settings::{LiveStatus, UserSettings},
+        test_util::ticket,
+    };
+
+    #[tokio::test]
+    async fn test_live_reminder_windows() {
+        let db = MyDb::new();
+        // never reached, the reminder is held for the end of quiet hours
+        let tg = TelegramClient::new(
+            "1:token",
+            "http://127.0.0.1:9".parse().unwrap(),
+            HeaderMap::new(),
+        );
+        let mut notifier = Notifier::new(
+            db.clone(),
+            tg.clone(),
+            SeverityThresholds::default(),
+            HashMap::new(),
+        );
+        let mut live_statuses = LiveStatuses::new(db.clone(), tg, HashMap::new(), 15);
+
+        let chat = ChatId(144441960);
+        db.update_settings(
+            chat,
+            UserSettings {
+                live_status: LiveStatus::On,
+                quiet_hours: Some("23:00-07:00 collapse".parse().unwrap()),
+                ..UserSettings::default()
+            },
+        )
+        .unwrap();
+        let ticket = ticket(
+            "043К",
+            // departs after quiet hours end, so the reminder waits for their digest
+            Kyiv.with_ymd_and_hms(2024, 4, 10, 7, 30, 0).unwrap(),
+        );
+        db.insert_ticket_data(chat, ticket.clone()).unwrap();
+        db.set_status_message(chat, ticket.clone(), chat, MessageId(7))
+            .unwrap();
+
+        // the status message was sent at the first window, a later one still gets a message
+        let now = ticket.departure_datetime - TimeDelta::hours(1);
+        live_statuses
+            .remind(&mut notifier, chat, &ticket, chat, None, now)
+            .await;
+        assert_eq!(
+            notifier.into_held(),
+            HashMap::from([(
+                chat,
+                vec![HeldNotification {
+                    message: "Train №043К departs in 1 h 0 min, delay information is currently unavailable. The status message is kept up to date.".to_owned(),
+                    expires_at: Some(ticket.departure_datetime),
+                }]
+            )])
+        );
+    }
+
+    #[test]
+    fn test_live_status_message() {
+        let departure_datetime = Kyiv.with_ymd_and_hms(2024, 4, 9, 18, 50, 0).unwrap();
+        let station = |name: &str| TicketStation {
+            code: None,
+            name: name.to_owned(),
+        };
+        let ticket = TicketData {
+            arrival_datetime: Some(Kyiv.with_ymd_and_hms(2024, 4, 10, 5, 44, 0).unwrap()),
+            departure_station: Some(station("Київ-Пасажирський")),
+            arrival_station: Some(station("Івано-Франківськ")),
+            ..ticket("043К", departure_datetime)
+        };
+        let delays: DelayedTrains = r#"<html><body><ul class="delayform-list">
+            <li>№43/44 Київ-Пас.-Івано-Франківськ (+1:10)</li>
+        </ul></body></html>"#
+            .parse()
+            .unwrap();
+        let now = departure_datetime - TimeDelta::minutes(95);
+
+        assert_eq!(
+            build_live_status_message(&ticket, Some(&delays), now, Kyiv),
+            "Train №043К, departing Tue 9 Apr, 18:50 Kyiv time\nКиїв-Пасажирський → Івано-Франківськ\nDeparts in 2 h 45 min.\nDelayed by 70 minutes, probable departure at 20:00 Kyiv time.\nUpdated at 17:15."
+        );
+        assert!(build_live_status_message(&ticket, None, now, Kyiv).ends_with(
+            "Departs in 1 h 35 min.\nDelay information is currently unavailable.\nUpdated at 17:15."
+        ));
+
+        let departed = build_live_status_message(
+            &ticket,
+            Some(&delays),
+            departure_datetime + TimeDelta::minutes(75),
+            Warsaw,
+        );
+        assert!(
+            departed.contains("Departed at about 19:00 Warsaw time (20:00 Kyiv time)."),
+            "{departed}"
+        );
+        assert!(!departed.contains("Updated"), "{departed}");
+    }
+}
@@ -0,0 +1,92 @@
+use chrono::{DateTime, TimeZone};
+use chrono_tz::{Europe::Kyiv, Tz};
+use database::Database;
+use telegram::ChatId;
+
+use crate::mydb::MyDb;
+
+/// E.g. `Tue 9 Apr, 20:36`.
+const DATETIME_FORMAT: &str = "%a %-d %b, %H:%M";
+const TIME_FORMAT: &str = "%H:%M";
+
+/// Zone, the chat reads times in. Tickets and the delays page are in Kyiv time.
+pub fn chat_timezone(db: &MyDb, chat: ChatId) -> Tz {
+    db.user_settings(chat).timezone.unwrap_or(Kyiv)
+}
+
+/// E.g. `Warsaw time` for `Europe/Warsaw`.
+pub fn zone_name(zone: Tz) -> String {
+    let city = zone.name().rsplit('/').next().unwrap_or(zone.name());
+    format!("{} time", city.replace('_', " "))
+}
+
+/// Date and time, e.g. `Tue 9 Apr, 20:36 Kyiv time`, in a zone other than Kyiv
+/// `Tue 9 Apr, 19:36 Warsaw time (20:36 Kyiv time)`.
+pub fn format_datetime<T: TimeZone>(datetime: &DateTime<T>, zone: Tz) -> String {
+    format_in(datetime, zone, DATETIME_FORMAT)
+}
+
+/// Time of day, e.g. `20:36 Kyiv time` or `19:36 Warsaw time (20:36 Kyiv time)`.
+pub fn format_time<T: TimeZone>(datetime: &DateTime<T>, zone: Tz) -> String {
+    format_in(datetime, zone, TIME_FORMAT)
+}
+
+fn format_in<T: TimeZone>(datetime: &DateTime<T>, zone: Tz, format: &str) -> String {
+    let kyiv = datetime.with_timezone(&Kyiv);
+    let local = datetime.with_timezone(&zone);
+    if local.naive_local() == kyiv.naive_local() {
+        return format!("{} Kyiv time", kyiv.format(format));
+    }
+
+    // near midnight the dates differ, then Kyiv date is shown as well
+    let kyiv_format = match local.date_naive() == kyiv.date_naive() {
+        true => TIME_FORMAT,
+        false => format,
+    };
+    format!(
+        "{} {} ({} Kyiv time)",
+        local.format(format),
+        zone_name(zone),
+        kyiv.format(kyiv_format)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+    use chrono_tz::{
+        America::New_York,
+        Europe::{Kiev, Kyiv, Warsaw},
+    };
+
+    use super::{format_datetime, format_time};
+
+    #[test]
+    fn test_format_in_chat_timezone() {
+        let departure = Kyiv.with_ymd_and_hms(2024, 4, 9, 20, 36, 0).unwrap();
+
+        assert_eq!(
+            format_datetime(&departure, Kyiv),
+            "Tue 9 Apr, 20:36 Kyiv time"
+        );
+        assert_eq!(
+            format_datetime(&departure, Kiev),
+            "Tue 9 Apr, 20:36 Kyiv time"
+        );
+        assert_eq!(
+            format_datetime(&departure, Warsaw),
+            "Tue 9 Apr, 19:36 Warsaw time (20:36 Kyiv time)"
+        );
+        assert_eq!(
+            format_time(&departure, Warsaw),
+            "19:36 Warsaw time (20:36 Kyiv time)"
+        );
+
+        let early = Kyiv.with_ymd_and_hms(2024, 4, 10, 5, 10, 0).unwrap();
+        assert_eq!(
+            format_datetime(&early, New_York),
+            "Tue 9 Apr, 22:10 New York time (Wed 10 Apr, 05:10 Kyiv time)"
+        );
+        assert_eq!(format_time(&early, Kyiv), "05:10 Kyiv time");
+    }
+}
@@ -1,78 +1,235 @@
+mod admin;
+mod broadcast;
+mod config;
 mod consts;
+mod conversation;
+mod delays;
+mod health;
+mod history;
+mod http;
+mod journey;
+mod live_status;
+mod local_time;
+mod matching;
+mod monitoring;
 mod mydb;
+mod notifier;
+mod scheduler_state;
+mod settings;
+#[cfg(test)]
+mod test_util;
 mod tg;
+mod watch;
+use admin::Admin;
 use chrono::prelude::*;
 use chrono::Duration;
 use chrono_tz::Europe::Kyiv;
 use chrono_tz::Tz;
-use consts::SLEEP_BEFORE_FETCH_TRAINS;
+use clap::Parser;
+use config::{Cli, Config, LogConfig};
 use database::Database;
+use delays::SharedDelays;
+use health::{Heartbeat, TelegramCheck};
+use history::DelayObservation;
+use http::HttpState;
+use live_status::{is_live, LiveStatuses};
+use local_time::chat_timezone;
+use matching::{match_delayed_train, MatchConfidence};
 use mydb::MyDb;
+use notifier::{Notifier, Urgency};
 use pdf_parser::TicketData;
+use scheduler_state::SchedulerState;
 use std::env;
-use std::{
-    collections::{HashMap, HashSet},
-    error::Error,
-};
-use telegram::ChatId;
+use std::error::Error;
+use std::process::ExitCode;
+use tg::build_arrival_notification_message;
+use tg::build_connection_at_risk_message;
+use tg::build_delays_unavailable_reminder_message;
+use tg::build_disruption_alert_message;
 use tg::build_train_notification_message;
+use tg::build_watch_message;
 use tg::telegram_worker;
-use tracing::{debug, level_filters::LevelFilter, warn};
+use tokio::signal::unix::{signal, SignalKind};
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, error, info, trace, warn};
 use tracing_appender::{
     non_blocking::WorkerGuard,
     rolling::{RollingFileAppender, Rotation},
 };
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt, Layer};
-use ukrzaliznytsia_parser::UzParserClient;
+use train_number::TrainNumber;
+use ukrzaliznytsia_parser::{DelaySeverity, DelayedTrain, UzParseError, UzParserClient};
 
-use crate::consts::NOTIFY_BEFORE_TRAIN;
+use crate::consts::{CONNECTION_AT_RISK_BUFFER, EXIT_DRAIN_TIMEOUT, EXIT_STATE_NOT_SAVED};
 
 #[tokio::main]
-async fn main() {
-    dotenvy::dotenv().expect("dotenvy error");
-    let _tracing_guard = init_tracing().expect("Error seting up tracing");
+async fn main() -> ExitCode {
+    // .env is optional, the bot key may come from the config file
+    let _ = dotenvy::dotenv();
+    let cli = Cli::parse();
+    let config = match Config::load(&cli, env::vars()) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("{e}");
+            return ExitCode::FAILURE;
+        }
+    };
+    if cli.check_config {
+        println!("Config is valid");
+        return ExitCode::SUCCESS;
+    }
+
+    let _tracing_guard = init_tracing(&config.log).expect("Error seting up tracing");
+    info!(?config, "starting");
 
-    let telegram_key =
-        std::env::var("TELEGRAM_BOT_API_KEY").expect("TELEGRAM_BOT_API_KEY env var not set");
-    let tg = telegram::TelegramClient::new(telegram_key);
+    let uz_headers = config.uz.headers().expect("validated on load");
+    let tg = telegram::TelegramClient::new(
+        config.telegram.bot_api_key.clone(),
+        config.telegram.api_url().expect("validated on load"),
+        uz_headers.clone(),
+    );
     let db = MyDb::new();
-    let uz_parser = UzParserClient::new();
+    let delays = SharedDelays::new(UzParserClient::with_config(
+        config.uz.url().expect("validated on load"),
+        uz_headers,
+        config.uz.fetch_policy(),
+    ));
+
+    let heartbeat = Heartbeat::new();
+    let metrics = monitoring::install_recorder().expect("Error installing metrics recorder");
+    if config.http.enabled {
+        let state = HttpState {
+            metrics,
+            heartbeat: heartbeat.clone(),
+            health: config.health.clone(),
+            db: db.clone(),
+            telegram: TelegramCheck::new(tg.clone()),
+            delays: delays.clone(),
+        };
+        let listen = config.http.listen;
+        tokio::spawn(async move {
+            if let Err(e) = http::serve(listen, state).await {
+                error!(%e, %listen, "http server");
+            }
+        });
+    }
 
+    let shutdown = CancellationToken::new();
     tokio::spawn({
+        let shutdown = shutdown.clone();
+        async move {
+            shutdown_signal().await;
+            shutdown.cancel();
+        }
+    });
+
+    let admin = Admin::new(config.admin.clone(), shutdown.clone());
+    if let Err(e) = admin.resume_broadcast(&tg) {
+        error!(%e, "resuming broadcast");
+    }
+
+    let worker = tokio::spawn({
         let db = db.clone();
         let tg = tg.clone();
+        let delays = delays.clone();
+        let admin = admin.clone();
+        let shutdown = shutdown.clone();
         async {
-            telegram_worker(db, tg).await;
+            telegram_worker(db, tg, delays, admin, shutdown.cancelled_owned()).await;
         }
     });
 
-    let mut notify_before_train_desc = NOTIFY_BEFORE_TRAIN;
-    notify_before_train_desc.sort_by(|a, b| b.cmp(a));
-
-    let mut notifications: HashMap<ChatId, HashSet<Duration>> = HashMap::new();
+    let notify_before_train_desc = config.scheduler.reminder_offsets();
+    let severity_thresholds = config.alerts.thresholds();
+
+    let state_file = &config.shutdown.state_file;
+    let SchedulerState {
+        mut notifications,
+        mut connection_alerts,
+        mut disruption_alerts,
+        mut arrival_updates,
+        mut watch_updates,
+        mut delays_followups,
+        live_status_delays,
+        held_notifications,
+    } = SchedulerState::load(state_file).unwrap_or_else(|e| {
+        warn!(%e, ?state_file, "loading scheduler state, starting afresh");
+        SchedulerState::default()
+    });
+    let mut notifier = Notifier::new(
+        db.clone(),
+        tg.clone(),
+        config.alerts.thresholds(),
+        held_notifications,
+    );
+    let mut live_statuses = LiveStatuses::new(
+        db.clone(),
+        tg.clone(),
+        live_status_delays,
+        config.scheduler.live_status_delay_change_minutes,
+    );
 
     loop {
+        heartbeat.beat();
         let kyiv_time_now = kyiv_time();
 
-        let delayed_trains_response = uz_parser.delayed_trains().await;
+        // reminders go out without delay data, everything based on delays waits for it
+        let delayed_trains_response = delays.get(Duration::zero()).await;
         let delayed_trains = match delayed_trains_response {
-            Ok(delayed_trains) => delayed_trains.0,
+            Ok(snapshot) => {
+                // the count alone goes stale during outages, the fetch time tells its age
+                metrics::gauge!("uz_bot_delayed_trains").set(snapshot.trains.0.len() as f64);
+                metrics::gauge!("uz_bot_delays_fetched_timestamp_seconds")
+                    .set(snapshot.fetched_at.timestamp() as f64);
+                Some(snapshot.trains)
+            }
+            Err(UzParseError::BackingOff(wait)) => {
+                trace!(?wait, "uz backing off");
+                None
+            }
             Err(e) => {
-                warn!(%e,"delayed trains");
-                continue;
+                warn!(%e, health = ?delays.health(), "delayed trains");
+                None
             }
         };
 
-        let user_with_his_train = |user_train: TicketData| {
-            let delayed = delayed_trains
-                .iter()
-                .find(|delayed_train| delayed_train.numbers.0.contains(&user_train.train_number));
-            (user_train, delayed)
-        };
+        let stats = db.stats();
+        metrics::gauge!("uz_bot_active_users").set(stats.users as f64);
+        metrics::gauge!("uz_bot_active_tickets").set(stats.tickets as f64);
+        metrics::gauge!("uz_bot_active_watches").set(stats.watches as f64);
+
+        if let Some(delayed_trains) = &delayed_trains {
+            let tickets = db
+                .users()
+                .flat_map(|(_, tickets)| tickets)
+                .collect::<Vec<_>>();
+            for delayed_train in &delayed_trains.0 {
+                let run_date = run_date(&tickets, &delayed_trains.0, delayed_train, kyiv_time_now);
+                db.record_delay(DelayObservation::new(
+                    delayed_train,
+                    run_date,
+                    kyiv_time_now,
+                ));
+            }
+        }
 
         for (user, user_trains) in db.users() {
+            let user_trains = user_trains.collect::<Vec<_>>();
+            // before removal of departed tickets, so that their status shows the departure
+            for user_train in &user_trains {
+                live_statuses
+                    .update(
+                        &mut notifier,
+                        user,
+                        user_train,
+                        delayed_trains.as_deref(),
+                        kyiv_time_now,
+                    )
+                    .await;
+            }
             let only_recent_trains = |user_train: TicketData| {
-                let entry = notifications.entry(user.clone()).or_insert(HashSet::new());
+                let key = (user, user_train.clone());
+                let entry = notifications.entry(key.clone()).or_default();
 
                 for timespan in notify_before_train_desc.iter() {
                     if entry.contains(timespan) {
@@ -87,8 +244,12 @@ async fn main() {
                     }
                 }
 
-                if entry.len() == notify_before_train_desc.len() {
+                // tracked tickets are kept until arrival and removed by arrival tracking below
+                if entry.len() == notify_before_train_desc.len()
+                    && db.arrival_trackers(user, &user_train).next().is_none()
+                {
                     debug!(%user,?user_train, "no notifications to send");
+                    notifications.remove(&key);
                     if let Err(e) = db.remove_user_train(user, user_train) {
                         warn!(%e,"removing user from db after notifications")
                     }
@@ -97,39 +258,440 @@ async fn main() {
                 None
             };
 
-            let user_delayed_trains = user_trains
+            let user_reminded_trains = user_trains
+                .iter()
+                .cloned()
                 .filter_map(only_recent_trains)
-                .map(user_with_his_train);
+                .collect::<Vec<_>>();
+
+            for user_ticket in user_reminded_trains {
+                // subscribers of a shared ticket get the same notifications as its owner
+                let recipients = std::iter::once(user)
+                    .chain(db.subscribers(user, &user_ticket))
+                    .collect::<Vec<_>>();
+                // `None` while delays are unavailable
+                let delay_match = delayed_trains.as_ref().map(|delayed_trains| {
+                    match_delayed_train(&delayed_trains.0, &user_ticket, kyiv_time_now)
+                });
+                if delay_match.is_some() {
+                    // the reminder itself carries the delay, no follow-up needed
+                    delays_followups.remove(&(user, user_ticket.clone()));
+                } else {
+                    delays_followups.insert((user, user_ticket.clone()));
+                }
+                let urgency = match delay_match {
+                    Some(delay_match) => notifier.delay_urgency(
+                        delay_match
+                            .map(|delay_match| delay_match.delayed_train.delay.total_minutes())
+                            .unwrap_or(0),
+                    ),
+                    None => Urgency::Routine,
+                };
+                for recipient in recipients {
+                    if is_live(&db, recipient) {
+                        live_statuses
+                            .remind(
+                                &mut notifier,
+                                user,
+                                &user_ticket,
+                                recipient,
+                                delayed_trains.as_deref(),
+                                kyiv_time_now,
+                            )
+                            .await;
+                        continue;
+                    }
+                    let zone = chat_timezone(&db, recipient);
+                    let message = match delay_match {
+                        Some(delay_match) => {
+                            build_train_notification_message(user_ticket.clone(), delay_match, zone)
+                        }
+                        None => build_delays_unavailable_reminder_message(&user_ticket, zone),
+                    };
+                    if let Err(e) = notifier
+                        .send_until(
+                            "departure",
+                            recipient,
+                            message,
+                            urgency,
+                            user_ticket.departure_datetime,
+                            kyiv_time_now,
+                        )
+                        .await
+                    {
+                        warn!(%e,"Error sending message to user telegram");
+                    }
+                }
+            }
 
-            for (user_ticket, delayed_train) in user_delayed_trains {
-                let message = build_train_notification_message(user_ticket, delayed_train);
-                if let Err(e) = tg.send_to_user(user, message).await {
-                    warn!(%e,"Error sending message to user telegram");
+            let Some(delayed_trains) = &delayed_trains else {
+                continue;
+            };
+            let upcoming_trains = user_trains
+                .iter()
+                .filter(|user_train| user_train.departure_datetime > kyiv_time_now);
+            for user_train in upcoming_trains {
+                // an uncertain delay is not worth an alert
+                let Some(delayed_train) =
+                    match_delayed_train(&delayed_trains.0, user_train, kyiv_time_now)
+                        .filter(|delay_match| delay_match.confidence > MatchConfidence::Low)
+                        .map(|delay_match| delay_match.delayed_train)
+                else {
+                    continue;
+                };
+
+                // alerted again only when the delay reaches a higher tier
+                let severity = delayed_train.delay.severity(&severity_thresholds);
+                let key = (user, user_train.clone());
+                let alerted = disruption_alerts
+                    .get(&key)
+                    .map(|delay_minutes| severity_thresholds.classify(*delay_minutes));
+                if severity == DelaySeverity::Minor || alerted >= Some(severity) {
+                    continue;
+                }
+                disruption_alerts.insert(key, delayed_train.delay.total_minutes());
+
+                let recipients = std::iter::once(user).chain(db.subscribers(user, user_train));
+                for recipient in recipients {
+                    let message = build_disruption_alert_message(
+                        user_train,
+                        delayed_train,
+                        severity,
+                        config.alerts.refund_delay_minutes,
+                        chat_timezone(&db, recipient),
+                    );
+                    if let Err(e) = notifier
+                        .send(
+                            "disruption",
+                            recipient,
+                            message,
+                            Urgency::Urgent,
+                            kyiv_time_now,
+                        )
+                        .await
+                    {
+                        warn!(%e,"Error sending disruption alert to user telegram");
+                    }
                 }
             }
+
+            let departed_trains = user_trains
+                .into_iter()
+                .filter(|user_train| user_train.departure_datetime <= kyiv_time_now);
+            for user_train in departed_trains {
+                let trackers = db.arrival_trackers(user, &user_train).collect::<Vec<_>>();
+                let Some(arrival_datetime) = user_train.arrival_datetime else {
+                    continue;
+                };
+                if trackers.is_empty() {
+                    continue;
+                }
+
+                let delayed_train =
+                    match_delayed_train(&delayed_trains.0, &user_train, kyiv_time_now)
+                        .map(|delay_match| delay_match.delayed_train);
+                let key = (user, user_train.clone());
+                // a train often leaves the page before it arrives, then the last known delay holds
+                let delay_minutes = delayed_train
+                    .map(|delayed_train| delayed_train.delay.total_minutes())
+                    .or_else(|| arrival_updates.get(&key).copied())
+                    .unwrap_or(0);
+
+                if arrival_datetime + Duration::minutes(delay_minutes as i64) < kyiv_time_now {
+                    debug!(%user,?user_train, "train arrived");
+                    arrival_updates.remove(&key);
+                    notifications.remove(&key);
+                    if let Err(e) = db.remove_user_train(user, user_train) {
+                        warn!(%e,"removing user from db after arrival")
+                    }
+                    continue;
+                }
+
+                if arrival_updates.get(&key) == Some(&delay_minutes) {
+                    continue;
+                }
+                arrival_updates.insert(key, delay_minutes);
+
+                for tracker in trackers {
+                    let message = build_arrival_notification_message(
+                        &user_train,
+                        arrival_datetime,
+                        delayed_train,
+                        chat_timezone(&db, tracker),
+                    );
+                    let urgency = notifier.delay_urgency(delay_minutes);
+                    if let Err(e) = notifier
+                        .send_until(
+                            "arrival",
+                            tracker,
+                            message,
+                            urgency,
+                            arrival_datetime + Duration::minutes(delay_minutes as i64),
+                            kyiv_time_now,
+                        )
+                        .await
+                    {
+                        warn!(%e,"Error sending arrival update to user telegram");
+                    }
+                }
+            }
+        }
+
+        disruption_alerts.retain(|(_, ticket), _| ticket.departure_datetime > kyiv_time_now);
+        live_statuses.prune(kyiv_time_now);
+
+        for (user, journey) in db.journeys() {
+            if journey.is_finished(kyiv_time_now) {
+                debug!(%user, ?journey, "journey finished");
+                connection_alerts
+                    .retain(|(alert_user, leg), _| !(*alert_user == user && journey.contains(leg)));
+                if let Err(e) = db.remove_journey(user, &journey) {
+                    warn!(%e,"removing finished journey");
+                }
+                continue;
+            }
+
+            let Some(delayed_trains) = &delayed_trains else {
+                continue;
+            };
+            for transfer in journey.transfers() {
+                if transfer.departing.departure_datetime < kyiv_time_now {
+                    continue;
+                }
+                // an uncertain delay is not worth a missed connection alert
+                let Some(delayed_train) =
+                    match_delayed_train(&delayed_trains.0, transfer.arriving, kyiv_time_now)
+                        .filter(|delay_match| delay_match.confidence > MatchConfidence::Low)
+                        .map(|delay_match| delay_match.delayed_train)
+                else {
+                    continue;
+                };
+
+                let delay_minutes = delayed_train.delay.total_minutes();
+                let remaining_buffer =
+                    transfer.remaining_buffer(Duration::minutes(delay_minutes as i64));
+                if remaining_buffer >= CONNECTION_AT_RISK_BUFFER {
+                    continue;
+                }
+
+                let key = (user, transfer.arriving.clone());
+                if connection_alerts.get(&key) == Some(&delay_minutes) {
+                    continue;
+                }
+                connection_alerts.insert(key, delay_minutes);
+
+                let message =
+                    build_connection_at_risk_message(&transfer, delayed_train, remaining_buffer);
+                let recipients =
+                    std::iter::once(user).chain(db.subscribers(user, transfer.arriving));
+                for recipient in recipients {
+                    if let Err(e) = notifier
+                        .send(
+                            "connection",
+                            recipient,
+                            message.clone(),
+                            Urgency::Urgent,
+                            kyiv_time_now,
+                        )
+                        .await
+                    {
+                        warn!(%e,"Error sending connection alert to user telegram");
+                    }
+                }
+            }
+        }
+
+        for (user, watch) in db.watches() {
+            let key = (user, watch.train_number.clone());
+            if watch.is_expired(kyiv_time_now) {
+                debug!(%user, ?watch, "watch expired");
+                watch_updates.remove(&key);
+                if let Err(e) = db.remove_watch(user, &watch) {
+                    warn!(%e,"removing expired watch");
+                }
+                let message = format!(
+                    "Stopped watching train №{}, use /watch to continue.",
+                    watch.train_number
+                );
+                if let Err(e) = notifier
+                    .send(
+                        "watch_expired",
+                        user,
+                        message,
+                        Urgency::Routine,
+                        kyiv_time_now,
+                    )
+                    .await
+                {
+                    warn!(%e,"Error sending watch expiration to user telegram");
+                }
+                continue;
+            }
+
+            let Some(delayed_trains) = &delayed_trains else {
+                continue;
+            };
+            let delayed_train = find_delayed_train(&delayed_trains.0, &watch.train_number);
+            let delay_minutes =
+                delayed_train.map(|delayed_train| delayed_train.delay.total_minutes());
+            if watch_updates.get(&key) == Some(&delay_minutes) {
+                continue;
+            }
+            watch_updates.insert(key, delay_minutes);
+
+            let message = build_watch_message(&watch.train_number, delayed_train);
+            let urgency = notifier.delay_urgency(delay_minutes.unwrap_or(0));
+            if let Err(e) = notifier
+                .send_until("watch", user, message, urgency, watch.until, kyiv_time_now)
+                .await
+            {
+                warn!(%e,"Error sending watch update to user telegram");
+            }
+        }
+
+        if let Some(delayed_trains) = &delayed_trains {
+            for (user, user_ticket) in std::mem::take(&mut delays_followups) {
+                let still_exists = db
+                    .retrieve_user_trains(user)
+                    .any(|user_train| user_train == user_ticket);
+                if !still_exists || user_ticket.departure_datetime <= kyiv_time_now {
+                    continue;
+                }
+
+                // status messages show the delay as soon as it is available
+                let recipients = std::iter::once(user)
+                    .chain(db.subscribers(user, &user_ticket))
+                    .filter(|recipient| db.status_message(user, &user_ticket, *recipient).is_none())
+                    .collect::<Vec<_>>();
+                let delay_match =
+                    match_delayed_train(&delayed_trains.0, &user_ticket, kyiv_time_now);
+                let urgency = notifier.delay_urgency(
+                    delay_match
+                        .as_ref()
+                        .map(|delay_match| delay_match.delayed_train.delay.total_minutes())
+                        .unwrap_or(0),
+                );
+                for recipient in recipients {
+                    let message = format!(
+                        "Delay information is available again.\n{}",
+                        build_train_notification_message(
+                            user_ticket.clone(),
+                            delay_match,
+                            chat_timezone(&db, recipient)
+                        )
+                    );
+                    if let Err(e) = notifier
+                        .send_until(
+                            "followup",
+                            recipient,
+                            message,
+                            urgency,
+                            user_ticket.departure_datetime,
+                            kyiv_time_now,
+                        )
+                        .await
+                    {
+                        warn!(%e,"Error sending delays follow-up to user telegram");
+                    }
+                }
+            }
+        }
+
+        notifier.flush(kyiv_time_now).await;
+
+        tokio::select! {
+            _ = tokio::time::sleep(config.scheduler.poll_interval()) => {}
+            _ = shutdown.cancelled() => break,
         }
-        tokio::time::sleep(SLEEP_BEFORE_FETCH_TRAINS).await;
     }
+
+    info!("shutting down");
+    let mut exit_code = ExitCode::SUCCESS;
+    match tokio::time::timeout(config.shutdown.drain_timeout(), worker).await {
+        Ok(Ok(())) => debug!("telegram updates drained"),
+        Ok(Err(e)) => {
+            error!(%e, "telegram worker");
+            exit_code = ExitCode::FAILURE;
+        }
+        Err(_) => {
+            warn!(timeout = ?config.shutdown.drain_timeout(), "telegram updates still handled at shutdown deadline");
+            exit_code = ExitCode::from(EXIT_DRAIN_TIMEOUT);
+        }
+    }
+
+    admin.stopped_broadcast().await;
+
+    let state = SchedulerState {
+        notifications,
+        connection_alerts,
+        disruption_alerts,
+        arrival_updates,
+        watch_updates,
+        delays_followups,
+        live_status_delays: live_statuses.into_notified_delays(),
+        held_notifications: notifier.into_held(),
+    };
+    if let Err(e) = state.save(state_file) {
+        error!(%e, ?state_file, "saving scheduler state");
+        exit_code = ExitCode::from(EXIT_STATE_NOT_SAVED);
+    }
+
+    info!("stopped");
+    // tracing guard is dropped after this, flushing the log file
+    exit_code
 }
 
-fn kyiv_time() -> DateTime<Tz> {
-    let local_time = chrono::offset::Utc::now();
-    let kyiv_time = Kyiv.from_utc_datetime(&local_time.naive_utc());
+/// Completes on SIGTERM, sent by orchestrators, or Ctrl-C.
+async fn shutdown_signal() {
+    let mut terminate = signal(SignalKind::terminate()).expect("Error listening for SIGTERM");
+    tokio::select! {
+        _ = terminate.recv() => info!("SIGTERM received"),
+        _ = tokio::signal::ctrl_c() => info!("Ctrl-C received"),
+    }
+}
+
+/// Kyiv departure date of the listed run, known from a ticket on it, otherwise the current date.
+fn run_date(
+    tickets: &[TicketData],
+    delayed_trains: &[DelayedTrain],
+    delayed_train: &DelayedTrain,
+    now: DateTime<Tz>,
+) -> NaiveDate {
+    tickets
+        .iter()
+        .find(|ticket| {
+            match_delayed_train(delayed_trains, ticket, now).is_some_and(|delay_match| {
+                delay_match.confidence > MatchConfidence::Low
+                    && std::ptr::eq(delay_match.delayed_train, delayed_train)
+            })
+        })
+        .map(|ticket| ticket.departure_datetime.date_naive())
+        .unwrap_or_else(|| now.date_naive())
+}
 
-    kyiv_time
+fn find_delayed_train<'a>(
+    delayed_trains: &'a [DelayedTrain],
+    train_number: &TrainNumber,
+) -> Option<&'a DelayedTrain> {
+    delayed_trains
+        .iter()
+        .find(|delayed_train| delayed_train.numbers.matches(train_number))
 }
 
-fn init_tracing() -> Result<WorkerGuard, Box<dyn Error>> {
-    let log_file_prefix = env::var("LOG_FILE_PREFIX").expect("LOG_FILE_PREFIX env var not set");
+fn kyiv_time() -> DateTime<Tz> {
+    let local_time = chrono::offset::Utc::now();
+    Kyiv.from_utc_datetime(&local_time.naive_utc())
+}
 
+fn init_tracing(log: &LogConfig) -> Result<WorkerGuard, Box<dyn Error>> {
     let file_appender = RollingFileAppender::builder()
         .rotation(Rotation::DAILY)
-        .filename_prefix(log_file_prefix)
-        .build("logs")?;
+        .filename_prefix(&log.file_prefix)
+        .build(&log.directory)?;
     let (non_blocking, guard) = tracing_appender::non_blocking(file_appender);
 
     let envfilter = tracing_subscriber::EnvFilter::builder()
-        .with_default_directive(LevelFilter::DEBUG.into()) //.en
+        .with_default_directive(log.file_level()?.into())
         .from_env()?
         .add_directive("hyper=info".parse()?)
         .add_directive("reqwest=info".parse()?)
@@ -147,7 +709,7 @@ fn init_tracing() -> Result<WorkerGuard, Box<dyn Error>> {
         .pretty()
         .with_thread_ids(true)
         .with_line_number(true)
-        .with_filter(LevelFilter::INFO);
+        .with_filter(log.stdout_level()?);
 
     tracing_subscriber::registry()
         .with(fmt_layer)
@@ -163,9 +725,14 @@ mod tests {
     use chrono_tz::Europe::Kyiv;
     use database::Database;
     use pdf_parser::TicketData;
-    use telegram::ChatId;
+    use telegram::{ChatId, MessageId};
+    use train_number::TrainNumber;
 
+    use crate::history::DelayObservation;
     use crate::mydb::MyDb;
+    use crate::settings::{LiveStatus, UserSettings};
+    use crate::test_util::ticket;
+    use crate::watch::Watch;
     use chrono::prelude::*;
 
     #[test]
@@ -173,13 +740,16 @@ mod tests {
         let db = MyDb::new();
 
         let departure_datetime = Kyiv.with_ymd_and_hms(2024, 4, 9, 20, 36, 0).unwrap();
-        let train_number = "35".to_owned();
+        let train_number: TrainNumber = "35".parse().unwrap();
         let chat_id1 = ChatId(144441960);
         db.insert_ticket_data(
             chat_id1,
             TicketData {
                 departure_datetime,
                 train_number: train_number.clone(),
+                arrival_datetime: None,
+                departure_station: None,
+                arrival_station: None,
             },
         )
         .unwrap();
@@ -190,6 +760,9 @@ mod tests {
             TicketData {
                 departure_datetime,
                 train_number: train_number.clone(),
+                arrival_datetime: None,
+                departure_station: None,
+                arrival_station: None,
             },
         )
         .unwrap();
@@ -209,13 +782,16 @@ mod tests {
         let db = MyDb::new();
 
         let departure_datetime = Kyiv.with_ymd_and_hms(2024, 4, 9, 20, 36, 0).unwrap();
-        let train_number = "35".to_owned();
+        let train_number: TrainNumber = "35".parse().unwrap();
         let chat_id1 = ChatId(144441960);
         db.insert_ticket_data(
             chat_id1,
             TicketData {
                 departure_datetime,
                 train_number: train_number.clone(),
+                arrival_datetime: None,
+                departure_station: None,
+                arrival_station: None,
             },
         )
         .unwrap();
@@ -226,6 +802,9 @@ mod tests {
             TicketData {
                 departure_datetime,
                 train_number: train_number.clone(),
+                arrival_datetime: None,
+                departure_station: None,
+                arrival_station: None,
             },
         )
         .unwrap();
@@ -256,4 +835,167 @@ mod tests {
 
         assert_eq!(first_train.train_number, train_number);
     }
+
+    #[test]
+    fn test_shared_ticket_subscribers() {
+        let db = MyDb::new();
+
+        let ticket = ticket("35", Kyiv.with_ymd_and_hms(2024, 4, 9, 20, 36, 0).unwrap());
+        let owner = ChatId(144441960);
+        let subscriber = ChatId(144441961);
+        db.insert_ticket_data(owner, ticket.clone()).unwrap();
+        assert_eq!(db.added_by(owner, &ticket), None);
+        db.set_added_by(owner, ticket.clone(), subscriber).unwrap();
+        db.set_added_by(owner, ticket.clone(), owner).unwrap();
+        assert_eq!(
+            db.added_by(owner, &ticket),
+            Some(subscriber),
+            "the first member adding the ticket is kept"
+        );
+
+        db.insert_invite("code".to_owned(), owner, ticket.clone())
+            .unwrap();
+        let (invite_owner, invite_ticket) = db.resolve_invite("code").unwrap();
+        assert_eq!(invite_owner, owner);
+        assert_eq!(invite_ticket, ticket);
+
+        db.subscribe(owner, ticket.clone(), subscriber).unwrap();
+        assert_eq!(
+            db.subscribers(owner, &ticket).collect::<Vec<_>>(),
+            vec![subscriber]
+        );
+        assert_eq!(
+            db.subscriptions(subscriber).collect::<Vec<_>>(),
+            vec![(owner, ticket.clone())]
+        );
+
+        db.remove_user_train(owner, ticket.clone()).unwrap();
+        assert_eq!(db.subscribers(owner, &ticket).count(), 0);
+        assert!(db.resolve_invite("code").is_none());
+        assert_eq!(db.added_by(owner, &ticket), None);
+        assert!(
+            db.subscribe(owner, ticket, subscriber).is_err(),
+            "removed ticket must not accept subscribers"
+        );
+    }
+
+    #[test]
+    fn test_arrival_trackers() {
+        let db = MyDb::new();
+
+        let ticket = TicketData {
+            arrival_datetime: Some(Kyiv.with_ymd_and_hms(2024, 4, 10, 7, 5, 0).unwrap()),
+            ..ticket("35", Kyiv.with_ymd_and_hms(2024, 4, 9, 20, 36, 0).unwrap())
+        };
+        let owner = ChatId(144441960);
+        let subscriber = ChatId(144441961);
+        db.insert_ticket_data(owner, ticket.clone()).unwrap();
+        db.subscribe(owner, ticket.clone(), subscriber).unwrap();
+
+        db.track_arrival(owner, ticket.clone(), subscriber).unwrap();
+        assert_eq!(
+            db.arrival_trackers(owner, &ticket).collect::<Vec<_>>(),
+            vec![subscriber]
+        );
+
+        db.unsubscribe(owner, ticket.clone(), subscriber).unwrap();
+        assert_eq!(
+            db.arrival_trackers(owner, &ticket).count(),
+            0,
+            "unsubscribed chat must not track arrival"
+        );
+
+        db.track_arrival(owner, ticket.clone(), owner).unwrap();
+        db.remove_user_train(owner, ticket.clone()).unwrap();
+        assert_eq!(db.arrival_trackers(owner, &ticket).count(), 0);
+    }
+
+    #[test]
+    fn test_status_messages() {
+        let db = MyDb::new();
+
+        let ticket = ticket("35", Kyiv.with_ymd_and_hms(2024, 4, 9, 20, 36, 0).unwrap());
+        let owner = ChatId(144441960);
+        let subscriber = ChatId(144441961);
+        assert!(db
+            .set_status_message(owner, ticket.clone(), owner, MessageId(1))
+            .is_err());
+
+        db.insert_ticket_data(owner, ticket.clone()).unwrap();
+        db.subscribe(owner, ticket.clone(), subscriber).unwrap();
+        db.set_status_message(owner, ticket.clone(), owner, MessageId(1))
+            .unwrap();
+        db.set_status_message(owner, ticket.clone(), subscriber, MessageId(7))
+            .unwrap();
+        assert_eq!(
+            db.status_message(owner, &ticket, subscriber),
+            Some(MessageId(7))
+        );
+
+        db.unsubscribe(owner, ticket.clone(), subscriber).unwrap();
+        assert_eq!(db.status_message(owner, &ticket, subscriber), None);
+        db.remove_user_train(owner, ticket.clone()).unwrap();
+        assert_eq!(db.status_message(owner, &ticket, owner), None);
+
+        assert_eq!(db.user_settings(owner), UserSettings::default());
+        let settings = UserSettings {
+            live_status: LiveStatus::Pinned,
+            ..UserSettings::default()
+        };
+        db.update_settings(owner, settings.clone()).unwrap();
+        assert_eq!(db.user_settings(owner), settings);
+    }
+
+    #[test]
+    fn test_watch_replaces_same_train() {
+        let db = MyDb::new();
+        let user = ChatId(144441960);
+        let watch = |train_number: &str, hour| Watch {
+            train_number: train_number.parse().unwrap(),
+            until: Kyiv.with_ymd_and_hms(2024, 4, 9, hour, 0, 0).unwrap(),
+        };
+
+        db.insert_watch(user, watch("749", 10)).unwrap();
+        db.insert_watch(user, watch("705", 10)).unwrap();
+        db.insert_watch(user, watch("749", 12)).unwrap();
+
+        let mut watches = db.user_watches(user).collect::<Vec<_>>();
+        watches.sort_by(|a, b| a.train_number.cmp(&b.train_number));
+        assert_eq!(watches, vec![watch("705", 10), watch("749", 12)]);
+
+        db.remove_watch(user, &watch("749", 12)).unwrap();
+        assert_eq!(db.watches().count(), 1);
+    }
+
+    #[test]
+    fn test_delay_history_keeps_largest_daily_delay() {
+        let db = MyDb::new();
+        let observation = |day, hour, delay_minutes| DelayObservation {
+            train_numbers: TrainNumber::parse_all("705/706").unwrap(),
+            direction: "Пшемисль Головний-Київ-Пас.".to_owned(),
+            delay_minutes,
+            run_date: NaiveDate::from_ymd_opt(2024, 4, day).unwrap(),
+            observed_at: Kyiv.with_ymd_and_hms(2024, 4, day, hour, 0, 0).unwrap(),
+        };
+
+        db.record_delay(observation(9, 10, 30));
+        db.record_delay(observation(9, 11, 45));
+        db.record_delay(observation(9, 12, 20));
+        // the run of the 9th, still listed after midnight
+        db.record_delay(DelayObservation {
+            observed_at: Kyiv.with_ymd_and_hms(2024, 4, 10, 0, 30, 0).unwrap(),
+            ..observation(9, 0, 15)
+        });
+        db.record_delay(observation(10, 10, 5));
+
+        let history = db
+            .delay_history(&"706".parse().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            history,
+            vec![observation(9, 11, 45), observation(10, 10, 5)]
+        );
+        assert_eq!(db.delay_history(&"705".parse().unwrap()).count(), 2);
+        assert_eq!(db.delay_history(&"749".parse().unwrap()).count(), 0);
+    }
 }
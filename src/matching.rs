@@ -0,0 +1,188 @@
+use chrono::{DateTime, TimeDelta};
+use chrono_tz::Tz;
+use pdf_parser::{TicketData, TicketStation};
+use ukrzaliznytsia_parser::{DelayedTrain, TrainDirection};
+
+use crate::consts::{SAME_RUN_CERTAIN_LEAD, SAME_RUN_MAX_LEAD, SAME_RUN_MAX_TRAVEL};
+
+/// How sure we are that a delay listed on the UZ page is about the run of the ticket.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum MatchConfidence {
+    /// Train departs too long from now, the listed delay is likely of an earlier run.
+    Low,
+    /// Run fits the ticket date, but route was not confirmed or the run of the previous day
+    /// may still be on its way.
+    Medium,
+    /// Run fits the ticket date and one of ticket stations is on the route.
+    High,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct DelayMatch<'a> {
+    pub delayed_train: &'a DelayedTrain,
+    pub confidence: MatchConfidence,
+}
+
+/// Finds the delay of the run of the ticket train. The page lists only trains running now,
+/// so a ticket departing after [`SAME_RUN_MAX_LEAD`] or already arrived matches nothing.
+pub fn match_delayed_train<'a>(
+    delayed_trains: &'a [DelayedTrain],
+    ticket: &TicketData,
+    now: DateTime<Tz>,
+) -> Option<DelayMatch<'a>> {
+    let date_confidence = run_date_confidence(ticket, now)?;
+    let delayed_train = delayed_trains
+        .iter()
+        .find(|delayed_train| delayed_train.numbers.matches(&ticket.train_number))?;
+
+    let on_route = [&ticket.departure_station, &ticket.arrival_station]
+        .into_iter()
+        .flatten()
+        .any(|station| is_route_end(&delayed_train.direction, station));
+    let route_confidence = match on_route {
+        true => MatchConfidence::High,
+        false => MatchConfidence::Medium,
+    };
+
+    Some(DelayMatch {
+        delayed_train,
+        confidence: date_confidence.min(route_confidence),
+    })
+}
+
+/// Before departure the run of the previous day may still be on its way, e.g. of an overnight
+/// train, then the listed delay can be its one and is not certain.
+fn run_date_confidence(ticket: &TicketData, now: DateTime<Tz>) -> Option<MatchConfidence> {
+    let lead = ticket.departure_datetime - now;
+    let travel_end =
+        ticket.arrival_datetime.unwrap_or(ticket.departure_datetime) + SAME_RUN_MAX_TRAVEL;
+    let travel_time = ticket
+        .arrival_datetime
+        .map(|arrival| arrival - ticket.departure_datetime)
+        .unwrap_or(SAME_RUN_MAX_TRAVEL);
+    let previous_run_end = ticket.departure_datetime - TimeDelta::days(1) + travel_time;
+
+    if lead > SAME_RUN_MAX_LEAD || now > travel_end {
+        None
+    } else if lead > SAME_RUN_CERTAIN_LEAD {
+        Some(MatchConfidence::Low)
+    } else if lead > TimeDelta::zero() && previous_run_end > now {
+        Some(MatchConfidence::Medium)
+    } else {
+        Some(MatchConfidence::High)
+    }
+}
+
+/// Ticket station is the origin or destination of the train. Stations missing in the directory
+/// are compared by their whole name, ignoring case and punctuation.
+fn is_route_end(direction: &TrainDirection, station: &TicketStation) -> bool {
+    if let Some(known) = stations::find(&station.name) {
+        return [&direction.origin, &direction.destination]
+            .into_iter()
+            .flatten()
+            .any(|end| *end == known.name);
+    }
+
+    [&direction.origin, &direction.destination]
+        .into_iter()
+        .flatten()
+        .any(|end| stations::same_name(end, &station.name))
+}
+
+/// Note for the notification message, `None` when the match is certain enough.
+pub fn confidence_note(confidence: MatchConfidence) -> Option<&'static str> {
+    match confidence {
+        MatchConfidence::Low => Some(
+            "This delay may belong to an earlier run of the train, it will be checked again closer to departure.",
+        ),
+        MatchConfidence::Medium | MatchConfidence::High => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{TimeDelta, TimeZone};
+    use chrono_tz::Europe::Kyiv;
+    use pdf_parser::{TicketData, TicketStation};
+    use ukrzaliznytsia_parser::DelayedTrains;
+
+    use super::{match_delayed_train, MatchConfidence};
+    use crate::test_util;
+
+    fn delayed_trains() -> DelayedTrains {
+        r#"<html><body><ul class="delayform-list">
+            <li>№705/706 Пшемисль Головний-Київ-Пас. (+0:30)</li>
+            <li>№43/44 Київ-Пас.-Івано-Франківськ (+1:10)</li>
+            <li>№143/144 Яремче-Львів (+0:40)</li>
+        </ul></body></html>"#
+            .parse()
+            .unwrap()
+    }
+
+    fn ticket(train_number: &str, departure_station: Option<&str>) -> TicketData {
+        TicketData {
+            arrival_datetime: Some(Kyiv.with_ymd_and_hms(2024, 4, 10, 5, 44, 0).unwrap()),
+            departure_station: departure_station.map(|name| TicketStation {
+                code: None,
+                name: name.to_owned(),
+            }),
+            ..test_util::ticket(
+                train_number,
+                Kyiv.with_ymd_and_hms(2024, 4, 9, 18, 50, 0).unwrap(),
+            )
+        }
+    }
+
+    #[test]
+    fn test_match_confidence() {
+        let delayed_trains = delayed_trains();
+        let ticket_time = |hours_before_departure| {
+            Kyiv.with_ymd_and_hms(2024, 4, 9, 18, 50, 0).unwrap()
+                - TimeDelta::hours(hours_before_departure)
+        };
+        let confidence = |ticket: &TicketData, hours_before_departure| {
+            match_delayed_train(
+                &delayed_trains.0,
+                ticket,
+                ticket_time(hours_before_departure),
+            )
+            .map(|delay_match| delay_match.confidence)
+        };
+
+        let with_station = ticket("043К", Some("КИЇВ-ПАСАЖИРСЬКИЙ"));
+        let other_station = ticket("043", Some("ЛЬВІВ"));
+        let unknown_station = ticket("043", Some("ІВАНО-ФРАНКІВСЬК-2"));
+        let darnytsia = ticket("043", Some("КИЇВ-ДАРНИЦЯ"));
+        let unknown_route_end = ticket("143", Some("ЯРЕМЧЕ"));
+        let without_station = ticket("043", None);
+
+        assert_eq!(confidence(&with_station, 1), Some(MatchConfidence::High));
+        assert_eq!(confidence(&other_station, 1), Some(MatchConfidence::Medium));
+        assert_eq!(
+            confidence(&unknown_station, 1),
+            Some(MatchConfidence::Medium)
+        );
+        assert_eq!(confidence(&darnytsia, 1), Some(MatchConfidence::Medium));
+        assert_eq!(
+            confidence(&unknown_route_end, 1),
+            Some(MatchConfidence::High)
+        );
+        assert_eq!(
+            confidence(&without_station, 1),
+            Some(MatchConfidence::Medium)
+        );
+        assert_eq!(confidence(&with_station, 18), Some(MatchConfidence::Low));
+        assert_eq!(confidence(&with_station, 30), None, "tomorrow's run");
+        assert_eq!(confidence(&with_station, -24), None, "arrived long ago");
+        assert_eq!(confidence(&ticket("749", None), 1), None);
+
+        // yesterday's run arrives at 10:00, after the ticket departs it is today's run
+        let overnight = TicketData {
+            arrival_datetime: Some(Kyiv.with_ymd_and_hms(2024, 4, 10, 10, 0, 0).unwrap()),
+            ..with_station.clone()
+        };
+        assert_eq!(confidence(&overnight, 10), Some(MatchConfidence::Medium));
+        assert_eq!(confidence(&overnight, 8), Some(MatchConfidence::High));
+        assert_eq!(confidence(&overnight, -1), Some(MatchConfidence::High));
+    }
+}
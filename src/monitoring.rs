@@ -0,0 +1,74 @@
+use metrics::{describe_counter, describe_gauge, describe_histogram, Unit};
+use metrics_exporter_prometheus::{BuildError, Matcher, PrometheusBuilder, PrometheusHandle};
+
+/// Latency buckets of UZ page and Bot API requests, in seconds.
+const LATENCY_BUCKETS: [f64; 10] = [0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 15.0, 30.0];
+
+/// Installs the global recorder, metrics of all crates are rendered by the returned handle.
+pub fn install_recorder() -> Result<PrometheusHandle, BuildError> {
+    let handle = PrometheusBuilder::new()
+        .set_buckets_for_metric(
+            Matcher::Suffix("_duration_seconds".to_owned()),
+            &LATENCY_BUCKETS,
+        )?
+        .install_recorder()?;
+    describe_metrics();
+    Ok(handle)
+}
+
+fn describe_metrics() {
+    describe_counter!(
+        "uz_bot_tickets_added_total",
+        "Tickets added from pdf or /add"
+    );
+    describe_counter!(
+        "uz_bot_ticket_parse_failures_total",
+        "Pdf tickets failed to parse, by error"
+    );
+    describe_histogram!(
+        "uz_bot_uz_fetch_duration_seconds",
+        Unit::Seconds,
+        "Requests of the UZ delays page"
+    );
+    describe_counter!(
+        "uz_bot_uz_fetch_failures_total",
+        "Failed requests of the UZ delays page, by error"
+    );
+    describe_gauge!(
+        "uz_bot_delayed_trains",
+        "Delayed trains listed on the UZ page at the last fetch"
+    );
+    describe_gauge!(
+        "uz_bot_delays_fetched_timestamp_seconds",
+        Unit::Seconds,
+        "Unix time of the last successful fetch of the UZ delays page"
+    );
+    describe_counter!(
+        "uz_bot_reminders_sent_total",
+        "Notifications sent by the scheduler, by kind"
+    );
+    describe_counter!(
+        "uz_bot_reminders_failed_total",
+        "Notifications the scheduler failed to send, by kind"
+    );
+    describe_counter!(
+        "uz_bot_reminders_quieted_total",
+        "Notifications suppressed or held during quiet hours of the chat, by kind"
+    );
+    describe_histogram!(
+        "uz_bot_telegram_request_duration_seconds",
+        Unit::Seconds,
+        "Bot API requests, by method"
+    );
+    describe_counter!(
+        "uz_bot_telegram_request_failures_total",
+        "Failed Bot API requests, by method"
+    );
+    describe_gauge!(
+        "uz_bot_telegram_updates_in_progress",
+        "Telegram updates being handled"
+    );
+    describe_gauge!("uz_bot_active_users", "Chats with at least one ticket");
+    describe_gauge!("uz_bot_active_tickets", "Stored tickets");
+    describe_gauge!("uz_bot_active_watches", "Trains watched with /watch");
+}
@@ -1,31 +1,72 @@
-use core::hash::Hash;
+use chrono::NaiveDate;
 use dashmap::DashMap;
-use database::Database;
+use database::{Database, DatabaseStats};
 use pdf_parser::TicketData;
 use rclite::Arc;
-use std::collections::HashSet;
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    hash::Hash,
+};
+use telegram::{ChatId, MessageId};
 use tracing::trace;
+use train_number::TrainNumber;
+
+use crate::{
+    consts::DELAY_HISTORY_RETENTION, history::DelayObservation, journey::Journey,
+    settings::UserSettings, watch::Watch,
+};
 
 #[derive(Clone)]
-pub struct MyDb<K, V>(Arc<DashMap<K, V>>);
+pub struct MyDb(Arc<MyDbInner>);
+
+#[derive(Default)]
+struct MyDbInner {
+    tickets: DashMap<ChatId, HashSet<TicketData>>,
+    subscribers: DashMap<(ChatId, TicketData), HashSet<ChatId>>,
+    invites: DashMap<String, (ChatId, TicketData)>,
+    /// Member of the chat, who added the ticket.
+    added_by: DashMap<(ChatId, TicketData), ChatId>,
+    journeys: ChatItems<Journey>,
+    arrival_trackers: DashMap<(ChatId, TicketData), HashSet<ChatId>>,
+    watches: ChatItems<Watch>,
+    /// Stored under every number of the train without suffix, one observation per day.
+    delay_history: DashMap<u32, BTreeMap<NaiveDate, DelayObservation>>,
+    settings: DashMap<ChatId, UserSettings>,
+    /// Status message of every chat in live mode, by ticket.
+    status_messages: DashMap<(ChatId, TicketData), HashMap<ChatId, MessageId>>,
+}
 
-impl<K: Eq + Hash, V> MyDb<K, V> {
+impl MyDb {
     pub fn new() -> Self {
-        Self(Arc::new(DashMap::new()))
+        Self(Arc::new(MyDbInner::default()))
+    }
+
+    fn has_ticket(&self, owner: ChatId, ticket: &TicketData) -> bool {
+        self.0
+            .tickets
+            .get(&owner)
+            .map(|tickets| tickets.contains(ticket))
+            .unwrap_or(false)
     }
 }
 
-impl Database for MyDb<telegram::ChatId, HashSet<TicketData>> {
+impl Database for MyDb {
     type Error = DatabaseError;
     type Ticket = TicketData;
-    type User = telegram::ChatId;
+    type Journey = Journey;
+    type Watch = Watch;
+    type Observation = DelayObservation;
+    type TrainNumber = TrainNumber;
+    type Settings = UserSettings;
+    type MessageId = MessageId;
+    type User = ChatId;
 
     fn insert_ticket_data(
         &self,
         user_id: Self::User,
         ticket_data: Self::Ticket,
     ) -> Result<(), Self::Error> {
-        if let Some(mut data) = self.0.get_mut(&user_id) {
+        if let Some(mut data) = self.0.tickets.get_mut(&user_id) {
             let x = data.value_mut();
             trace!(?ticket_data, %user_id, "inserting old hashset");
             x.insert(ticket_data);
@@ -34,7 +75,7 @@ impl Database for MyDb<telegram::ChatId, HashSet<TicketData>> {
             trace!(?ticket_data, %user_id, "inserting to new hashset");
             hashset.insert(ticket_data);
 
-            self.0.insert(user_id, hashset);
+            self.0.tickets.insert(user_id, hashset);
         }
         Ok(())
     }
@@ -42,6 +83,7 @@ impl Database for MyDb<telegram::ChatId, HashSet<TicketData>> {
     fn retrieve_user_trains(&self, user_id: Self::User) -> impl Iterator<Item = TicketData> {
         let user_trains = self
             .0
+            .tickets
             .get(&user_id)
             .map(|data| data.value().iter().cloned().collect::<Vec<_>>())
             .unwrap_or_default();
@@ -54,22 +96,61 @@ impl Database for MyDb<telegram::ChatId, HashSet<TicketData>> {
         user_id: Self::User,
         train: Self::Ticket,
     ) -> Result<(), Self::Error> {
-        let Some(mut data) = self.0.get_mut(&user_id) else {
+        let Some(mut data) = self.0.tickets.get_mut(&user_id) else {
             return Err(DatabaseError::UserNotExisting);
         };
         trace!(?train, %user_id, "removing from db");
 
         data.value_mut().remove(&train);
+        drop(data);
+
+        self.0
+            .invites
+            .retain(|_, (owner, ticket)| !(*owner == user_id && *ticket == train));
+        self.0.arrival_trackers.remove(&(user_id, train.clone()));
+        self.0.status_messages.remove(&(user_id, train.clone()));
+        self.0.added_by.remove(&(user_id, train.clone()));
+        self.0.subscribers.remove(&(user_id, train));
+        Ok(())
+    }
+
+    fn set_added_by(
+        &self,
+        owner: Self::User,
+        ticket: Self::Ticket,
+        member: Self::User,
+    ) -> Result<(), Self::Error> {
+        if !self.has_ticket(owner, &ticket) {
+            return Err(DatabaseError::TicketNotExisting);
+        }
+        trace!(%owner, ?ticket, %member, "setting ticket added by");
+
+        self.0.added_by.entry((owner, ticket)).or_insert(member);
         Ok(())
     }
 
+    fn added_by(&self, owner: Self::User, ticket: &Self::Ticket) -> Option<Self::User> {
+        self.0
+            .added_by
+            .get(&(owner, ticket.clone()))
+            .map(|member| *member.value())
+    }
+
+    /// Ordered by chat and collected first, so that tickets can be changed while iterating.
     fn users(&self) -> impl Iterator<Item = (Self::User, impl Iterator<Item = TicketData>)> {
-        self.0.iter().map(|user| {
-            let (key, value) = user.pair();
-            let key = key.to_owned();
-            let value = value.to_owned();
-            (key, value.into_iter())
-        })
+        let mut users = self
+            .0
+            .tickets
+            .iter()
+            .map(|user| {
+                let (key, value) = user.pair();
+                let key = key.to_owned();
+                let value = value.to_owned();
+                (key, value.into_iter())
+            })
+            .collect::<Vec<_>>();
+        users.sort_by_key(|(user, _)| user.0);
+        users.into_iter()
     }
 
     // fn find_users_by_train(
@@ -106,10 +187,377 @@ impl Database for MyDb<telegram::ChatId, HashSet<TicketData>> {
     //     // .flatten();
     //     // todo!()
     // }
+
+    fn insert_invite(
+        &self,
+        code: String,
+        owner: Self::User,
+        ticket: Self::Ticket,
+    ) -> Result<(), Self::Error> {
+        trace!(%code, %owner, ?ticket, "inserting invite");
+        self.0.invites.insert(code, (owner, ticket));
+        Ok(())
+    }
+
+    fn resolve_invite(&self, code: &str) -> Option<(Self::User, Self::Ticket)> {
+        self.0
+            .invites
+            .get(code)
+            .map(|invite| invite.value().clone())
+    }
+
+    fn subscribe(
+        &self,
+        owner: Self::User,
+        ticket: Self::Ticket,
+        subscriber: Self::User,
+    ) -> Result<(), Self::Error> {
+        if !self.has_ticket(owner, &ticket) {
+            return Err(DatabaseError::TicketNotExisting);
+        }
+        trace!(%owner, ?ticket, %subscriber, "subscribing");
+
+        self.0
+            .subscribers
+            .entry((owner, ticket))
+            .or_default()
+            .insert(subscriber);
+        Ok(())
+    }
+
+    fn unsubscribe(
+        &self,
+        owner: Self::User,
+        ticket: Self::Ticket,
+        subscriber: Self::User,
+    ) -> Result<(), Self::Error> {
+        let key = (owner, ticket);
+        let Some(mut subscribers) = self.0.subscribers.get_mut(&key) else {
+            return Err(DatabaseError::TicketNotExisting);
+        };
+        trace!(%owner, %subscriber, "unsubscribing");
+
+        subscribers.value_mut().remove(&subscriber);
+        drop(subscribers);
+
+        if let Some(mut trackers) = self.0.arrival_trackers.get_mut(&key) {
+            trackers.value_mut().remove(&subscriber);
+        }
+        if let Some(mut messages) = self.0.status_messages.get_mut(&key) {
+            messages.value_mut().remove(&subscriber);
+        }
+        Ok(())
+    }
+
+    fn subscribers(
+        &self,
+        owner: Self::User,
+        ticket: &Self::Ticket,
+    ) -> impl Iterator<Item = ChatId> {
+        let subscribers = self
+            .0
+            .subscribers
+            .get(&(owner, ticket.clone()))
+            .map(|data| data.value().iter().copied().collect::<Vec<_>>())
+            .unwrap_or_default();
+
+        subscribers.into_iter()
+    }
+
+    fn subscriptions(&self, subscriber: Self::User) -> impl Iterator<Item = (ChatId, TicketData)> {
+        let subscriptions = self
+            .0
+            .subscribers
+            .iter()
+            .filter(|entry| entry.value().contains(&subscriber))
+            .map(|entry| entry.key().clone())
+            .collect::<Vec<_>>();
+
+        subscriptions.into_iter()
+    }
+
+    fn track_arrival(
+        &self,
+        owner: Self::User,
+        ticket: Self::Ticket,
+        chat: Self::User,
+    ) -> Result<(), Self::Error> {
+        if !self.has_ticket(owner, &ticket) {
+            return Err(DatabaseError::TicketNotExisting);
+        }
+        trace!(%owner, ?ticket, %chat, "tracking arrival");
+
+        self.0
+            .arrival_trackers
+            .entry((owner, ticket))
+            .or_default()
+            .insert(chat);
+        Ok(())
+    }
+
+    fn untrack_arrival(
+        &self,
+        owner: Self::User,
+        ticket: Self::Ticket,
+        chat: Self::User,
+    ) -> Result<(), Self::Error> {
+        let Some(mut trackers) = self.0.arrival_trackers.get_mut(&(owner, ticket)) else {
+            return Err(DatabaseError::TicketNotExisting);
+        };
+        trace!(%owner, %chat, "untracking arrival");
+
+        trackers.value_mut().remove(&chat);
+        Ok(())
+    }
+
+    fn arrival_trackers(
+        &self,
+        owner: Self::User,
+        ticket: &Self::Ticket,
+    ) -> impl Iterator<Item = ChatId> {
+        let trackers = self
+            .0
+            .arrival_trackers
+            .get(&(owner, ticket.clone()))
+            .map(|data| data.value().iter().copied().collect::<Vec<_>>())
+            .unwrap_or_default();
+
+        trackers.into_iter()
+    }
+
+    fn insert_journey(&self, user: Self::User, journey: Self::Journey) -> Result<(), Self::Error> {
+        trace!(%user, ?journey, "inserting journey");
+        self.0.journeys.insert(user, journey, |_| false);
+        Ok(())
+    }
+
+    fn user_journeys(&self, user: Self::User) -> impl Iterator<Item = Journey> {
+        self.0.journeys.of(user).into_iter()
+    }
+
+    fn journeys(&self) -> impl Iterator<Item = (ChatId, Journey)> {
+        self.0.journeys.all().into_iter()
+    }
+
+    fn remove_journey(&self, user: Self::User, journey: &Self::Journey) -> Result<(), Self::Error> {
+        trace!(%user, ?journey, "removing journey");
+        self.0.journeys.remove(user, journey)
+    }
+
+    fn insert_watch(&self, user: Self::User, watch: Self::Watch) -> Result<(), Self::Error> {
+        trace!(%user, ?watch, "inserting watch");
+        let train_number = watch.train_number.clone();
+        self.0.watches.insert(user, watch, |existing| {
+            existing.train_number == train_number
+        });
+        Ok(())
+    }
+
+    fn user_watches(&self, user: Self::User) -> impl Iterator<Item = Watch> {
+        self.0.watches.of(user).into_iter()
+    }
+
+    fn watches(&self) -> impl Iterator<Item = (ChatId, Watch)> {
+        self.0.watches.all().into_iter()
+    }
+
+    fn remove_watch(&self, user: Self::User, watch: &Self::Watch) -> Result<(), Self::Error> {
+        trace!(%user, ?watch, "removing watch");
+        self.0.watches.remove(user, watch)
+    }
+
+    /// One observation per run, so that a run listed past midnight is counted once.
+    fn record_delay(&self, observation: Self::Observation) {
+        let day = observation.run_date;
+        let oldest_day = (observation.observed_at - DELAY_HISTORY_RETENTION).date_naive();
+
+        for train_number in observation.train_numbers.iter() {
+            let mut history = self
+                .0
+                .delay_history
+                .entry(train_number.number())
+                .or_default();
+            let is_larger = history
+                .get(&day)
+                .map(|recorded| recorded.delay_minutes < observation.delay_minutes)
+                .unwrap_or(true);
+            if is_larger {
+                history.insert(day, observation.clone());
+            }
+            history.retain(|recorded_day, _| *recorded_day >= oldest_day);
+        }
+    }
+
+    fn delay_history(&self, train_number: &TrainNumber) -> impl Iterator<Item = DelayObservation> {
+        let history = self
+            .0
+            .delay_history
+            .get(&train_number.number())
+            .map(|data| data.value().values().cloned().collect::<Vec<_>>())
+            .unwrap_or_default();
+
+        history.into_iter()
+    }
+
+    fn user_settings(&self, user: Self::User) -> UserSettings {
+        self.0
+            .settings
+            .get(&user)
+            .map(|settings| settings.value().clone())
+            .unwrap_or_default()
+    }
+
+    fn update_settings(&self, user: Self::User, settings: UserSettings) -> Result<(), Self::Error> {
+        trace!(%user, ?settings, "updating settings");
+        self.0.settings.insert(user, settings);
+        Ok(())
+    }
+
+    fn set_status_message(
+        &self,
+        owner: Self::User,
+        ticket: Self::Ticket,
+        chat: Self::User,
+        message_id: MessageId,
+    ) -> Result<(), Self::Error> {
+        if !self.has_ticket(owner, &ticket) {
+            return Err(DatabaseError::TicketNotExisting);
+        }
+        trace!(%owner, ?ticket, %chat, ?message_id, "setting status message");
+
+        self.0
+            .status_messages
+            .entry((owner, ticket))
+            .or_default()
+            .insert(chat, message_id);
+        Ok(())
+    }
+
+    fn status_message(
+        &self,
+        owner: Self::User,
+        ticket: &Self::Ticket,
+        chat: Self::User,
+    ) -> Option<MessageId> {
+        self.0
+            .status_messages
+            .get(&(owner, ticket.clone()))
+            .and_then(|messages| messages.value().get(&chat).copied())
+    }
+
+    fn stats(&self) -> DatabaseStats {
+        let tickets = self.0.tickets.iter().map(|tickets| tickets.len());
+        DatabaseStats {
+            users: tickets.clone().filter(|count| *count > 0).count(),
+            tickets: tickets.sum(),
+            subscriptions: self.0.subscribers.iter().map(|s| s.len()).sum(),
+            watches: self.0.watches.count(),
+        }
+    }
+}
+
+/// Items every chat keeps by itself, like journeys and watches.
+struct ChatItems<T>(DashMap<ChatId, HashSet<T>>);
+
+impl<T> Default for ChatItems<T> {
+    fn default() -> Self {
+        Self(DashMap::new())
+    }
+}
+
+impl<T: Clone + Eq + Hash> ChatItems<T> {
+    /// Items of the chat, which the new one `replaces`, are removed.
+    fn insert(&self, chat: ChatId, item: T, replaces: impl Fn(&T) -> bool) {
+        let mut items = self.0.entry(chat).or_default();
+        items.retain(|existing| !replaces(existing));
+        items.insert(item);
+    }
+
+    fn of(&self, chat: ChatId) -> Vec<T> {
+        self.0
+            .get(&chat)
+            .map(|items| items.value().iter().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    fn all(&self) -> Vec<(ChatId, T)> {
+        self.0
+            .iter()
+            .flat_map(|entry| {
+                let chat = *entry.key();
+                entry
+                    .value()
+                    .iter()
+                    .map(|item| (chat, item.clone()))
+                    .collect::<Vec<_>>()
+            })
+            .collect()
+    }
+
+    fn remove(&self, chat: ChatId, item: &T) -> Result<(), DatabaseError> {
+        let Some(mut items) = self.0.get_mut(&chat) else {
+            return Err(DatabaseError::UserNotExisting);
+        };
+        items.value_mut().remove(item);
+        Ok(())
+    }
+
+    fn count(&self) -> usize {
+        self.0.iter().map(|items| items.len()).sum()
+    }
 }
 
 #[derive(Debug, thiserror::Error)]
 pub enum DatabaseError {
     #[error("User does not exist")]
     UserNotExisting,
+    #[error("Ticket does not exist")]
+    TicketNotExisting,
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+    use chrono_tz::Europe::Kyiv;
+    use database::{Database, DatabaseStats};
+    use telegram::ChatId;
+
+    use super::MyDb;
+    use crate::{test_util::ticket, watch::Watch};
+
+    #[test]
+    fn test_stats() {
+        let db = MyDb::new();
+        let departure = Kyiv.with_ymd_and_hms(2024, 4, 9, 20, 36, 0).unwrap();
+        let (owner, friend) = (ChatId(144441960), ChatId(144441961));
+        db.insert_ticket_data(owner, ticket("35", departure))
+            .unwrap();
+        db.insert_ticket_data(owner, ticket("43", departure))
+            .unwrap();
+        db.insert_ticket_data(friend, ticket("35", departure))
+            .unwrap();
+        db.subscribe(owner, ticket("43", departure), friend)
+            .unwrap();
+        db.insert_watch(
+            friend,
+            Watch {
+                train_number: "91".parse().unwrap(),
+                until: departure,
+            },
+        )
+        .unwrap();
+        // a chat without tickets left is not counted
+        db.remove_user_train(friend, ticket("35", departure))
+            .unwrap();
+
+        assert_eq!(
+            db.stats(),
+            DatabaseStats {
+                users: 1,
+                tickets: 2,
+                subscriptions: 1,
+                watches: 1,
+            }
+        );
+    }
 }
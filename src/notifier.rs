@@ -0,0 +1,304 @@
+use std::collections::HashMap;
+
+use chrono::{DateTime, NaiveTime};
+use chrono_tz::{Europe::Kyiv, Tz};
+use database::Database;
+use telegram::{ChatId, TelegramClient, TelegramErrors};
+use tracing::{trace, warn};
+use ukrzaliznytsia_parser::{DelaySeverity, SeverityThresholds};
+
+use crate::{
+    mydb::MyDb,
+    settings::{QuietHours, QuietMode},
+};
+
+/// Whether a notification may disturb the chat during its quiet hours.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Urgency {
+    Routine,
+    /// Breaks through quiet hours.
+    Urgent,
+}
+
+/// Notification collected during quiet hours in [`QuietMode::Collapse`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HeldNotification {
+    pub message: String,
+    /// Dropped after this time, e.g. an arrival update once its train arrived.
+    pub expires_at: Option<DateTime<Tz>>,
+}
+
+/// Sends scheduler notifications, following quiet hours of every chat.
+pub struct Notifier {
+    db: MyDb,
+    tg: TelegramClient,
+    /// Delays of at least major severity break through quiet hours.
+    thresholds: SeverityThresholds,
+    /// Notifications collected during quiet hours, by chat.
+    held: HashMap<ChatId, Vec<HeldNotification>>,
+}
+
+impl Notifier {
+    pub fn new(
+        db: MyDb,
+        tg: TelegramClient,
+        thresholds: SeverityThresholds,
+        held: HashMap<ChatId, Vec<HeldNotification>>,
+    ) -> Self {
+        Self {
+            db,
+            tg,
+            thresholds,
+            held,
+        }
+    }
+
+    /// Kept over restarts in the scheduler state.
+    pub fn into_held(self) -> HashMap<ChatId, Vec<HeldNotification>> {
+        self.held
+    }
+
+    /// Urgent for a delay of major severity or more.
+    pub fn delay_urgency(&self, delay_minutes: usize) -> Urgency {
+        match self.thresholds.classify(delay_minutes) >= DelaySeverity::Major {
+            true => Urgency::Urgent,
+            false => Urgency::Routine,
+        }
+    }
+
+    /// Quiet hours of the chat, if they are now in its time zone.
+    pub fn quiet_hours(&self, chat: ChatId, now: DateTime<Tz>) -> Option<QuietHours> {
+        let local_time = self.local_time(chat, now);
+        self.db
+            .user_settings(chat)
+            .quiet_hours
+            .filter(|quiet_hours| quiet_hours.contains(local_time))
+    }
+
+    fn local_time(&self, chat: ChatId, now: DateTime<Tz>) -> NaiveTime {
+        let zone = self.db.user_settings(chat).timezone.unwrap_or(Kyiv);
+        now.with_timezone(&zone).time()
+    }
+
+    /// Routine notifications during quiet hours are sent silently, dropped or held,
+    /// depending on the [`QuietMode`] of the chat.
+    pub async fn send(
+        &mut self,
+        kind: &'static str,
+        chat: ChatId,
+        message: String,
+        urgency: Urgency,
+        now: DateTime<Tz>,
+    ) -> Result<(), TelegramErrors> {
+        self.deliver(kind, chat, message, urgency, None, now).await
+    }
+
+    /// Like [`Self::send`], but a notification is not held past `expires_at`, e.g. a reminder
+    /// about a train departing during quiet hours is sent silently instead of collapsed.
+    pub async fn send_until(
+        &mut self,
+        kind: &'static str,
+        chat: ChatId,
+        message: String,
+        urgency: Urgency,
+        expires_at: DateTime<Tz>,
+        now: DateTime<Tz>,
+    ) -> Result<(), TelegramErrors> {
+        self.deliver(kind, chat, message, urgency, Some(expires_at), now)
+            .await
+    }
+
+    async fn deliver(
+        &mut self,
+        kind: &'static str,
+        chat: ChatId,
+        message: String,
+        urgency: Urgency,
+        expires_at: Option<DateTime<Tz>>,
+        now: DateTime<Tz>,
+    ) -> Result<(), TelegramErrors> {
+        let quiet_hours = match urgency {
+            Urgency::Urgent => None,
+            Urgency::Routine => self.quiet_hours(chat, now),
+        };
+        let quiet_mode = quiet_hours.map(|quiet_hours| {
+            let digest_at = now + quiet_hours.remaining(self.local_time(chat, now));
+            match quiet_hours.mode {
+                // the digest would come too late, e.g. for a train departing in quiet hours
+                QuietMode::Collapse
+                    if expires_at.is_some_and(|expires_at| expires_at <= digest_at) =>
+                {
+                    QuietMode::Silent
+                }
+                mode => mode,
+            }
+        });
+        let result = match quiet_mode {
+            None => self.tg.send_to_user(chat, message).await,
+            Some(QuietMode::Silent) => self.tg.send_silently(chat, message).await.map(|_| ()),
+            Some(QuietMode::Suppress) => {
+                trace!(%chat, kind, "suppressed in quiet hours");
+                metrics::counter!("uz_bot_reminders_quieted_total", "kind" => kind).increment(1);
+                return Ok(());
+            }
+            Some(QuietMode::Collapse) => {
+                trace!(%chat, kind, "held until the end of quiet hours");
+                metrics::counter!("uz_bot_reminders_quieted_total", "kind" => kind).increment(1);
+                self.held.entry(chat).or_default().push(HeldNotification {
+                    message,
+                    expires_at,
+                });
+                return Ok(());
+            }
+        };
+        match result {
+            Ok(_) => metrics::counter!("uz_bot_reminders_sent_total", "kind" => kind).increment(1),
+            Err(_) => {
+                metrics::counter!("uz_bot_reminders_failed_total", "kind" => kind).increment(1)
+            }
+        }
+        result
+    }
+
+    /// Sends held notifications as one message to chats, whose quiet hours are over.
+    /// Expired ones are dropped.
+    pub async fn flush(&mut self, now: DateTime<Tz>) {
+        for held in self.held.values_mut() {
+            held.retain(|held| held.expires_at.is_none_or(|expires_at| expires_at > now));
+        }
+        self.held.retain(|_, held| !held.is_empty());
+
+        let chats = self
+            .held
+            .keys()
+            .filter(|chat| self.quiet_hours(**chat, now).is_none())
+            .copied()
+            .collect::<Vec<_>>();
+        for chat in chats {
+            let Some(held) = self.held.remove(&chat) else {
+                continue;
+            };
+            let messages = held
+                .into_iter()
+                .map(|held| held.message)
+                .collect::<Vec<_>>();
+            let message = format!("While you had quiet hours:\n\n{}", messages.join("\n\n"));
+            if let Err(e) = self
+                .send("quiet_digest", chat, message, Urgency::Urgent, now)
+                .await
+            {
+                warn!(%e, "Error sending quiet hours digest to user telegram");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use chrono::TimeZone;
+    use chrono_tz::{America::New_York, Europe::Kyiv};
+    use database::Database;
+    use reqwest::header::HeaderMap;
+    use telegram::{ChatId, TelegramClient};
+    use ukrzaliznytsia_parser::SeverityThresholds;
+
+    use super::{HeldNotification, Notifier, Urgency};
+    use crate::{mydb::MyDb, settings::UserSettings};
+
+    #[tokio::test]
+    async fn test_quiet_hours_collapse() {
+        let db = MyDb::new();
+        // nothing listens there, collapsed notifications are only held
+        let tg = TelegramClient::new(
+            "1:token",
+            "http://127.0.0.1:9".parse().unwrap(),
+            HeaderMap::new(),
+        );
+        let mut notifier = Notifier::new(
+            db.clone(),
+            tg,
+            SeverityThresholds::default(),
+            HashMap::new(),
+        );
+        let chat = ChatId(144441960);
+        db.update_settings(
+            chat,
+            UserSettings {
+                quiet_hours: Some("23:00-07:00 collapse".parse().unwrap()),
+                ..UserSettings::default()
+            },
+        )
+        .unwrap();
+        let night = Kyiv.with_ymd_and_hms(2024, 4, 9, 4, 10, 0).unwrap();
+
+        assert_eq!(notifier.delay_urgency(30), Urgency::Routine);
+        assert_eq!(notifier.delay_urgency(90), Urgency::Urgent);
+        for message in ["first", "second"] {
+            notifier
+                .send(
+                    "departure",
+                    chat,
+                    message.to_owned(),
+                    Urgency::Routine,
+                    night,
+                )
+                .await
+                .unwrap();
+        }
+        // the train departs before quiet hours end, so the reminder is sent right away,
+        // which fails as nothing listens
+        let departure = Kyiv.with_ymd_and_hms(2024, 4, 9, 5, 10, 0).unwrap();
+        assert!(notifier
+            .send_until(
+                "departure",
+                chat,
+                "departed".to_owned(),
+                Urgency::Routine,
+                departure,
+                night,
+            )
+            .await
+            .is_err());
+        // the train arrives after quiet hours end
+        let arrival = Kyiv.with_ymd_and_hms(2024, 4, 9, 9, 0, 0).unwrap();
+        notifier
+            .send_until(
+                "arrival",
+                chat,
+                "arriving".to_owned(),
+                Urgency::Routine,
+                arrival,
+                night,
+            )
+            .await
+            .unwrap();
+        notifier.flush(night).await;
+        // quiet hours are in the zone of the chat, 04:10 in Kyiv is 21:10 in New York
+        db.update_settings(
+            chat,
+            UserSettings {
+                timezone: Some(New_York),
+                ..db.user_settings(chat)
+            },
+        )
+        .unwrap();
+        assert_eq!(notifier.quiet_hours(chat, night), None);
+        let held = |message: &str, expires_at| HeldNotification {
+            message: message.to_owned(),
+            expires_at,
+        };
+        assert_eq!(
+            notifier.into_held(),
+            HashMap::from([(
+                chat,
+                vec![
+                    held("first", None),
+                    held("second", None),
+                    held("arriving", Some(arrival))
+                ]
+            )])
+        );
+    }
+}
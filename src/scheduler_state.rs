@@ -0,0 +1,228 @@
+use std::{
+    collections::{HashMap, HashSet},
+    path::Path,
+};
+
+use chrono::{DateTime, FixedOffset, TimeDelta};
+use chrono_tz::Europe::Kyiv;
+use pdf_parser::TicketData;
+use serde::{Deserialize, Serialize};
+use telegram::ChatId;
+use thiserror::Error;
+use train_number::TrainNumber;
+
+use crate::notifier::HeldNotification;
+
+/// What the scheduler has already told users, kept over restarts so that nothing is sent twice.
+/// Only useful with persistent storage, [`crate::mydb::MyDb`] loses tickets on restart.
+#[derive(Debug, Default, PartialEq)]
+pub struct SchedulerState {
+    /// Reminders sent for the ticket, by time before departure.
+    pub notifications: HashMap<(ChatId, TicketData), HashSet<TimeDelta>>,
+    /// Delay in minutes, user was last warned about, for the arriving train of a transfer.
+    pub connection_alerts: HashMap<(ChatId, TicketData), usize>,
+    /// Delay in minutes, the disruption alert was last sent for, before departure.
+    pub disruption_alerts: HashMap<(ChatId, TicketData), usize>,
+    /// Delay in minutes, trackers were last told about, for the departed train.
+    pub arrival_updates: HashMap<(ChatId, TicketData), usize>,
+    /// Delay in minutes, last reported for a watched train number, `None` for no delay.
+    pub watch_updates: HashMap<(ChatId, TrainNumber), Option<usize>>,
+    /// Tickets reminded about while delay data was unavailable, followed up once it is back.
+    pub delays_followups: HashSet<(ChatId, TicketData)>,
+    /// Delay in minutes, a chat in live mode was last sent a new message about.
+    pub live_status_delays: HashMap<(ChatId, TicketData), usize>,
+    /// Notifications collected during quiet hours, sent when they end.
+    pub held_notifications: HashMap<ChatId, Vec<HeldNotification>>,
+}
+
+#[derive(Debug, Error)]
+pub enum SchedulerStateError {
+    #[error("Error accessing scheduler state file: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Error (de)serializing scheduler state: {0}")]
+    Json(#[from] serde_json::Error),
+}
+
+/// Held notification message with its expiry.
+type StoredHeld = (String, Option<DateTime<FixedOffset>>);
+
+/// Stored form, JSON has no tuple keys.
+#[derive(Serialize, Deserialize)]
+struct StoredState {
+    notifications: Vec<(ChatId, TicketData, Vec<i64>)>,
+    connection_alerts: Vec<(ChatId, TicketData, usize)>,
+    #[serde(default)]
+    disruption_alerts: Vec<(ChatId, TicketData, usize)>,
+    arrival_updates: Vec<(ChatId, TicketData, usize)>,
+    watch_updates: Vec<(ChatId, TrainNumber, Option<usize>)>,
+    delays_followups: Vec<(ChatId, TicketData)>,
+    #[serde(default)]
+    live_status_delays: Vec<(ChatId, TicketData, usize)>,
+    #[serde(default)]
+    held_notifications: Vec<(ChatId, Vec<StoredHeld>)>,
+}
+
+impl SchedulerState {
+    /// Empty state if the file does not exist yet.
+    pub fn load(path: &Path) -> Result<Self, SchedulerStateError> {
+        let content = match std::fs::read_to_string(path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(e) => return Err(e.into()),
+        };
+        let stored: StoredState = serde_json::from_str(&content)?;
+
+        let entries = |entries: Vec<(ChatId, TicketData, usize)>| {
+            entries
+                .into_iter()
+                .map(|(user, ticket, delay)| ((user, ticket), delay))
+                .collect()
+        };
+        Ok(Self {
+            notifications: stored
+                .notifications
+                .into_iter()
+                .map(|(user, ticket, seconds)| {
+                    let timespans = seconds.into_iter().map(TimeDelta::seconds).collect();
+                    ((user, ticket), timespans)
+                })
+                .collect(),
+            connection_alerts: entries(stored.connection_alerts),
+            disruption_alerts: entries(stored.disruption_alerts),
+            arrival_updates: entries(stored.arrival_updates),
+            watch_updates: stored
+                .watch_updates
+                .into_iter()
+                .map(|(user, train_number, delay)| ((user, train_number), delay))
+                .collect(),
+            delays_followups: stored.delays_followups.into_iter().collect(),
+            live_status_delays: entries(stored.live_status_delays),
+            held_notifications: stored
+                .held_notifications
+                .into_iter()
+                .map(|(user, held)| {
+                    let held = held
+                        .into_iter()
+                        .map(|(message, expires_at)| HeldNotification {
+                            message,
+                            expires_at: expires_at
+                                .map(|expires_at| expires_at.with_timezone(&Kyiv)),
+                        })
+                        .collect();
+                    (user, held)
+                })
+                .collect(),
+        })
+    }
+
+    /// Writes to a temporary file first, so that a failed write keeps the previous state.
+    pub fn save(&self, path: &Path) -> Result<(), SchedulerStateError> {
+        let entries = |entries: &HashMap<(ChatId, TicketData), usize>| {
+            entries
+                .iter()
+                .map(|((user, ticket), delay)| (*user, ticket.clone(), *delay))
+                .collect()
+        };
+        let stored = StoredState {
+            notifications: self
+                .notifications
+                .iter()
+                .map(|((user, ticket), timespans)| {
+                    let seconds = timespans.iter().map(TimeDelta::num_seconds).collect();
+                    (*user, ticket.clone(), seconds)
+                })
+                .collect(),
+            connection_alerts: entries(&self.connection_alerts),
+            disruption_alerts: entries(&self.disruption_alerts),
+            arrival_updates: entries(&self.arrival_updates),
+            watch_updates: self
+                .watch_updates
+                .iter()
+                .map(|((user, train_number), delay)| (*user, train_number.clone(), *delay))
+                .collect(),
+            delays_followups: self.delays_followups.iter().cloned().collect(),
+            live_status_delays: entries(&self.live_status_delays),
+            held_notifications: self
+                .held_notifications
+                .iter()
+                .map(|(user, held)| {
+                    let held = held
+                        .iter()
+                        .map(|held| {
+                            let expires_at =
+                                held.expires_at.map(|expires_at| expires_at.fixed_offset());
+                            (held.message.clone(), expires_at)
+                        })
+                        .collect();
+                    (*user, held)
+                })
+                .collect(),
+        };
+
+        let temporary = path.with_extension("tmp");
+        std::fs::write(&temporary, serde_json::to_vec(&stored)?)?;
+        std::fs::rename(&temporary, path)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{TimeDelta, TimeZone};
+    use chrono_tz::Europe::Kyiv;
+    use pdf_parser::{TicketData, TicketStation};
+    use telegram::ChatId;
+
+    use super::SchedulerState;
+    use crate::{notifier::HeldNotification, test_util::ticket};
+
+    #[test]
+    fn test_scheduler_state_round_trip() {
+        let ticket = TicketData {
+            arrival_datetime: Some(Kyiv.with_ymd_and_hms(2024, 4, 10, 5, 44, 0).unwrap()),
+            departure_station: Some(TicketStation {
+                code: Some("2200001".to_owned()),
+                name: "КИЇВ-ПАСАЖИРСЬКИЙ".to_owned(),
+            }),
+            ..ticket(
+                "043К",
+                Kyiv.with_ymd_and_hms(2024, 4, 9, 20, 36, 0).unwrap(),
+            )
+        };
+        let user = ChatId(144441960);
+
+        let mut state = SchedulerState::default();
+        state.notifications.insert(
+            (user, ticket.clone()),
+            [TimeDelta::minutes(60), TimeDelta::minutes(30)].into(),
+        );
+        state.arrival_updates.insert((user, ticket.clone()), 25);
+        state.disruption_alerts.insert((user, ticket.clone()), 200);
+        state
+            .watch_updates
+            .insert((user, "91".parse().unwrap()), None);
+        state.live_status_delays.insert((user, ticket.clone()), 70);
+        state.delays_followups.insert((user, ticket.clone()));
+        state.held_notifications.insert(
+            user,
+            vec![HeldNotification {
+                message: "Your train №043К".to_owned(),
+                expires_at: Some(ticket.departure_datetime),
+            }],
+        );
+
+        let path = std::env::temp_dir().join(format!(
+            "uz_bot_scheduler_state_{}.json",
+            std::process::id()
+        ));
+        assert_eq!(
+            SchedulerState::load(&path).unwrap(),
+            SchedulerState::default()
+        );
+
+        state.save(&path).unwrap();
+        let loaded = SchedulerState::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(loaded, state);
+    }
+}
@@ -0,0 +1,151 @@
+use std::{fmt::Display, str::FromStr};
+
+use chrono::{NaiveTime, TimeDelta};
+use chrono_tz::Tz;
+
+use crate::consts::QUIET_USAGE_MESSAGE;
+
+/// Preferences a chat sets with commands, see [`database::Database::user_settings`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct UserSettings {
+    pub live_status: LiveStatus,
+    /// Quiet hours are in this zone too.
+    pub quiet_hours: Option<QuietHours>,
+    /// Times in messages are shown in this zone next to Kyiv time, `None` is Kyiv.
+    pub timezone: Option<Tz>,
+}
+
+/// Reminders as separate messages, or one status message per ticket kept up to date.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum LiveStatus {
+    #[default]
+    Off,
+    On,
+    /// Status message is also pinned, when the bot is allowed to pin in the chat.
+    Pinned,
+}
+
+/// Time of day, when routine notifications don't disturb the chat, e.g. `23:00-07:00`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct QuietHours {
+    pub start: NaiveTime,
+    pub end: NaiveTime,
+    pub mode: QuietMode,
+}
+
+/// What happens to routine notifications during quiet hours.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum QuietMode {
+    /// Sent with `disable_notification`.
+    #[default]
+    Silent,
+    /// Not sent at all.
+    Suppress,
+    /// Sent together in one message at the end of quiet hours.
+    Collapse,
+}
+
+impl QuietHours {
+    /// Quiet hours ending before they start pass midnight.
+    pub fn contains(&self, time: NaiveTime) -> bool {
+        match self.start <= self.end {
+            true => self.start <= time && time < self.end,
+            false => self.start <= time || time < self.end,
+        }
+    }
+
+    /// Time from `time` until quiet hours end next.
+    pub fn remaining(&self, time: NaiveTime) -> TimeDelta {
+        let remaining = self.end - time;
+        match remaining < TimeDelta::zero() {
+            true => remaining + TimeDelta::days(1),
+            false => remaining,
+        }
+    }
+}
+
+impl FromStr for QuietHours {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut words = s.split_whitespace();
+        let (start, end) = words
+            .next()
+            .and_then(|range| range.split_once('-'))
+            .ok_or_else(|| QUIET_USAGE_MESSAGE.to_owned())?;
+        let parse_time =
+            |time: &str| NaiveTime::parse_from_str(time, "%H:%M").map_err(|_| QUIET_USAGE_MESSAGE);
+        let (start, end) = (parse_time(start)?, parse_time(end)?);
+        if start == end {
+            return Err("Quiet hours must not start and end at the same time.".to_owned());
+        }
+
+        let mode = match words.next() {
+            None | Some("silent") => QuietMode::Silent,
+            Some("suppress") => QuietMode::Suppress,
+            Some("collapse") => QuietMode::Collapse,
+            Some(_) => return Err(QUIET_USAGE_MESSAGE.to_owned()),
+        };
+        if words.next().is_some() {
+            return Err(QUIET_USAGE_MESSAGE.to_owned());
+        }
+
+        Ok(Self { start, end, mode })
+    }
+}
+
+impl Display for QuietHours {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mode = match self.mode {
+            QuietMode::Silent => "notifications come silently",
+            QuietMode::Suppress => "notifications are not sent",
+            QuietMode::Collapse => "notifications are collected into one message at the end",
+        };
+        write!(
+            f,
+            "from {} to {}, {mode}",
+            self.start.format("%H:%M"),
+            self.end.format("%H:%M")
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{NaiveTime, TimeDelta};
+
+    use super::{QuietHours, QuietMode};
+
+    #[test]
+    fn test_quiet_hours() {
+        let time = |time: &str| NaiveTime::parse_from_str(time, "%H:%M").unwrap();
+
+        let night = "23:00-07:00".parse::<QuietHours>().unwrap();
+        assert_eq!(night.mode, QuietMode::Silent);
+        assert!(night.contains(time("23:00")));
+        assert!(night.contains(time("04:10")));
+        assert!(!night.contains(time("07:00")));
+        assert!(!night.contains(time("12:00")));
+        assert_eq!(night.remaining(time("04:10")), TimeDelta::minutes(170));
+        assert_eq!(night.remaining(time("23:30")), TimeDelta::minutes(450));
+
+        let afternoon = "13:00-15:30 collapse".parse::<QuietHours>().unwrap();
+        assert_eq!(afternoon.mode, QuietMode::Collapse);
+        assert!(afternoon.contains(time("14:00")));
+        assert!(!afternoon.contains(time("23:00")));
+        assert_eq!(
+            afternoon.to_string(),
+            "from 13:00 to 15:30, notifications are collected into one message at the end"
+        );
+
+        for wrong in [
+            "",
+            "23-07",
+            "23:00-07:00 loud",
+            "07:00-07:00",
+            "23:00-07:00 silent x",
+        ] {
+            assert!(wrong.parse::<QuietHours>().is_err(), "{wrong}");
+        }
+    }
+}
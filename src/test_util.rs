@@ -0,0 +1,16 @@
+//! Fixtures shared by tests of several modules.
+
+use chrono::DateTime;
+use chrono_tz::Tz;
+use pdf_parser::TicketData;
+
+/// Ticket without arrival time and stations, add them with struct update syntax.
+pub fn ticket(train_number: &str, departure_datetime: DateTime<Tz>) -> TicketData {
+    TicketData {
+        departure_datetime,
+        train_number: train_number.parse().unwrap(),
+        arrival_datetime: None,
+        departure_station: None,
+        arrival_station: None,
+    }
+}
@@ -1,86 +1,1000 @@
-use std::collections::HashSet;
-
-use chrono::{Duration, Timelike};
+use chrono::{DateTime, Datelike, Duration, TimeDelta};
+use chrono_tz::{Europe::Kyiv, Tz};
 use database::Database;
 use pdf_parser::TicketData;
-use telegram::{ChatId, TelegramClient};
-use tracing::{error, trace};
-use ukrzaliznytsia_parser::DelayedTrain;
+use rand::{distributions::Alphanumeric, Rng};
+use std::future::Future;
+use telegram::{ChatId, Command, Incoming, Reply, TelegramClient, WRONG_MESSAGE_RECEIVED};
+use tracing::{error, trace, warn};
+use train_number::TrainNumber;
+use ukrzaliznytsia_parser::{DelaySeverity, DelayedTrain, DelaysSnapshot, TrainDelayTime};
 
 use crate::{
-    consts::{EXTRACT_TICKET_ERROR_MESSAGE, LAYOUT_CHANGED_MESSAGE},
+    admin::{Admin, ADMIN_CALLBACK_PREFIX},
+    consts::{
+        DELAYS_UNAVAILABLE_MESSAGE, DELAY_HISTORY_RETENTION, EXTRACT_TICKET_ERROR_MESSAGE,
+        INVITE_CODE_LEN, INVITE_PREFIX, LAYOUT_CHANGED_MESSAGE, LIVE_USAGE_MESSAGE,
+        NO_JOURNEYS_MESSAGE, NO_TICKETS_MESSAGE, PREDICTION_MIN_SAMPLES, STATS_DEFAULT_WEEKS,
+        STATUS_MAX_AGE, TICKET_INDEX_ERROR_MESSAGE, TIMEZONE_USAGE_MESSAGE,
+        TRAIN_NUMBER_ERROR_MESSAGE, UNKNOWN_BUTTON_MESSAGE, UNKNOWN_COMMAND_MESSAGE,
+        WATCH_DEFAULT_DURATION, WATCH_MAX_DURATION,
+    },
+    conversation::{Conversations, Step, ADD_CALLBACK_PREFIX},
+    delays::SharedDelays,
+    find_delayed_train,
+    history::{DelayStats, TrainStats},
+    journey::{Journey, Transfer},
+    kyiv_time,
+    local_time::{chat_timezone, format_datetime, format_time, zone_name},
+    matching::{confidence_note, match_delayed_train, DelayMatch},
     mydb::MyDb,
+    settings::{LiveStatus, QuietHours},
+    watch::Watch,
 };
 
-pub async fn telegram_worker(db: MyDb<ChatId, HashSet<TicketData>>, tg: TelegramClient) {
-    tg.receive_messages(move |user, file_content| {
-        let db = db.clone();
-        async move {
-            let parsed_pdf_resp = tokio::task::spawn_blocking(move || {
-                pdf_parser::parse_departure_data_from_pdf(&*file_content)
-            })
-            .await;
-
-            let parsed_pdf_resp = match parsed_pdf_resp {
-                Ok(parsed_pdf_resp) => parsed_pdf_resp,
-                Err(e) => {
-                    error!(%e,"task join error");
-                    return Err("Internal error".to_owned());
-                }
-            };
+/// Answers users until `shutdown` completes and updates being handled are answered.
+pub async fn telegram_worker(
+    db: MyDb,
+    tg: TelegramClient,
+    delays: SharedDelays,
+    admin: Admin,
+    shutdown: impl Future<Output = ()> + Send + 'static,
+) {
+    let identity = match tg.bot_identity().await {
+        Ok(identity) => identity,
+        Err(e) => {
+            error!(%e, "getting bot username");
+            return;
+        }
+    };
+    let bot_username = identity.username.clone();
 
-            let ticket_data = match parsed_pdf_resp {
-                Ok(data) => data,
-                Err(e) => {
-                    let user_error_message = match e {
-                        pdf_parser::ParsePdfError::PdfExtractError(err) => {
-                            trace!(%err, "parsing departure data in telegram receiver");
-                            EXTRACT_TICKET_ERROR_MESSAGE
+    let conversations = Conversations::new();
+
+    tg.clone()
+        .receive_messages(
+            identity,
+            move |user, sender, incoming| {
+                let db = db.clone();
+                let tg = tg.clone();
+                let bot_username = bot_username.clone();
+                let conversations = conversations.clone();
+                let delays = delays.clone();
+                let admin = admin.clone();
+                // in a private chat the sender is the chat itself
+                let sender = ChatId::from(sender);
+                async move {
+                    match incoming {
+                        Incoming::Ticket(file_content) => {
+                            let ticket_data = parse_ticket(&admin, user, file_content).await?;
+                            insert_ticket(&db, user, sender, ticket_data).map(Reply::from)
+                        }
+                        Incoming::Command(command) if command.name == "status" => {
+                            train_status(&db, &delays, user, &command.args)
+                                .await
+                                .map(Reply::from)
+                        }
+                        Incoming::Command(command) if command.name == "admin" => {
+                            admin
+                                .command(&db, &delays, user, &command.args, kyiv_time())
+                                .await
                         }
-                        err => {
-                            error!(%err, "parsing departure data in telegram receiver");
-                            LAYOUT_CHANGED_MESSAGE
+                        Incoming::Command(command) => handle_command(
+                            &db,
+                            &conversations,
+                            user,
+                            sender,
+                            command,
+                            &bot_username,
+                        ),
+                        Incoming::Text(text) => {
+                            let step = conversations
+                                .answer(user, &text, kyiv_time())
+                                .ok_or_else(|| WRONG_MESSAGE_RECEIVED.to_owned())??;
+                            finish_step(&db, user, sender, step)
                         }
-                    };
+                        Incoming::Callback(data) => {
+                            if let Some(data) = data.strip_prefix(ADMIN_CALLBACK_PREFIX) {
+                                return admin.callback(&db, &tg, user, data);
+                            }
+                            let Some(data) = data.strip_prefix(ADD_CALLBACK_PREFIX) else {
+                                return Err(UNKNOWN_BUTTON_MESSAGE.to_owned());
+                            };
+                            let step = conversations.callback(user, data, kyiv_time())?;
+                            finish_step(&db, user, sender, step)
+                        }
+                    }
+                }
+            },
+            shutdown,
+        )
+        .await;
+}
+
+fn finish_step(db: &MyDb, user: ChatId, sender: ChatId, step: Step) -> Result<Reply, String> {
+    match step {
+        Step::Ask(reply) => Ok(reply),
+        Step::Done(ticket_data) => insert_ticket(db, user, sender, ticket_data).map(Reply::from),
+    }
+}
 
-                    return Err(user_error_message.to_owned());
+/// Failures are also kept for `/admin parse-failures`.
+async fn parse_ticket(
+    admin: &Admin,
+    user: ChatId,
+    file_content: Vec<u8>,
+) -> Result<TicketData, String> {
+    let parsed_pdf_resp = tokio::task::spawn_blocking(move || {
+        pdf_parser::parse_departure_data_from_pdf(&*file_content)
+    })
+    .await;
+
+    let parsed_pdf_resp = match parsed_pdf_resp {
+        Ok(parsed_pdf_resp) => parsed_pdf_resp,
+        Err(e) => {
+            error!(%e,"task join error");
+            return Err("Internal error".to_owned());
+        }
+    };
+
+    let ticket_data = match parsed_pdf_resp {
+        Ok(data) => data,
+        Err(e) => {
+            metrics::counter!("uz_bot_ticket_parse_failures_total", "error" => e.kind())
+                .increment(1);
+            admin.record_parse_failure(user, e.kind(), e.to_string(), kyiv_time());
+            let user_error_message = match e {
+                pdf_parser::ParsePdfError::PdfExtractError(err) => {
+                    trace!(%err, "parsing departure data in telegram receiver");
+                    EXTRACT_TICKET_ERROR_MESSAGE
+                }
+                err => {
+                    error!(%err, "parsing departure data in telegram receiver");
+                    LAYOUT_CHANGED_MESSAGE
                 }
             };
 
-            if let Err(e) = db.insert_ticket_data(user, ticket_data.clone()) {
-                error!(%e,"inserting to db");
-                return Err("Database Error.".to_owned());
+            return Err(user_error_message.to_owned());
+        }
+    };
+
+    Ok(ticket_data)
+}
+
+/// Both ticket sources, pdf and `/add` conversation, end up here.
+fn insert_ticket(
+    db: &MyDb,
+    user: ChatId,
+    sender: ChatId,
+    ticket_data: TicketData,
+) -> Result<String, String> {
+    if let Err(e) = db.insert_ticket_data(user, ticket_data.clone()) {
+        error!(%e,"inserting to db");
+        return Err("Database Error.".to_owned());
+    } else {
+        trace!(%user,?ticket_data, "inserted to db");
+        metrics::counter!("uz_bot_tickets_added_total").increment(1);
+    }
+    if let Err(e) = db.set_added_by(user, ticket_data.clone(), sender) {
+        error!(%e,"setting ticket added by");
+    }
+    let route = match (&ticket_data.departure_station, &ticket_data.arrival_station) {
+        (Some(departure), Some(arrival)) => format!("{} → {}\n", departure.name, arrival.name),
+        _ => String::new(),
+    };
+    let message = format!(
+        "Your ticket to train №{train_num}, departing at {depart_at}, is added to monitoring!\n{route}{prediction}\nUse /tickets to share it with your family or friends.",
+        train_num = ticket_data.train_number,
+        depart_at = format_datetime(&ticket_data.departure_datetime, chat_timezone(db, user)),
+        prediction = build_delay_prediction(db, &ticket_data)
+    );
+
+    Ok(message)
+}
+
+/// Usual delay on the departure weekday, from the recorded history of the train.
+/// Days on time are not recorded, so it tells how late the train is when delayed, not how often.
+fn build_delay_prediction(db: &MyDb, ticket: &TicketData) -> String {
+    let weekday = ticket.departure_datetime.weekday();
+    let stats = TrainStats::new(
+        db.delay_history(&ticket.train_number),
+        kyiv_time() - TimeDelta::weeks(STATS_DEFAULT_WEEKS),
+    );
+
+    match stats
+        .weekday(weekday)
+        .filter(|stats| stats.samples >= PREDICTION_MIN_SAMPLES)
+    {
+        Some(stats) => format!(
+            "When delayed on {weekday} over the last {STATS_DEFAULT_WEEKS} weeks ({samples} times), this train was late by {average:.0} min on average, 90% of those delays within {p90} min.",
+            samples = stats.samples,
+            average = stats.average,
+            p90 = stats.p90
+        ),
+        None => format!("Too few delays of this train on {weekday} are known to tell how late it usually is."),
+    }
+}
+
+fn handle_command(
+    db: &MyDb,
+    conversations: &Conversations,
+    user: ChatId,
+    sender: ChatId,
+    command: Command,
+    bot_username: &str,
+) -> Result<Reply, String> {
+    match command.name.as_str() {
+        "add" => Ok(conversations.start(user)),
+        "skip" => {
+            let step = conversations
+                .skip(user)
+                .ok_or_else(|| "Nothing to skip.".to_owned())?;
+            finish_step(db, user, sender, step)
+        }
+        "cancel" if conversations.cancel(user) => {
+            Ok("Adding ticket is cancelled.".to_owned().into())
+        }
+        "cancel" => Err("Nothing to cancel.".to_owned()),
+        _ => handle_ticket_command(db, user, sender, command, bot_username).map(Reply::from),
+    }
+}
+
+fn handle_ticket_command(
+    db: &MyDb,
+    user: ChatId,
+    sender: ChatId,
+    command: Command,
+    bot_username: &str,
+) -> Result<String, String> {
+    match command.name.as_str() {
+        "start" => join_shared_ticket(db, user, &command.args),
+        "tickets" => Ok(list_tickets(db, user)),
+        "share" => share_ticket(db, user, &command.args, bot_username),
+        "remove" => remove_ticket(db, user, sender, &command.args),
+        "track" => toggle_arrival_tracking(db, user, &command.args),
+        "watch" => watch_train(db, user, &command.args),
+        "unwatch" => unwatch_train(db, user, &command.args),
+        "stats" => train_stats(db, &command.args),
+        "live" => set_live_status(db, user, &command.args),
+        "quiet" => set_quiet_hours(db, user, &command.args),
+        "timezone" => set_timezone(db, user, &command.args),
+        "journey" if command.args.is_empty() => Ok(list_journeys(db, user)),
+        "journey" => create_journey(db, user, &command.args),
+        _ => Err(UNKNOWN_COMMAND_MESSAGE.to_owned()),
+    }
+}
+
+/// Ticket visible to a chat: either its own or shared with it by `owner`.
+struct ChatTicket {
+    owner: ChatId,
+    ticket: TicketData,
+}
+
+/// Own and shared tickets of the chat, ordered by departure, so numbers in commands stay stable.
+fn chat_tickets(db: &MyDb, user: ChatId) -> Vec<ChatTicket> {
+    let own = db.retrieve_user_trains(user).map(|ticket| ChatTicket {
+        owner: user,
+        ticket,
+    });
+    let shared = db
+        .subscriptions(user)
+        .map(|(owner, ticket)| ChatTicket { owner, ticket });
+
+    let mut tickets = own.chain(shared).collect::<Vec<_>>();
+    tickets.sort_by(|a, b| {
+        a.ticket
+            .departure_datetime
+            .cmp(&b.ticket.departure_datetime)
+            .then_with(|| a.ticket.train_number.cmp(&b.ticket.train_number))
+    });
+    tickets
+}
+
+fn chat_ticket_by_index(db: &MyDb, user: ChatId, args: &str) -> Result<ChatTicket, String> {
+    let index = args
+        .parse::<usize>()
+        .map_err(|_| TICKET_INDEX_ERROR_MESSAGE.to_owned())?;
+
+    let mut tickets = chat_tickets(db, user);
+    if index == 0 || index > tickets.len() {
+        return Err(TICKET_INDEX_ERROR_MESSAGE.to_owned());
+    }
+    Ok(tickets.swap_remove(index - 1))
+}
+
+fn list_tickets(db: &MyDb, user: ChatId) -> String {
+    let tickets = chat_tickets(db, user);
+    if tickets.is_empty() {
+        return NO_TICKETS_MESSAGE.to_owned();
+    }
+    let zone = chat_timezone(db, user);
+
+    let lines = tickets
+        .iter()
+        .enumerate()
+        .map(|(i, chat_ticket)| {
+            let shared = if chat_ticket.owner == user {
+                ""
+            } else {
+                " (shared with you)"
+            };
+            let tracked = if db
+                .arrival_trackers(chat_ticket.owner, &chat_ticket.ticket)
+                .any(|tracker| tracker == user)
+            {
+                " (tracked until arrival)"
             } else {
-                trace!(%user,?ticket_data, "inserted to db");
+                ""
+            };
+            format!(
+                "{n}. Train №{train_number}, departing at {depart_at}{shared}{tracked}",
+                n = i + 1,
+                train_number = chat_ticket.ticket.train_number,
+                depart_at = format_datetime(&chat_ticket.ticket.departure_datetime, zone)
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    format!("{lines}\n\n/share <n> - invite others to ticket\n/remove <n> - stop tracking ticket\n/track <n> - toggle delay updates until arrival\n/journey <n> <n> - group tickets with a transfer into a journey")
+}
+
+fn share_ticket(db: &MyDb, user: ChatId, args: &str, bot_username: &str) -> Result<String, String> {
+    let ChatTicket { owner, ticket } = chat_ticket_by_index(db, user, args)?;
+    if owner != user {
+        return Err("Only the owner of the ticket can share it.".to_owned());
+    }
+
+    let code = rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(INVITE_CODE_LEN)
+        .map(char::from)
+        .collect::<String>();
+
+    if let Err(e) = db.insert_invite(code.clone(), owner, ticket.clone()) {
+        error!(%e,"inserting invite to db");
+        return Err("Database Error.".to_owned());
+    }
+
+    Ok(format!(
+        "Forward this link to everyone, who should receive notifications about train №{train_number}:\nhttps://t.me/{bot_username}?start={INVITE_PREFIX}{code}\nYou can also add the bot to a group chat and send the ticket there.",
+        train_number = ticket.train_number
+    ))
+}
+
+fn join_shared_ticket(db: &MyDb, user: ChatId, args: &str) -> Result<String, String> {
+    let Some((owner, ticket)) = args
+        .strip_prefix(INVITE_PREFIX)
+        .and_then(|code| db.resolve_invite(code))
+    else {
+        return Err("Invite link is invalid or the ticket is no longer tracked.".to_owned());
+    };
+
+    if owner == user {
+        return Ok("This ticket is already yours.".to_owned());
+    }
+
+    if let Err(e) = db.subscribe(owner, ticket.clone(), user) {
+        error!(%e,"subscribing to ticket");
+        return Err("Invite link is invalid or the ticket is no longer tracked.".to_owned());
+    }
+    trace!(%user, %owner, ?ticket, "subscribed to shared ticket");
+
+    Ok(format!(
+        "You will receive notifications about train №{train_num}, departing at {depart_at}!",
+        train_num = ticket.train_number,
+        depart_at = format_datetime(&ticket.departure_datetime, chat_timezone(db, user))
+    ))
+}
+
+/// In a group chat only the member, who added the ticket, removes it.
+fn remove_ticket(db: &MyDb, user: ChatId, sender: ChatId, args: &str) -> Result<String, String> {
+    let ChatTicket { owner, ticket } = chat_ticket_by_index(db, user, args)?;
+
+    if owner == user {
+        if db
+            .added_by(owner, &ticket)
+            .is_some_and(|member| member != sender)
+        {
+            return Err("Only the chat member, who added the ticket, can remove it.".to_owned());
+        }
+        if let Err(e) = db.remove_user_train(owner, ticket.clone()) {
+            error!(%e,"removing ticket by owner");
+            return Err("Database Error.".to_owned());
+        }
+        for journey in db
+            .user_journeys(owner)
+            .filter(|journey| journey.contains(&ticket))
+        {
+            if let Err(e) = db.remove_journey(owner, &journey) {
+                error!(%e,"removing journey with removed ticket");
             }
-            let message = format!(
-            "Your ticket to train №{train_num}, departing at {depart_at}, is added to monitoring!",
-            train_num = ticket_data.train_number,
-            depart_at = ticket_data.departure_datetime
+        }
+        Ok(format!(
+            "Ticket to train №{train_num} is removed from monitoring for everyone it was shared with.",
+            train_num = ticket.train_number
+        ))
+    } else {
+        if let Err(e) = db.unsubscribe(owner, ticket.clone(), user) {
+            error!(%e,"unsubscribing from ticket");
+            return Err("Database Error.".to_owned());
+        }
+        Ok(format!(
+            "You will no longer receive notifications about train №{train_num}. Only the owner can remove the ticket itself.",
+            train_num = ticket.train_number
+        ))
+    }
+}
+
+fn toggle_arrival_tracking(db: &MyDb, user: ChatId, args: &str) -> Result<String, String> {
+    let ChatTicket { owner, ticket } = chat_ticket_by_index(db, user, args)?;
+    let Some(arrival_datetime) = ticket.arrival_datetime else {
+        return Err("Arrival time of this ticket is unknown.".to_owned());
+    };
+
+    let tracked = db
+        .arrival_trackers(owner, &ticket)
+        .any(|tracker| tracker == user);
+    let result = if tracked {
+        db.untrack_arrival(owner, ticket.clone(), user)
+    } else {
+        db.track_arrival(owner, ticket.clone(), user)
+    };
+    if let Err(e) = result {
+        error!(%e,"toggling arrival tracking");
+        return Err("Database Error.".to_owned());
+    }
+
+    if tracked {
+        Ok(format!(
+            "Train №{train_num} is no longer tracked after departure.",
+            train_num = ticket.train_number
+        ))
+    } else {
+        Ok(format!(
+            "You will receive delay updates for train №{train_num} until its arrival at {arrive_at}.",
+            train_num = ticket.train_number,
+            arrive_at = format_datetime(&arrival_datetime, chat_timezone(db, user))
+        ))
+    }
+}
+
+/// Replies with current delays of chat's tickets, or of a train number from `args`.
+async fn train_status(
+    db: &MyDb,
+    delays: &SharedDelays,
+    user: ChatId,
+    args: &str,
+) -> Result<String, String> {
+    let tickets = chat_tickets(db, user);
+    if args.is_empty() && tickets.is_empty() {
+        return Err(NO_TICKETS_MESSAGE.to_owned());
+    }
+
+    let fetched = match delays.get(STATUS_MAX_AGE).await {
+        Ok(fetched) => fetched,
+        Err(e) => {
+            warn!(%e,"delayed trains for status");
+            return Err(DELAYS_UNAVAILABLE_MESSAGE.to_owned());
+        }
+    };
+
+    let zone = chat_timezone(db, user);
+    if !args.is_empty() {
+        let train_number = parse_train_number_arg(args)?;
+        let message = build_watch_message(
+            &train_number,
+            find_delayed_train(&fetched.trains.0, &train_number),
         );
+        return Ok(format!("{message}\n\n{}", as_of(&fetched, zone)));
+    }
+
+    let now = kyiv_time();
+    let lines = tickets
+        .iter()
+        .map(|chat_ticket| {
+            let ticket = &chat_ticket.ticket;
+            let delay_match = match_delayed_train(&fetched.trains.0, ticket, now);
+            build_status_line(ticket, delay_match, zone)
+        })
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    Ok(format!("{lines}\n\n{}", as_of(&fetched, zone)))
+}
+
+fn watch_train(db: &MyDb, user: ChatId, args: &str) -> Result<String, String> {
+    let mut args = args.split_whitespace();
+    let Some(train_number) = args.next() else {
+        return Err("Usage: /watch <train number> [hours]".to_owned());
+    };
+    let train_number = parse_train_number_arg(train_number)?;
+    let duration = match args.next() {
+        Some(hours) => hours
+            .parse::<i64>()
+            .ok()
+            .map(TimeDelta::hours)
+            .filter(|duration| *duration > TimeDelta::zero() && *duration <= WATCH_MAX_DURATION)
+            .ok_or_else(|| {
+                format!(
+                    "Hours must be a number from 1 to {}.",
+                    WATCH_MAX_DURATION.num_hours()
+                )
+            })?,
+        None => WATCH_DEFAULT_DURATION,
+    };
+
+    let watch = Watch {
+        train_number,
+        until: kyiv_time() + duration,
+    };
+    if let Err(e) = db.insert_watch(user, watch.clone()) {
+        error!(%e,"inserting watch to db");
+        return Err("Database Error.".to_owned());
+    }
+
+    Ok(format!(
+        "Watching train №{train_number} for {hours} hours, you will be notified when its delay changes.\n/unwatch {train_number} - stop watching",
+        train_number = watch.train_number,
+        hours = duration.num_hours()
+    ))
+}
+
+/// Without arguments stops watching all trains.
+fn unwatch_train(db: &MyDb, user: ChatId, args: &str) -> Result<String, String> {
+    let train_number = match args.is_empty() {
+        true => None,
+        false => Some(parse_train_number_arg(args)?),
+    };
+    let watches = db
+        .user_watches(user)
+        .filter(|watch| {
+            train_number
+                .as_ref()
+                .map(|train_number| watch.train_number.matches(train_number))
+                .unwrap_or(true)
+        })
+        .collect::<Vec<_>>();
+    if watches.is_empty() {
+        return Err("You are not watching this train.".to_owned());
+    }
 
-            Ok(message)
+    for watch in watches.iter() {
+        if let Err(e) = db.remove_watch(user, watch) {
+            error!(%e,"removing watch from db");
+            return Err("Database Error.".to_owned());
         }
-    })
-    .await;
+    }
+
+    let train_numbers = watches
+        .iter()
+        .map(|watch| format!("№{}", watch.train_number))
+        .collect::<Vec<_>>()
+        .join(", ");
+    Ok(format!("Stopped watching train {train_numbers}."))
 }
 
-pub fn build_train_notification_message(
-    user_ticket: TicketData,
+/// `/live on`, `/live pin` or `/live off`, without argument tells the current mode.
+fn set_live_status(db: &MyDb, user: ChatId, args: &str) -> Result<String, String> {
+    let mut settings = db.user_settings(user);
+    settings.live_status = match args {
+        "" => return Ok(describe_live_status(settings.live_status).to_owned()),
+        "on" => LiveStatus::On,
+        "pin" => LiveStatus::Pinned,
+        "off" => LiveStatus::Off,
+        _ => return Err(LIVE_USAGE_MESSAGE.to_owned()),
+    };
+    if let Err(e) = db.update_settings(user, settings.clone()) {
+        error!(%e, "updating settings");
+        return Err("Database Error.".to_owned());
+    }
+
+    Ok(describe_live_status(settings.live_status).to_owned())
+}
+
+fn describe_live_status(live_status: LiveStatus) -> &'static str {
+    match live_status {
+        LiveStatus::Off => "Reminders come as separate messages. Use /live on to get one status message per ticket, updated until departure.",
+        LiveStatus::On => "Each ticket gets one status message, updated until departure. A short message still comes at every reminder time and when the delay changes a lot. Use /live off to get separate reminders.",
+        LiveStatus::Pinned => "Each ticket gets one pinned status message, updated until departure. A short message still comes at every reminder time and when the delay changes a lot. Use /live off to get separate reminders.",
+    }
+}
+
+/// `/quiet 23:00-07:00 [mode]` or `/quiet off`, without argument tells the current quiet hours.
+fn set_quiet_hours(db: &MyDb, user: ChatId, args: &str) -> Result<String, String> {
+    let mut settings = db.user_settings(user);
+    settings.quiet_hours = match args {
+        "" => {
+            return Ok(describe_quiet_hours(
+                settings.quiet_hours,
+                chat_timezone(db, user),
+            ))
+        }
+        "off" => None,
+        args => Some(args.parse::<QuietHours>()?),
+    };
+    if let Err(e) = db.update_settings(user, settings.clone()) {
+        error!(%e, "updating settings");
+        return Err("Database Error.".to_owned());
+    }
+
+    Ok(describe_quiet_hours(
+        settings.quiet_hours,
+        chat_timezone(db, user),
+    ))
+}
+
+fn describe_quiet_hours(quiet_hours: Option<QuietHours>, zone: Tz) -> String {
+    match quiet_hours {
+        Some(quiet_hours) => format!(
+            "Quiet hours, by {zone}, are {quiet_hours}. Alerts about long delays and missed connections still come with sound. Use /quiet off to disable them.",
+            zone = zone_name(zone)
+        ),
+        None => "No quiet hours. Use /quiet 23:00-07:00 to get reminders silently at night, add suppress to skip them or collapse to get them in one message in the morning.".to_owned(),
+    }
+}
+
+/// `/timezone Europe/Warsaw`, without argument tells the current zone.
+fn set_timezone(db: &MyDb, user: ChatId, args: &str) -> Result<String, String> {
+    let mut settings = db.user_settings(user);
+    if !args.is_empty() {
+        let zone = args
+            .parse::<Tz>()
+            .map_err(|_| TIMEZONE_USAGE_MESSAGE.to_owned())?;
+        settings.timezone = Some(zone);
+        if let Err(e) = db.update_settings(user, settings.clone()) {
+            error!(%e, "updating settings");
+            return Err("Database Error.".to_owned());
+        }
+    }
+
+    let zone = settings.timezone.unwrap_or(Kyiv);
+    Ok(format!(
+        "Times are shown like {now}. Use /timezone <Area/City> to change the time zone.",
+        now = format_datetime(&kyiv_time(), zone)
+    ))
+}
+
+fn parse_train_number_arg(arg: &str) -> Result<TrainNumber, String> {
+    arg.parse()
+        .map_err(|_| TRAIN_NUMBER_ERROR_MESSAGE.to_owned())
+}
+
+fn train_stats(db: &MyDb, args: &str) -> Result<String, String> {
+    let mut args = args.split_whitespace();
+    let Some(train_number) = args.next() else {
+        return Err("Usage: /stats <train number> [weeks]".to_owned());
+    };
+    let train_number = parse_train_number_arg(train_number)?;
+    let weeks = match args.next() {
+        Some(weeks) => weeks
+            .parse::<i64>()
+            .ok()
+            .filter(|weeks| *weeks > 0 && TimeDelta::weeks(*weeks) <= DELAY_HISTORY_RETENTION)
+            .ok_or_else(|| {
+                format!(
+                    "Weeks must be a number from 1 to {}.",
+                    DELAY_HISTORY_RETENTION.num_weeks()
+                )
+            })?,
+        None => STATS_DEFAULT_WEEKS,
+    };
+
+    let stats = TrainStats::new(
+        db.delay_history(&train_number),
+        kyiv_time() - TimeDelta::weeks(weeks),
+    );
+    let Some(overall) = &stats.overall else {
+        return Ok(format!(
+            "Train №{train_number} was not delayed over the last {weeks} weeks, or it is not known yet."
+        ));
+    };
+
+    let by_weekday = stats
+        .by_weekday
+        .iter()
+        .map(|(weekday, stats)| format!("{weekday}: {}", describe_delay_stats(stats)))
+        .collect::<Vec<_>>()
+        .join("\n");
+    let by_route = stats
+        .by_route
+        .iter()
+        .map(|(route, stats)| format!("{route}: {}", describe_delay_stats(stats)))
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    Ok(format!(
+        "Train №{train_number} when delayed, over the last {weeks} weeks: {overall}\n\nWhen delayed, by weekday of departure:\n{by_weekday}\n\nWhen delayed, by route:\n{by_route}\n\nOnly days when the train was listed as delayed are counted, days on time are not known.",
+        overall = describe_delay_stats(overall)
+    ))
+}
+
+fn describe_delay_stats(stats: &DelayStats) -> String {
+    format!(
+        "average {average:.0} min, median {median} min, 90% within {p90} min ({samples} delayed days)",
+        average = stats.average,
+        median = stats.median,
+        p90 = stats.p90,
+        samples = stats.samples
+    )
+}
+
+fn create_journey(db: &MyDb, user: ChatId, args: &str) -> Result<String, String> {
+    let mut legs = vec![];
+    for index in args.split_whitespace() {
+        let ChatTicket { owner, ticket } = chat_ticket_by_index(db, user, index)?;
+        if owner != user {
+            return Err("Only your own tickets can be grouped into a journey.".to_owned());
+        }
+        legs.push(ticket);
+    }
+
+    let journey = Journey::new(legs).map_err(|e| e.to_string())?;
+
+    if let Err(e) = db.insert_journey(user, journey.clone()) {
+        error!(%e,"inserting journey to db");
+        return Err("Database Error.".to_owned());
+    }
+    trace!(%user, ?journey, "journey inserted to db");
+
+    Ok(format!(
+        "Journey is added! You will be warned if a delay puts your connection at risk.\n{}",
+        describe_journey(&journey)
+    ))
+}
+
+fn list_journeys(db: &MyDb, user: ChatId) -> String {
+    let journeys = db.user_journeys(user).collect::<Vec<_>>();
+    if journeys.is_empty() {
+        return NO_JOURNEYS_MESSAGE.to_owned();
+    }
+
+    journeys
+        .iter()
+        .map(describe_journey)
+        .collect::<Vec<_>>()
+        .join("\n\n")
+}
+
+fn describe_journey(journey: &Journey) -> String {
+    let legs = journey
+        .legs()
+        .iter()
+        .map(|leg| format!("№{}", leg.train_number))
+        .collect::<Vec<_>>()
+        .join(" → ");
+    let transfers = journey
+        .transfers()
+        .map(|transfer| {
+            format!(
+                "Transfer from №{arriving} to №{departing}: {slack} minutes",
+                arriving = transfer.arriving.train_number,
+                departing = transfer.departing.train_number,
+                slack = transfer.slack.num_minutes()
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    format!("{legs}\n{transfers}")
+}
+
+/// Delays page may be cached, so tell how fresh it is, e.g. `As of 14:32 Kyiv time.`
+fn as_of(snapshot: &DelaysSnapshot, zone: Tz) -> String {
+    format!("As of {}.", format_time(&snapshot.fetched_at, zone))
+}
+
+fn build_status_line(ticket: &TicketData, delay_match: Option<DelayMatch>, zone: Tz) -> String {
+    let departure = ticket.departure_datetime;
+    let Some(DelayMatch {
+        delayed_train,
+        confidence,
+    }) = delay_match
+    else {
+        return format!(
+            "№{train_number} departing {departure}: no delays.",
+            train_number = ticket.train_number,
+            departure = format_datetime(&departure, zone)
+        );
+    };
+
+    let delay_minutes = delayed_train.delay.total_minutes();
+    let probable_departure = departure
+        .checked_add_signed(Duration::minutes(delay_minutes as i64))
+        .expect("must not overflow");
+    let line = format!(
+        "№{train_number} departing {departure}: delayed by {delay_minutes} minutes, probable departure at {probable_departure}.",
+        train_number = ticket.train_number,
+        departure = format_datetime(&departure, zone),
+        probable_departure = format_time(&probable_departure, zone)
+    );
+    match confidence_note(confidence) {
+        Some(note) => format!("{line} {note}"),
+        None => line,
+    }
+}
+
+pub fn build_watch_message(
+    train_number: &TrainNumber,
     delayed_train: Option<&DelayedTrain>,
 ) -> String {
     if let Some(delayed_train) = delayed_train {
-        let delay_minutes = delayed_train.delay.hr * 60 + delayed_train.delay.min;
+        format!(
+            "Train №{train_number} {train_direction} is delayed by {delay_minutes} minutes.",
+            train_direction = delayed_train.direction,
+            delay_minutes = delayed_train.delay.total_minutes()
+        )
+    } else {
+        format!("No delays found for train №{train_number}!")
+    }
+}
+
+pub fn build_connection_at_risk_message(
+    transfer: &Transfer,
+    delayed_train: &DelayedTrain,
+    remaining_buffer: TimeDelta,
+) -> String {
+    let remaining_minutes = remaining_buffer.num_minutes();
+    let outlook = if remaining_minutes < 0 {
+        format!(
+            "You will probably miss the connection by {} minutes.",
+            -remaining_minutes
+        )
+    } else {
+        format!("Only {remaining_minutes} minutes remain for the transfer.")
+    };
+
+    format!(
+        "Connection at risk!\nYour train №{arriving} {train_direction} is delayed by {delay_minutes} minutes, while the transfer to train №{departing} was {slack} minutes.\n{outlook}",
+        arriving = transfer.arriving.train_number,
+        train_direction = delayed_train.direction,
+        delay_minutes = delayed_train.delay.total_minutes(),
+        departing = transfer.departing.train_number,
+        slack = transfer.slack.num_minutes(),
+    )
+}
+
+/// Sent as soon as the train is listed with a major or severe delay, not at the next reminder.
+pub fn build_disruption_alert_message(
+    user_ticket: &TicketData,
+    delayed_train: &DelayedTrain,
+    severity: DelaySeverity,
+    refund_delay_minutes: usize,
+    zone: Tz,
+) -> String {
+    let headline = match severity {
+        DelaySeverity::Severe => "Severe delay!",
+        DelaySeverity::Major | DelaySeverity::Minor => "Major delay!",
+    };
+    let delay_minutes = delayed_train.delay.total_minutes();
+    let probable_departure = user_ticket
+        .departure_datetime
+        .checked_add_signed(Duration::minutes(delay_minutes as i64))
+        .expect("must not overflow");
+    let advice = if delay_minutes >= refund_delay_minutes {
+        "With a delay this long you may be eligible for a full refund of an unused ticket, check at a ticket office or in the UZ app before the trip."
+    } else {
+        "Plan for a later departure, you will be alerted if the delay grows."
+    };
+
+    format!(
+        "{headline}\nYour train №{train_number} {train_direction}, departing {departure}, is delayed by {delay}.\nProbable departure at {probable_departure}.\n{advice}",
+        train_number = user_ticket.train_number,
+        train_direction = delayed_train.direction,
+        departure = format_datetime(&user_ticket.departure_datetime, zone),
+        delay = describe_delay(&delayed_train.delay),
+        probable_departure = format_time(&probable_departure, zone)
+    )
+}
+
+/// Long delays read better in hours, e.g. `3 h 20 min`.
+fn describe_delay(delay: &TrainDelayTime) -> String {
+    match (delay.hr, delay.min) {
+        (0, min) => format!("{min} min"),
+        (hr, 0) => format!("{hr} h"),
+        (hr, min) => format!("{hr} h {min} min"),
+    }
+}
+
+pub fn build_arrival_notification_message(
+    user_ticket: &TicketData,
+    arrival_datetime: DateTime<Tz>,
+    delayed_train: Option<&DelayedTrain>,
+    zone: Tz,
+) -> String {
+    if let Some(delayed_train) = delayed_train {
+        let delay_minutes = delayed_train.delay.total_minutes();
+
+        let train_probable_arrival_time = arrival_datetime
+            .checked_add_signed(Duration::minutes(delay_minutes as i64))
+            .expect("must not overflow");
+        format!("Train №{train_number} {train_direction} is running {delay_minutes} minutes late.\nExpected arrival time is {arrival}.",train_number=user_ticket.train_number,train_direction=delayed_train.direction,arrival=format_time(&train_probable_arrival_time, zone))
+    } else {
+        format!(
+            "Train №{train_number} is running on time.\nExpected arrival time is {arrival}.",
+            train_number = user_ticket.train_number,
+            arrival = format_time(&arrival_datetime, zone)
+        )
+    }
+}
+
+pub fn build_delays_unavailable_reminder_message(user_ticket: &TicketData, zone: Tz) -> String {
+    format!("Your train №{train_number} departs at {departure}.\nDelay information is currently unavailable, you will get an update if it appears before departure.",train_number=user_ticket.train_number,departure=format_time(&user_ticket.departure_datetime, zone))
+}
+
+pub fn build_train_notification_message(
+    user_ticket: TicketData,
+    delay_match: Option<DelayMatch>,
+    zone: Tz,
+) -> String {
+    if let Some(DelayMatch {
+        delayed_train,
+        confidence,
+    }) = delay_match
+    {
+        let delay_minutes = delayed_train.delay.total_minutes();
 
         let train_probable_departure_time = user_ticket
             .departure_datetime
             .checked_add_signed(Duration::minutes(delay_minutes as i64))
-            .expect("must not overflow")
-            .time();
-        format!("Your train №{train_number} {train_direction} is delayed by {delay_minutes} minutes.\nProbable arrival time is {arrival_hr:0>2}:{arrival_min:0>2} Kyiv time.",train_number=user_ticket.train_number,train_direction=delayed_train.direction,arrival_hr=train_probable_departure_time.hour(),arrival_min=train_probable_departure_time.minute())
+            .expect("must not overflow");
+        let message = format!("Your train №{train_number} {train_direction} is delayed by {delay_minutes} minutes.\nProbable arrival time is {arrival}.",train_number=user_ticket.train_number,train_direction=delayed_train.direction,arrival=format_time(&train_probable_departure_time, zone));
+        match confidence_note(confidence) {
+            Some(note) => format!("{message}\n{note}"),
+            None => message,
+        }
     } else {
-        let train_probable_departure_time = user_ticket.departure_datetime.time();
+        format!(
+            "No delays found for your train №{train_number}!\nArrival time is {arrival}.",
+            train_number = user_ticket.train_number,
+            arrival = format_time(&user_ticket.departure_datetime, zone)
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+    use chrono_tz::Europe::Kyiv;
+    use ukrzaliznytsia_parser::{DelaySeverity, DelayedTrains};
+
+    use super::{build_disruption_alert_message, describe_delay};
+    use crate::test_util::ticket;
+
+    #[test]
+    fn test_disruption_alert_message() {
+        let delays: DelayedTrains = r#"<html><body><ul class="delayform-list">
+            <li>№43/44 Київ-Пас.-Івано-Франківськ (+1:00)</li>
+            <li>№91/92 Київ-Пас.-Львів (+3:20)</li>
+        </ul></body></html>"#
+            .parse()
+            .unwrap();
+        let departure = Kyiv.with_ymd_and_hms(2024, 4, 9, 20, 36, 0).unwrap();
+
+        let major = build_disruption_alert_message(
+            &ticket("043К", departure),
+            &delays.0[0],
+            DelaySeverity::Major,
+            180,
+            Kyiv,
+        );
+        assert_eq!(
+            major,
+            "Major delay!\nYour train №043К Київ-Пас.-Івано-Франківськ, departing Tue 9 Apr, 20:36 Kyiv time, is delayed by 1 h.\nProbable departure at 21:36 Kyiv time.\nPlan for a later departure, you will be alerted if the delay grows."
+        );
+
+        let severe = build_disruption_alert_message(
+            &ticket("091", departure),
+            &delays.0[1],
+            DelaySeverity::Severe,
+            180,
+            Kyiv,
+        );
+        assert!(severe.starts_with("Severe delay!\n"), "{severe}");
+        assert!(severe.contains("is delayed by 3 h 20 min."), "{severe}");
+        assert!(severe.contains("Probable departure at 23:56"), "{severe}");
+        assert!(severe.contains("full refund"), "{severe}");
 
-        format!("No delays found for your train №{train_number}!\nArrival time is {arrival_hr:0>2}:{arrival_min:0>2} Kyiv time.",train_number=user_ticket.train_number,arrival_hr=train_probable_departure_time.hour(),arrival_min=train_probable_departure_time.minute())
+        assert_eq!(describe_delay(&"0:45".parse().unwrap()), "45 min");
     }
 }
@@ -0,0 +1,16 @@
+use chrono::DateTime;
+use chrono_tz::Tz;
+use train_number::TrainNumber;
+
+/// Subscription to delays of a train number, without owning a ticket.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Watch {
+    pub train_number: TrainNumber,
+    pub until: DateTime<Tz>,
+}
+
+impl Watch {
+    pub fn is_expired(&self, now: DateTime<Tz>) -> bool {
+        self.until < now
+    }
+}
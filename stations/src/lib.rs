@@ -0,0 +1,157 @@
+mod search;
+mod translit;
+
+use std::sync::OnceLock;
+
+pub use search::search;
+pub use translit::transliterate;
+
+/// Stations bundled with the bot, see `stations.tsv`.
+const STATIONS_TSV: &str = include_str!("../stations.tsv");
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct Station {
+    /// Full name, as printed on tickets, e.g. `Київ-Пасажирський`.
+    pub name: String,
+    /// Abbreviated names, e.g. `Київ-Пас.`.
+    pub short_names: Vec<String>,
+    /// Names in Latin script other than the transliteration, e.g. `Wien Hbf`.
+    pub latin_names: Vec<String>,
+}
+
+impl Station {
+    /// Transliterated full name, e.g. `Kyiv-Pasazhyrskyi`.
+    pub fn latin_name(&self) -> String {
+        transliterate(&self.name)
+    }
+
+    fn names(&self) -> impl Iterator<Item = &str> {
+        std::iter::once(self.name.as_str())
+            .chain(self.short_names.iter().map(String::as_str))
+            .chain(self.latin_names.iter().map(String::as_str))
+    }
+}
+
+pub fn all() -> &'static [Station] {
+    static STATIONS: OnceLock<Vec<Station>> = OnceLock::new();
+    STATIONS.get_or_init(|| {
+        STATIONS_TSV
+            .lines()
+            .filter(|line| !line.trim().is_empty() && !line.starts_with('#'))
+            .map(|line| {
+                let mut columns = line.split('\t').map(str::trim);
+                let mut column = || columns.next().unwrap_or_default();
+                let list = |column: &str| {
+                    column
+                        .split(',')
+                        .map(str::trim)
+                        .filter(|name| !name.is_empty())
+                        .map(str::to_owned)
+                        .collect()
+                };
+
+                Station {
+                    name: column().to_owned(),
+                    short_names: list(column()),
+                    latin_names: list(column()),
+                }
+            })
+            .collect()
+    })
+}
+
+/// Finds a station by any of its names in Cyrillic or Latin script,
+/// ignoring case, punctuation and diacritics. See [`search`] for misspelled names.
+pub fn find(name: &str) -> Option<&'static Station> {
+    let name = search_key(name);
+    all()
+        .iter()
+        .find(|station| station.names().any(|known| search_key(known) == name))
+}
+
+/// Names are equal ignoring case, punctuation and diacritics, e.g. `ЛЬВІВ` and `Львів`.
+/// Unlike [`find`] works for stations missing in the directory.
+pub fn same_name(a: &str, b: &str) -> bool {
+    search_key(a) == search_key(b)
+}
+
+/// Splits `origin-destination` text, where names may contain hyphens themselves,
+/// at the hyphen that leaves the most known stations on both sides.
+/// Returns `None` when no split has a known station and the text has several hyphens.
+pub fn split_route(route: &str) -> Option<(&str, &str)> {
+    let splits = route
+        .match_indices('-')
+        .map(|(index, _)| (&route[..index], &route[index + 1..]))
+        .filter(|(origin, destination)| !origin.is_empty() && !destination.is_empty())
+        .collect::<Vec<_>>();
+
+    let known = |(origin, destination): &(&str, &str)| {
+        find(origin).is_some() as usize + find(destination).is_some() as usize
+    };
+    // `min_by_key` keeps the first of equally good splits
+    let best = splits
+        .iter()
+        .min_by_key(|split| std::cmp::Reverse(known(split)))?;
+
+    (known(best) > 0 || splits.len() == 1).then_some(*best)
+}
+
+/// Lowercase Latin words of the name, so that `Київ-Пас.` and `kyiv pas` are equal.
+fn search_key(name: &str) -> String {
+    transliterate(&name.to_lowercase())
+        .chars()
+        .map(translit::strip_diacritics)
+        .map(|c| if c.is_alphanumeric() { c } else { ' ' })
+        .collect::<String>()
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{find, same_name, split_route};
+
+    #[test]
+    fn test_find_station() {
+        let kyiv = find("КИЇВ-ПАСАЖИРСЬКИЙ").unwrap();
+        assert_eq!(kyiv.name, "Київ-Пасажирський");
+        assert_eq!(find("Київ-Пас."), Some(kyiv));
+        assert_eq!(find("Запоріжжя 1").unwrap().name, "Запоріжжя-1");
+        assert_eq!(find("Слов’янськ").unwrap().name, "Слов'янськ");
+        assert_eq!(find("Атлантида"), None);
+
+        assert_eq!(find("kyiv pasazhyrskyi"), Some(kyiv));
+        assert_eq!(find("KIEV"), Some(kyiv));
+        assert_eq!(find("Przemysl Glowny").unwrap().name, "Пшемисль Головний");
+        assert_eq!(kyiv.latin_name(), "Kyiv-Pasazhyrskyi");
+
+        assert!(same_name("ІВАНО-ФРАНКІВСЬК-2", "Івано-Франківськ 2"));
+        assert!(!same_name("Київ-Дарниця", "Київ-Пас."));
+    }
+
+    #[test]
+    fn test_split_route() {
+        let routes = [
+            (
+                "Київ-Пас.-Відень Головний",
+                Some(("Київ-Пас.", "Відень Головний")),
+            ),
+            (
+                "Пшемисль Головний-Київ-Пас.",
+                Some(("Пшемисль Головний", "Київ-Пас.")),
+            ),
+            (
+                "Київ-Пас.-Івано-Франківськ",
+                Some(("Київ-Пас.", "Івано-Франківськ")),
+            ),
+            ("Кривий Ріг-Гол.-Львів", Some(("Кривий Ріг-Гол.", "Львів"))),
+            ("Атлантида-Лемурія", Some(("Атлантида", "Лемурія"))),
+            ("Атлантида-Нова-Лемурія", None),
+            ("Київ", None),
+        ];
+        for (route, expected) in routes {
+            assert_eq!(split_route(route), expected, "{route}");
+        }
+    }
+}
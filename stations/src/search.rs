@@ -0,0 +1,99 @@
+use crate::{all, search_key, Station};
+
+/// Stations best matching a possibly misspelled name in Cyrillic or Latin script,
+/// e.g. `Ivano-Frankivsk`, `франківськ` or `Zaporizhia`. Best match goes first.
+pub fn search(query: &str, limit: usize) -> Vec<&'static Station> {
+    let query = search_key(query);
+    if query.is_empty() {
+        return vec![];
+    }
+
+    let mut scored = all()
+        .iter()
+        .filter_map(|station| {
+            let score = station
+                .names()
+                .filter_map(|name| score(&query, &search_key(name)))
+                .min()?;
+            Some((score, station))
+        })
+        .collect::<Vec<_>>();
+    scored.sort_by(|(a_score, a), (b_score, b)| a_score.cmp(b_score).then(a.name.cmp(&b.name)));
+
+    scored
+        .into_iter()
+        .take(limit)
+        .map(|(_, station)| station)
+        .collect()
+}
+
+/// Lower is better, `None` for names too different from the query.
+fn score(query: &str, name: &str) -> Option<usize> {
+    if query == name {
+        return Some(0);
+    }
+    let query_len = query.chars().count();
+    // words of a name can be typed partially, e.g. `франків` for `Івано-Франківськ`
+    if query_len >= 3 && name.split(' ').any(|word| word.starts_with(query)) {
+        return Some(1);
+    }
+
+    let max_distance = (query_len / 4).max(1);
+    let distance = std::iter::once(name)
+        .chain(name.split(' '))
+        .map(|candidate| levenshtein(query, candidate))
+        .min()
+        .unwrap_or(usize::MAX);
+    (distance <= max_distance).then_some(1 + distance)
+}
+
+fn levenshtein(a: &str, b: &str) -> usize {
+    let b = b.chars().collect::<Vec<_>>();
+    let mut previous = (0..=b.len()).collect::<Vec<_>>();
+
+    for (i, a_char) in a.chars().enumerate() {
+        let mut current = vec![i + 1; b.len() + 1];
+        for (j, b_char) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(a_char != *b_char);
+            current[j + 1] = substitution.min(previous[j + 1] + 1).min(current[j] + 1);
+        }
+        previous = current;
+    }
+
+    previous[b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{levenshtein, search};
+
+    #[test]
+    fn test_levenshtein() {
+        assert_eq!(levenshtein("kyiv", "kyiv"), 0);
+        assert_eq!(levenshtein("kyiv", "kiev"), 2);
+        assert_eq!(levenshtein("lviv", "lvov"), 1);
+        assert_eq!(levenshtein("", "odesa"), 5);
+    }
+
+    #[test]
+    fn test_search() {
+        let first = |query| {
+            search(query, 3)
+                .first()
+                .map(|station| station.name.as_str())
+        };
+
+        assert_eq!(first("Ivano-Frankivsk"), Some("Івано-Франківськ"));
+        assert_eq!(first("франків"), Some("Івано-Франківськ"));
+        assert_eq!(first("Zaporizhia"), Some("Запоріжжя-1"));
+        assert_eq!(first("Odesa"), Some("Одеса-Головна"));
+        assert_eq!(first("Ужгород"), Some("Ужгород"));
+        assert_eq!(first("Wien"), Some("Відень Головний"));
+        assert_eq!(first("Атлантида"), None);
+        assert!(search("", 3).is_empty());
+
+        let kyiv = search("Київ", 3);
+        assert_eq!(kyiv[0].name, "Київ-Пасажирський");
+        assert!(kyiv.iter().any(|station| station.name == "Київ-Дарниця"));
+    }
+}
@@ -0,0 +1,127 @@
+/// Ukrainian to Latin transliteration by the official 2010 rules, e.g. `Київ-Пасажирський`
+/// becomes `Kyiv-Pasazhyrskyi`. Letters of other alphabets are kept as is.
+pub fn transliterate(text: &str) -> String {
+    let chars = text.chars().collect::<Vec<_>>();
+    let mut result = String::with_capacity(text.len());
+
+    for (index, &letter) in chars.iter().enumerate() {
+        let lower = letter.to_lowercase().next().unwrap_or(letter);
+        // apostrophe is inside a word, e.g. `Слов'янськ` is `Sloviansk`
+        let word_start =
+            index == 0 || !(chars[index - 1].is_alphabetic() || is_apostrophe(chars[index - 1]));
+        let after_z = index > 0 && matches!(chars[index - 1], 'з' | 'З');
+
+        let latin = match lower {
+            'г' if after_z => "gh",
+            'є' if word_start => "ye",
+            'ї' if word_start => "yi",
+            'й' if word_start => "y",
+            'ю' if word_start => "yu",
+            'я' if word_start => "ya",
+            'є' => "ie",
+            'ї' | 'й' => "i",
+            'ю' => "iu",
+            'я' => "ia",
+            lower => match latin_letter(lower) {
+                Some(latin) => latin,
+                None => {
+                    result.push(letter);
+                    continue;
+                }
+            },
+        };
+
+        if letter.is_uppercase() {
+            let mut latin = latin.chars();
+            result.extend(latin.next().map(|first| first.to_ascii_uppercase()));
+            result.extend(latin);
+        } else {
+            result.push_str(latin);
+        }
+    }
+
+    result
+}
+
+/// Lowercase letters, that don't depend on position. Russian letters are accepted as users type them.
+fn latin_letter(letter: char) -> Option<&'static str> {
+    let latin = match letter {
+        'а' => "a",
+        'б' => "b",
+        'в' => "v",
+        'г' => "h",
+        'ґ' => "g",
+        'д' => "d",
+        'е' | 'э' => "e",
+        'ж' => "zh",
+        'з' => "z",
+        'и' | 'ы' => "y",
+        'і' => "i",
+        'к' => "k",
+        'л' => "l",
+        'м' => "m",
+        'н' => "n",
+        'о' => "o",
+        'п' => "p",
+        'р' => "r",
+        'с' => "s",
+        'т' => "t",
+        'у' => "u",
+        'ф' => "f",
+        'х' => "kh",
+        'ц' => "ts",
+        'ч' => "ch",
+        'ш' => "sh",
+        'щ' => "shch",
+        'ё' => "yo",
+        'ь' | 'ъ' => "",
+        letter if is_apostrophe(letter) => "",
+        _ => return None,
+    };
+    Some(latin)
+}
+
+fn is_apostrophe(letter: char) -> bool {
+    matches!(letter, '\'' | '’' | 'ʼ')
+}
+
+/// Latin letters with diacritics of neighbouring countries, as users often type them without.
+pub(crate) fn strip_diacritics(letter: char) -> char {
+    match letter {
+        'ą' | 'á' | 'ă' | 'â' | 'ä' => 'a',
+        'ć' | 'č' => 'c',
+        'ę' | 'é' | 'ě' => 'e',
+        'í' | 'î' => 'i',
+        'ł' => 'l',
+        'ń' | 'ň' => 'n',
+        'ó' | 'ö' | 'ő' => 'o',
+        'ś' | 'š' | 'ș' => 's',
+        'ț' | 'ť' => 't',
+        'ü' | 'ú' | 'ű' => 'u',
+        'ź' | 'ż' | 'ž' => 'z',
+        letter => letter,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::transliterate;
+
+    #[test]
+    fn test_transliterate() {
+        let names = [
+            ("Київ-Пасажирський", "Kyiv-Pasazhyrskyi"),
+            ("Запоріжжя-1", "Zaporizhzhia-1"),
+            ("Слов'янськ", "Sloviansk"),
+            ("Згурівка", "Zghurivka"),
+            ("Єнакієве", "Yenakiieve"),
+            ("Їжакевич", "Yizhakevych"),
+            ("Щастя", "Shchastia"),
+            ("Ужгород", "Uzhhorod"),
+            ("Wien Hbf", "Wien Hbf"),
+        ];
+        for (name, latin) in names {
+            assert_eq!(transliterate(name), latin, "{name}");
+        }
+    }
+}
@@ -1,3 +1,4 @@
-pub const WELCOME_MESSAGE:&str="Welcome to Ukrzaliznytsia delay notification bot!\nPlease, send Ukrzaliznytsia .pdf ticket (either from email or from mobile app).\nYou will receive notifications if the train is being late prior to train's departure time.";
-pub const WRONG_MESSAGE_RECEIVED: &str = "Must be a .pdf document.";
+pub const WELCOME_MESSAGE:&str="Welcome to Ukrzaliznytsia delay notification bot!\nPlease, send Ukrzaliznytsia .pdf ticket (either from email or from mobile app).\nYou will receive notifications if the train is being late prior to train's departure time.\nNo pdf? Use /add to enter train number, date and time manually.\n\n/status - check delays of your trains right now\n/tickets - list your tickets\n/share <n> - get invite link to share ticket with family or friends\n/remove <n> - stop tracking ticket\n/track <n> - keep receiving delay updates until the train arrives\n/watch <number> - get delays of a train without a ticket\n/stats <number> - how late the train usually is\n/journey <n> <n> - group tickets with a transfer to get warned about missed connections\n/live on|pin|off - one status message per ticket, updated until departure\n/quiet 23:00-07:00 - no reminder sounds at night\n/timezone Europe/Warsaw - show times in your time zone too";
+pub const WRONG_MESSAGE_RECEIVED: &str =
+    "Must be a .pdf document or a ticket link. Tickets without pdf can be added with /add.";
 pub const URL_PARSE_ERROR: &str = "Erorr parsing url.";
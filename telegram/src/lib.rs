@@ -1,25 +1,43 @@
 mod consts;
 mod errors;
 
+pub use consts::WRONG_MESSAGE_RECEIVED;
 use consts::*;
 pub use errors::TelegramErrors;
 use futures::{Future, StreamExt};
 use reqwest::Url;
 use reqwest::{header::HeaderMap, Client, ClientBuilder};
-use teloxide::requests::Requester;
-pub use teloxide::types::ChatId;
-use teloxide::RequestError;
+use std::future::IntoFuture;
+use std::time::Instant;
+use teloxide::dispatching::{Dispatcher, UpdateFilterExt};
+use teloxide::payloads::{EditMessageTextSetters, PinChatMessageSetters, SendMessageSetters};
+use teloxide::requests::{Requester, ResponseResult};
+use teloxide::types::{CallbackQuery, InlineKeyboardButton, InlineKeyboardMarkup, Update};
+pub use teloxide::types::{ChatId, MessageId, UserId};
+use teloxide::{dptree, ApiError, RequestError};
 use teloxide::{net::Download, types::Document};
 use teloxide::{types::Message, Bot};
 use tracing::trace;
 
+/// Pause before asking the dispatcher to shut down again, while it is starting.
+const SHUTDOWN_RETRY: std::time::Duration = std::time::Duration::from_millis(100);
+
 #[derive(Clone)]
 pub struct TelegramClient(Bot, Client);
+
+/// This bot, as returned by `getMe`.
+#[derive(Debug, Clone)]
+pub struct BotIdentity {
+    pub id: UserId,
+    pub username: String,
+}
+
 impl TelegramClient {
-    pub fn new(token: impl Into<String>) -> Self {
-        let bot = teloxide::Bot::new(token);
+    /// Bot talking to the Bot API at `api_url`, downloading tickets from UZ app links with `uz_headers`.
+    pub fn new(token: impl Into<String>, api_url: Url, uz_headers: HeaderMap) -> Self {
+        let bot = teloxide::Bot::new(token).set_api_url(api_url);
         let web_client = ClientBuilder::new()
-            .default_headers(uz_default_headers())
+            .default_headers(uz_headers)
             .build()
             .expect("Error building uz file downloader web client");
         Self(bot, web_client)
@@ -30,58 +48,338 @@ impl TelegramClient {
         user_id: ChatId,
         message: impl Into<String>,
     ) -> Result<(), TelegramErrors> {
-        let _ = self.0.send_message(user_id, message).await?;
+        let _ = timed("sendMessage", self.0.send_message(user_id, message)).await?;
         Ok(())
     }
 
-    pub async fn receive_messages<F, O>(self, parse: F)
-    where
-        F: Fn(ChatId, Vec<u8>) -> O + Clone + Send + Sync + 'static,
-        O: Future<Output = Result<String, String>> + Send,
+    /// Like [`Self::send_to_user`], returns the id to edit the message later.
+    pub async fn send_editable(
+        &self,
+        user_id: ChatId,
+        message: impl Into<String>,
+    ) -> Result<MessageId, TelegramErrors> {
+        let sent = timed("sendMessage", self.0.send_message(user_id, message)).await?;
+        Ok(sent.id)
+    }
+
+    /// Delivered without sound, returns the id like [`Self::send_editable`].
+    pub async fn send_silently(
+        &self,
+        user_id: ChatId,
+        message: impl Into<String>,
+    ) -> Result<MessageId, TelegramErrors> {
+        let request = self
+            .0
+            .send_message(user_id, message)
+            .disable_notification(true);
+        let sent = timed("sendMessage", request).await?;
+        Ok(sent.id)
+    }
+
+    /// Pins without notifying chat members, fails in groups where the bot may not pin.
+    pub async fn pin_message(
+        &self,
+        user_id: ChatId,
+        message_id: MessageId,
+    ) -> Result<(), TelegramErrors> {
+        let request = self
+            .0
+            .pin_chat_message(user_id, message_id)
+            .disable_notification(true);
+        timed("pinChatMessage", request).await?;
+        Ok(())
+    }
+
+    /// Editing to the same text is not an error.
+    pub async fn edit_message(
+        &self,
+        user_id: ChatId,
+        message_id: MessageId,
+        message: impl Into<String>,
+    ) -> Result<(), TelegramErrors> {
+        let request = self.0.edit_message_text(user_id, message_id, message);
+        match timed("editMessageText", request).await {
+            Ok(_) | Err(RequestError::Api(ApiError::MessageNotModified)) => Ok(()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    pub async fn bot_username(&self) -> Result<String, TelegramErrors> {
+        Ok(self.bot_identity().await?.username)
+    }
+
+    pub async fn bot_identity(&self) -> Result<BotIdentity, TelegramErrors> {
+        let me = self.0.get_me().await?;
+        Ok(BotIdentity {
+            id: me.id,
+            username: me.username().to_owned(),
+        })
+    }
+
+    /// Handles updates until `shutdown` completes, then stops polling and waits for updates
+    /// being handled, so that a ticket being parsed is still answered.
+    /// `parse` gets the chat and the user, who sent the update to it.
+    pub async fn receive_messages<F, O>(
+        self,
+        identity: BotIdentity,
+        parse: F,
+        shutdown: impl Future<Output = ()> + Send + 'static,
+    ) where
+        F: Fn(ChatId, UserId, Incoming) -> O + Clone + Send + Sync + 'static,
+        O: Future<Output = Result<Reply, String>> + Send,
     {
-        teloxide::repl(self.0, move |bot: Bot, msg: Message| {
+        let on_message = {
             let client = self.1.clone();
+            let parse = parse.clone();
+            move |bot: Bot, msg: Message| {
+                let client = client.clone();
+                let parse = parse.clone();
+                let identity = identity.clone();
+                async move {
+                    let _in_progress = UpdateInProgress::start();
+                    handle_message(bot, msg, client, parse, &identity).await
+                }
+            }
+        };
+        let on_callback = move |bot: Bot, query: CallbackQuery| {
             let parse = parse.clone();
             async move {
-                if msg.text() == Some("/start") {
-                    bot.send_message(msg.chat.id, WELCOME_MESSAGE).await?;
-                    trace!(%msg.chat.id, "welcome");
-                    return Ok(());
+                let _in_progress = UpdateInProgress::start();
+                handle_callback(bot, query, parse).await
+            }
+        };
+
+        let handler = dptree::entry()
+            .branch(Update::filter_message().endpoint(on_message))
+            .branch(Update::filter_callback_query().endpoint(on_callback));
+
+        let mut dispatcher = Dispatcher::builder(self.0, handler).build();
+        let shutdown_token = dispatcher.shutdown_token();
+        tokio::spawn(async move {
+            shutdown.await;
+            // fails while the dispatcher is starting, nothing to drain yet
+            loop {
+                match shutdown_token.shutdown() {
+                    Ok(drained) => break drained.await,
+                    Err(_) => tokio::time::sleep(SHUTDOWN_RETRY).await,
                 }
+            }
+        });
+        dispatcher.dispatch().await;
+    }
+}
+
+async fn handle_message<F, O>(
+    bot: Bot,
+    msg: Message,
+    client: Client,
+    parse: F,
+    identity: &BotIdentity,
+) -> ResponseResult<()>
+where
+    F: Fn(ChatId, UserId, Incoming) -> O,
+    O: Future<Output = Result<Reply, String>>,
+{
+    // channel posts have no sender and are not answered
+    let Some(sender) = msg.from().map(|from| from.id) else {
+        trace!(%msg.chat.id, "message without sender");
+        return Ok(());
+    };
+    let command = msg
+        .text()
+        .and_then(|text| Command::parse(text, &identity.username));
 
-                let file_content = if let Some(document) = msg.document() {
-                    download_telegram_document(&bot, document).await?
-                } else if msg
-                    .text()
-                    .map(|text| text.starts_with("https://app.uz.gov.ua/ticket-"))
-                    .unwrap_or(false)
-                {
-                    let Ok(url) = msg.text().unwrap_or_default().parse::<Url>() else {
-                        bot.send_message(msg.chat.id, URL_PARSE_ERROR).await?;
-                        return Ok(());
-                    };
-
-                    download_uz_document(&client, url).await?
-                } else {
-                    bot.send_message(msg.chat.id, WRONG_MESSAGE_RECEIVED)
-                        .await?;
-                    trace!(%msg.chat.id, "message without document");
-                    return Ok(());
-                };
-
-                let message = match parse(msg.chat.id, file_content).await {
-                    Ok(data) => data,
-                    Err(user_error_message) => {
-                        bot.send_message(msg.chat.id, user_error_message).await?;
-                        return Ok(());
-                    }
-                };
-                bot.send_message(msg.chat.id, message).await?;
-                trace!(%msg.chat.id,"user notified about new train added");
-                Ok(())
+    let incoming = if let Some(command) = command {
+        if command.name == "start" && command.args.is_empty() {
+            bot.send_message(msg.chat.id, WELCOME_MESSAGE).await?;
+            trace!(%msg.chat.id, "welcome");
+            return Ok(());
+        }
+        Incoming::Command(command)
+    } else if let Some(document) = msg.document() {
+        Incoming::Ticket(download_telegram_document(&bot, document).await?)
+    } else if msg
+        .text()
+        .map(|text| text.starts_with("https://app.uz.gov.ua/ticket-"))
+        .unwrap_or(false)
+    {
+        let Ok(url) = msg.text().unwrap_or_default().parse::<Url>() else {
+            bot.send_message(msg.chat.id, URL_PARSE_ERROR).await?;
+            return Ok(());
+        };
+
+        Incoming::Ticket(download_uz_document(&client, url).await?)
+    } else if let Some(text) = msg.text() {
+        // group members talk to each other, so only replies to the bot are addressed to it
+        let replied_to_bot = msg
+            .reply_to_message()
+            .and_then(|reply| reply.from())
+            .is_some_and(|from| from.id == identity.id);
+        if !msg.chat.is_private() && !replied_to_bot {
+            trace!(%msg.chat.id, "group message not addressed to bot");
+            return Ok(());
+        }
+        Incoming::Text(text.to_owned())
+    } else {
+        if msg.chat.is_private() {
+            bot.send_message(msg.chat.id, WRONG_MESSAGE_RECEIVED)
+                .await?;
+        }
+        trace!(%msg.chat.id, "message without document");
+        return Ok(());
+    };
+
+    let reply = match parse(msg.chat.id, sender, incoming).await {
+        Ok(reply) => reply,
+        Err(user_error_message) => {
+            bot.send_message(msg.chat.id, user_error_message).await?;
+            return Ok(());
+        }
+    };
+    let mut request = bot.send_message(msg.chat.id, reply.text);
+    if let Some(keyboard) = reply.keyboard {
+        request = request.reply_markup(keyboard.into_markup());
+    }
+    timed("sendMessage", request).await?;
+    trace!(%msg.chat.id,"user replied");
+    Ok(())
+}
+
+/// Button presses edit the message with the keyboard instead of sending a new one.
+async fn handle_callback<F, O>(bot: Bot, query: CallbackQuery, parse: F) -> ResponseResult<()>
+where
+    F: Fn(ChatId, UserId, Incoming) -> O,
+    O: Future<Output = Result<Reply, String>>,
+{
+    bot.answer_callback_query(query.id).await?;
+    let (Some(data), Some(message)) = (query.data, query.message) else {
+        return Ok(());
+    };
+
+    match parse(message.chat.id, query.from.id, Incoming::Callback(data)).await {
+        Ok(reply) => {
+            let mut request = bot.edit_message_text(message.chat.id, message.id, reply.text);
+            if let Some(keyboard) = reply.keyboard {
+                request = request.reply_markup(keyboard.into_markup());
+            }
+            match timed("editMessageText", request).await {
+                Ok(_) | Err(RequestError::Api(ApiError::MessageNotModified)) => {}
+                Err(e) => return Err(e),
             }
+        }
+        Err(user_error_message) => {
+            bot.send_message(message.chat.id, user_error_message)
+                .await?;
+        }
+    }
+    trace!(%message.chat.id,"callback handled");
+    Ok(())
+}
+
+/// Records latency of a Bot API request, failed requests are also counted by `method`.
+async fn timed<T>(
+    method: &'static str,
+    request: impl IntoFuture<Output = ResponseResult<T>>,
+) -> ResponseResult<T> {
+    let started = Instant::now();
+    let result = request.await;
+    metrics::histogram!("uz_bot_telegram_request_duration_seconds", "method" => method)
+        .record(started.elapsed().as_secs_f64());
+    if result.is_err() {
+        metrics::counter!("uz_bot_telegram_request_failures_total", "method" => method)
+            .increment(1);
+    }
+    result
+}
+
+/// Counts updates being handled, so that a growing number shows the bot falling behind.
+struct UpdateInProgress;
+
+impl UpdateInProgress {
+    fn start() -> Self {
+        metrics::gauge!("uz_bot_telegram_updates_in_progress").increment(1.0);
+        Self
+    }
+}
+
+impl Drop for UpdateInProgress {
+    fn drop(&mut self) {
+        metrics::gauge!("uz_bot_telegram_updates_in_progress").decrement(1.0);
+    }
+}
+
+/// What the user sent to the bot.
+pub enum Incoming {
+    /// Content of a ticket, either attached as document or downloaded by UZ link.
+    Ticket(Vec<u8>),
+    Command(Command),
+    /// Plain text, e.g. an answer in a conversation.
+    Text(String),
+    /// Data of a pressed inline keyboard button.
+    Callback(String),
+}
+
+/// Message the bot answers with.
+#[derive(Debug, Default)]
+pub struct Reply {
+    pub text: String,
+    pub keyboard: Option<Keyboard>,
+}
+
+impl From<String> for Reply {
+    fn from(text: String) -> Self {
+        Self {
+            text,
+            keyboard: None,
+        }
+    }
+}
+
+/// Inline keyboard, as rows of buttons.
+#[derive(Debug, Default)]
+pub struct Keyboard(pub Vec<Vec<Button>>);
+
+#[derive(Debug)]
+pub struct Button {
+    pub text: String,
+    /// Passed back as [`Incoming::Callback`] when the button is pressed.
+    pub data: String,
+}
+
+impl Keyboard {
+    fn into_markup(self) -> InlineKeyboardMarkup {
+        InlineKeyboardMarkup::new(self.0.into_iter().map(|row| {
+            row.into_iter()
+                .map(|button| InlineKeyboardButton::callback(button.text, button.data))
+        }))
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct Command {
+    /// Command name without leading `/` and `@botname` suffix.
+    pub name: String,
+    pub args: String,
+}
+
+impl Command {
+    /// Parses `/name@bot args`. Commands addressed to other bots in a group are ignored.
+    pub fn parse(text: &str, bot_username: &str) -> Option<Self> {
+        let text = text.strip_prefix('/')?;
+        let (name, args) = text.split_once(char::is_whitespace).unwrap_or((text, ""));
+        let name = match name.split_once('@') {
+            Some((name, username)) if username.eq_ignore_ascii_case(bot_username) => name,
+            Some(_) => return None,
+            None => name,
+        };
+        if name.is_empty() {
+            return None;
+        }
+
+        Some(Self {
+            name: name.to_lowercase(),
+            args: args.trim().to_owned(),
         })
-        .await;
     }
 }
 
@@ -105,9 +403,28 @@ async fn download_uz_document(client: &Client, path: Url) -> Result<Vec<u8>, req
     Ok(bytes.to_vec())
 }
 
-fn uz_default_headers() -> HeaderMap {
-    let mut headers = HeaderMap::new();
-    headers.append("Accept", "text/html,application/xhtml+xml,application/xml;q=0.9,image/avif,image/webp,image/apng,*/*;q=0.8".parse().expect("Eror parsing Accept header"));
-    headers.append("User-Agent", "Mozilla/5.0 (Macintosh; Intel Mac OS X 10_15_7) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/123.0.0.0 Safari/537.36".parse().expect("Eror parsing User-Agent header"));
-    headers
+#[cfg(test)]
+mod tests {
+    use crate::Command;
+
+    #[test]
+    fn test_command_parse() {
+        let cases = [
+            ("/tickets", Some(("tickets", ""))),
+            ("/share 2", Some(("share", "2"))),
+            ("/Remove@UzReminderBot  1 ", Some(("remove", "1"))),
+            ("/start share_abc", Some(("start", "share_abc"))),
+            ("/remove@OtherBot 1", None),
+            ("hello", None),
+            ("/", None),
+        ];
+
+        for (text, expected) in cases {
+            let expected = expected.map(|(name, args)| Command {
+                name: name.to_owned(),
+                args: args.to_owned(),
+            });
+            assert_eq!(Command::parse(text, "uzreminderbot"), expected, "{text}");
+        }
+    }
 }
@@ -0,0 +1,11 @@
+#[derive(Debug, thiserror::Error, PartialEq, Eq)]
+pub enum TrainNumberError {
+    #[error("Train number is empty")]
+    Empty,
+    #[error("Train number must start with digits: {0}")]
+    DigitsAbsent(String),
+    #[error("Train number is too long: {0}")]
+    TooLong(String),
+    #[error("Train number suffix must contain only letters: {0}")]
+    WrongSuffix(String),
+}
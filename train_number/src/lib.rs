@@ -0,0 +1,215 @@
+mod errors;
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::{
+    cmp::Ordering,
+    fmt::Display,
+    hash::{Hash, Hasher},
+    str::FromStr,
+};
+
+pub use errors::TrainNumberError;
+
+/// Number of a UZ train, like `043К` on a ticket or `43` typed by a user.
+///
+/// Tickets pad numbers with zeros and add letters of the train category, while the
+/// delays page and users usually don't, so trains are compared with [`TrainNumber::matches`].
+/// Equality, ordering and hashing agree with it and ignore the suffix.
+#[derive(Debug, Clone)]
+pub struct TrainNumber {
+    number: u32,
+    /// Uppercase Cyrillic letters, Latin lookalikes are converted.
+    suffix: Option<String>,
+}
+
+impl TrainNumber {
+    pub fn number(&self) -> u32 {
+        self.number
+    }
+
+    pub fn suffix(&self) -> Option<&str> {
+        self.suffix.as_deref()
+    }
+
+    /// Same train regardless of leading zeros and suffix letters.
+    pub fn matches(&self, other: &TrainNumber) -> bool {
+        self.number == other.number
+    }
+
+    /// Parses numbers written together, like `705/706` of a train changing its number on the way,
+    /// where the odd number goes in one direction and the even one in the other.
+    pub fn parse_all(numbers: &str) -> Result<Vec<TrainNumber>, TrainNumberError> {
+        numbers.split('/').map(str::parse).collect()
+    }
+}
+
+impl PartialEq for TrainNumber {
+    fn eq(&self, other: &Self) -> bool {
+        self.matches(other)
+    }
+}
+
+impl Eq for TrainNumber {}
+
+impl Hash for TrainNumber {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.number.hash(state);
+    }
+}
+
+impl PartialOrd for TrainNumber {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for TrainNumber {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.number.cmp(&other.number)
+    }
+}
+
+impl FromStr for TrainNumber {
+    type Err = TrainNumberError;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        let trimmed = input.trim().trim_start_matches('№').trim();
+        if trimmed.is_empty() {
+            return Err(TrainNumberError::Empty);
+        }
+
+        let digits_end = trimmed
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(trimmed.len());
+        let (digits, suffix) = trimmed.split_at(digits_end);
+        if digits.is_empty() {
+            return Err(TrainNumberError::DigitsAbsent(input.to_owned()));
+        }
+        let number = digits
+            .parse::<u32>()
+            .map_err(|_| TrainNumberError::TooLong(input.to_owned()))?;
+
+        let suffix = suffix.trim_start_matches(['-', ' ']);
+        if !suffix.chars().all(char::is_alphabetic) {
+            return Err(TrainNumberError::WrongSuffix(input.to_owned()));
+        }
+        let suffix = suffix
+            .chars()
+            .flat_map(char::to_uppercase)
+            .map(latin_to_cyrillic)
+            .collect::<String>();
+
+        Ok(Self {
+            number,
+            suffix: Some(suffix).filter(|suffix| !suffix.is_empty()),
+        })
+    }
+}
+
+/// Zero padded to three digits, as on tickets.
+impl Display for TrainNumber {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{:0>3}{}",
+            self.number,
+            self.suffix().unwrap_or_default()
+        )
+    }
+}
+
+/// Stored as text, e.g. `"043К"`.
+impl Serialize for TrainNumber {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for TrainNumber {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let text = String::deserialize(deserializer)?;
+        text.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+fn latin_to_cyrillic(letter: char) -> char {
+    match letter {
+        'A' => 'А',
+        'B' => 'В',
+        'C' => 'С',
+        'E' => 'Е',
+        'H' => 'Н',
+        'I' => 'І',
+        'K' => 'К',
+        'M' => 'М',
+        'O' => 'О',
+        'P' => 'Р',
+        'T' => 'Т',
+        'X' => 'Х',
+        letter => letter,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::{TrainNumber, TrainNumberError};
+
+    #[test]
+    fn test_train_number_forms() {
+        let valid = [
+            ("043", 43, None, "043"),
+            ("43", 43, None, "043"),
+            ("043К", 43, Some("К"), "043К"),
+            ("043K", 43, Some("К"), "043К"),
+            ("43к", 43, Some("К"), "043К"),
+            ("№705", 705, None, "705"),
+            (" 091 ", 91, None, "091"),
+            ("6401", 6401, None, "6401"),
+            ("749-П", 749, Some("П"), "749П"),
+            ("014 ЛЬ", 14, Some("ЛЬ"), "014ЛЬ"),
+        ];
+        for (input, number, suffix, display) in valid {
+            let train_number: TrainNumber = input.parse().expect(input);
+            assert_eq!(train_number.number(), number, "{input}");
+            assert_eq!(train_number.suffix(), suffix, "{input}");
+            assert_eq!(train_number.to_string(), display, "{input}");
+        }
+
+        let invalid = [
+            ("", TrainNumberError::Empty),
+            ("№", TrainNumberError::Empty),
+            ("К43", TrainNumberError::DigitsAbsent("К43".to_owned())),
+            ("43/44", TrainNumberError::WrongSuffix("43/44".to_owned())),
+            (
+                "99999999999",
+                TrainNumberError::TooLong("99999999999".to_owned()),
+            ),
+        ];
+        for (input, error) in invalid {
+            assert_eq!(input.parse::<TrainNumber>(), Err(error), "{input}");
+        }
+    }
+
+    #[test]
+    fn test_train_number_matching() {
+        let parse = |input: &str| input.parse::<TrainNumber>().unwrap();
+
+        assert!(parse("043К").matches(&parse("43")));
+        assert!(parse("043К").matches(&parse("043П")));
+        assert!(!parse("043").matches(&parse("044")));
+        assert!(!parse("043").matches(&parse("430")));
+
+        // maps keyed by train number treat both forms as one train
+        assert_eq!(parse("043К"), parse("43"));
+        let numbers = HashSet::from([parse("043К"), parse("43"), parse("043П")]);
+        assert_eq!(numbers.len(), 1);
+        assert!(parse("043К") < parse("44"));
+
+        let pair = TrainNumber::parse_all("092/091").unwrap();
+        assert_eq!(pair, vec![parse("92"), parse("91")]);
+        assert!(pair.iter().any(|number| number.matches(&parse("091"))));
+        assert!(TrainNumber::parse_all("705/").is_err());
+    }
+}
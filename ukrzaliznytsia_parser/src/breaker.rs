@@ -0,0 +1,181 @@
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, Utc};
+
+/// How hard to retry the UZ page, when it fails.
+#[derive(Debug, Clone)]
+pub struct FetchPolicy {
+    pub request_timeout: Duration,
+    /// Wait after the first failure, doubled after every next one.
+    pub base_backoff: Duration,
+    pub max_backoff: Duration,
+    /// Consecutive failures, after which the circuit opens and requests stop for `open_duration`.
+    pub failure_threshold: u32,
+    pub open_duration: Duration,
+}
+
+impl Default for FetchPolicy {
+    fn default() -> Self {
+        Self {
+            request_timeout: Duration::from_secs(15),
+            base_backoff: Duration::from_secs(10),
+            max_backoff: Duration::from_secs(5 * 60),
+            failure_threshold: 5,
+            open_duration: Duration::from_secs(10 * 60),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CircuitState {
+    /// Requests go through.
+    Closed,
+    /// Too many failures, requests are not made until the open period ends.
+    Open,
+    /// Open period ended, next request decides whether the circuit closes or opens again.
+    HalfOpen,
+}
+
+/// State of the UZ page as seen by the client, for telling users that delay data is unavailable.
+#[derive(Debug, Clone)]
+pub struct UpstreamHealth {
+    pub circuit: CircuitState,
+    pub consecutive_failures: u32,
+    pub last_success: Option<DateTime<Utc>>,
+    pub last_error: Option<String>,
+}
+
+impl UpstreamHealth {
+    /// Last request failed, so delay data is either stale or absent.
+    pub fn is_degraded(&self) -> bool {
+        self.consecutive_failures > 0
+    }
+}
+
+pub(crate) struct Breaker {
+    policy: FetchPolicy,
+    consecutive_failures: u32,
+    retry_at: Option<Instant>,
+    last_success: Option<DateTime<Utc>>,
+    last_error: Option<String>,
+}
+
+impl Breaker {
+    pub fn new(policy: FetchPolicy) -> Self {
+        Self {
+            policy,
+            consecutive_failures: 0,
+            retry_at: None,
+            last_success: None,
+            last_error: None,
+        }
+    }
+
+    pub fn policy(&self) -> &FetchPolicy {
+        &self.policy
+    }
+
+    /// Time left until the next request is allowed, `None` if it is allowed now.
+    pub fn wait_time(&self, now: Instant) -> Option<Duration> {
+        self.retry_at
+            .filter(|retry_at| *retry_at > now)
+            .map(|retry_at| retry_at - now)
+    }
+
+    pub fn on_success(&mut self) {
+        self.consecutive_failures = 0;
+        self.retry_at = None;
+        self.last_success = Some(Utc::now());
+        self.last_error = None;
+    }
+
+    /// `jitter` from 0 to 1 spreads retries of many clients, it takes up to half of the backoff.
+    pub fn on_failure(&mut self, now: Instant, error: String, jitter: f64) {
+        self.consecutive_failures += 1;
+        self.last_error = Some(error);
+
+        let wait = if self.consecutive_failures >= self.policy.failure_threshold {
+            self.policy.open_duration
+        } else {
+            let exponent = (self.consecutive_failures - 1).min(16);
+            let backoff = self
+                .policy
+                .base_backoff
+                .saturating_mul(1 << exponent)
+                .min(self.policy.max_backoff);
+            backoff.mul_f64(1.0 - jitter.clamp(0.0, 1.0) / 2.0)
+        };
+        self.retry_at = Some(now + wait);
+    }
+
+    pub fn health(&self, now: Instant) -> UpstreamHealth {
+        let circuit = if self.consecutive_failures < self.policy.failure_threshold {
+            CircuitState::Closed
+        } else if self.wait_time(now).is_some() {
+            CircuitState::Open
+        } else {
+            CircuitState::HalfOpen
+        };
+
+        UpstreamHealth {
+            circuit,
+            consecutive_failures: self.consecutive_failures,
+            last_success: self.last_success,
+            last_error: self.last_error.clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::{Breaker, CircuitState, FetchPolicy};
+
+    #[test]
+    fn test_backoff_and_circuit() {
+        let policy = FetchPolicy {
+            request_timeout: Duration::from_secs(1),
+            base_backoff: Duration::from_secs(10),
+            max_backoff: Duration::from_secs(25),
+            failure_threshold: 4,
+            open_duration: Duration::from_secs(600),
+        };
+        let mut breaker = Breaker::new(policy);
+        let now = Instant::now();
+        assert_eq!(breaker.wait_time(now), None);
+
+        let expected_waits = [10, 20, 25];
+        for expected_wait in expected_waits {
+            breaker.on_failure(now, "error".to_owned(), 0.0);
+            assert_eq!(
+                breaker.wait_time(now),
+                Some(Duration::from_secs(expected_wait))
+            );
+            assert_eq!(breaker.health(now).circuit, CircuitState::Closed);
+            assert!(breaker.health(now).is_degraded());
+        }
+
+        breaker.on_failure(now, "error".to_owned(), 0.0);
+        assert_eq!(breaker.wait_time(now), Some(Duration::from_secs(600)));
+        assert_eq!(breaker.health(now).circuit, CircuitState::Open);
+        let after_open = now + Duration::from_secs(601);
+        assert_eq!(breaker.health(after_open).circuit, CircuitState::HalfOpen);
+        assert_eq!(breaker.wait_time(after_open), None);
+
+        breaker.on_success();
+        let health = breaker.health(after_open);
+        assert_eq!(health.circuit, CircuitState::Closed);
+        assert!(!health.is_degraded());
+        assert!(health.last_success.is_some());
+    }
+
+    #[test]
+    fn test_jitter_shortens_backoff() {
+        let mut breaker = Breaker::new(FetchPolicy::default());
+        let now = Instant::now();
+
+        breaker.on_failure(now, "error".to_owned(), 1.0);
+        assert_eq!(breaker.wait_time(now), Some(Duration::from_secs(5)));
+    }
+}
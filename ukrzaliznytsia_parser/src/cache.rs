@@ -0,0 +1,188 @@
+use std::{
+    hash::{DefaultHasher, Hash, Hasher},
+    sync::Arc,
+};
+
+use chrono::{DateTime, Utc};
+use reqwest::header::{HeaderMap, HeaderValue, IF_MODIFIED_SINCE, IF_NONE_MATCH};
+use tracing::trace;
+
+use crate::{delayed_trains::DelayedTrains, errors::UzParseError};
+
+/// Delayed trains together with the time, UZ page was last confirmed to contain them.
+#[derive(Debug, Clone)]
+pub struct DelaysSnapshot {
+    pub trains: Arc<DelayedTrains>,
+    pub fetched_at: DateTime<Utc>,
+}
+
+/// Last delays page, kept to make conditional requests and skip parsing of unchanged pages.
+pub(crate) struct CachedPage {
+    pub etag: Option<HeaderValue>,
+    pub last_modified: Option<HeaderValue>,
+    pub list_hash: u64,
+    pub snapshot: DelaysSnapshot,
+}
+
+impl CachedPage {
+    /// Headers asking the server to skip the body, if the page didn't change since it was cached.
+    pub fn conditional_headers(&self) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        if let Some(etag) = &self.etag {
+            headers.insert(IF_NONE_MATCH, etag.clone());
+        }
+        if let Some(last_modified) = &self.last_modified {
+            headers.insert(IF_MODIFIED_SINCE, last_modified.clone());
+        }
+        headers
+    }
+}
+
+/// Cached snapshot with a new timestamp, after the server answered `304 Not Modified`.
+pub(crate) fn not_modified(
+    cache: &mut Option<CachedPage>,
+    fetched_at: DateTime<Utc>,
+) -> Result<DelaysSnapshot, UzParseError> {
+    let cached = cache
+        .as_mut()
+        .ok_or(UzParseError::NotModifiedWithoutCache)?;
+    trace!("delays page not modified");
+    cached.snapshot.fetched_at = fetched_at;
+    Ok(cached.snapshot.clone())
+}
+
+/// Snapshot of a fetched page, parsed only if its list of delayed trains differs from the cached one.
+pub(crate) fn page_snapshot(
+    cache: &mut Option<CachedPage>,
+    body: &str,
+    etag: Option<HeaderValue>,
+    last_modified: Option<HeaderValue>,
+    fetched_at: DateTime<Utc>,
+) -> Result<DelaysSnapshot, UzParseError> {
+    let list_hash = list_hash(body);
+    if let Some(cached) = cache
+        .as_mut()
+        .filter(|cached| cached.list_hash == list_hash)
+    {
+        trace!("delayed trains list unchanged");
+        cached.etag = etag;
+        cached.last_modified = last_modified;
+        cached.snapshot.fetched_at = fetched_at;
+        return Ok(cached.snapshot.clone());
+    }
+
+    let delayed_trains: DelayedTrains = body.parse()?;
+    trace!(?delayed_trains);
+    let snapshot = DelaysSnapshot {
+        trains: Arc::new(delayed_trains),
+        fetched_at,
+    };
+    *cache = Some(CachedPage {
+        etag,
+        last_modified,
+        list_hash,
+        snapshot: snapshot.clone(),
+    });
+    Ok(snapshot)
+}
+
+/// Hash of the delayed trains list only, the rest of the page changes with every request,
+/// e.g. its csrf token.
+fn list_hash(body: &str) -> u64 {
+    let list = body
+        .find("delayform-list")
+        .map(|start| &body[start..])
+        .map(|list| list.find("</ul>").map_or(list, |end| &list[..end]))
+        .unwrap_or(body);
+    let mut hasher = DefaultHasher::new();
+    list.hash(&mut hasher);
+    hasher.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use chrono::{TimeDelta, Utc};
+    use reqwest::header::{HeaderValue, IF_MODIFIED_SINCE, IF_NONE_MATCH};
+
+    use super::{not_modified, page_snapshot};
+    use crate::errors::UzParseError;
+
+    fn page(csrf_token: &str, trains: &str) -> String {
+        format!(
+            r#"<html><head><meta name="csrf-token" content="{csrf_token}"></head><body>
+            <ul class="delayform-list">{trains}</ul></body></html>"#
+        )
+    }
+
+    #[test]
+    fn test_unchanged_list_is_not_parsed_again() {
+        let mut cache = None;
+        let first_fetch = Utc::now();
+        let etag = HeaderValue::from_static("\"v1\"");
+        let last_modified = HeaderValue::from_static("Tue, 09 Apr 2024 17:36:00 GMT");
+        let first = page_snapshot(
+            &mut cache,
+            &page(
+                "token1",
+                "<li>№43/44 Київ-Пас.-Івано-Франківськ (+1:10)</li>",
+            ),
+            Some(etag.clone()),
+            Some(last_modified.clone()),
+            first_fetch,
+        )
+        .unwrap();
+        assert_eq!(first.trains.0.len(), 1);
+
+        let headers = cache.as_ref().unwrap().conditional_headers();
+        assert_eq!(headers.get(IF_NONE_MATCH), Some(&etag));
+        assert_eq!(headers.get(IF_MODIFIED_SINCE), Some(&last_modified));
+
+        // new csrf token, same trains
+        let second_fetch = first_fetch + TimeDelta::minutes(1);
+        let second = page_snapshot(
+            &mut cache,
+            &page(
+                "token2",
+                "<li>№43/44 Київ-Пас.-Івано-Франківськ (+1:10)</li>",
+            ),
+            None,
+            None,
+            second_fetch,
+        )
+        .unwrap();
+        assert!(Arc::ptr_eq(&first.trains, &second.trains));
+        assert_eq!(second.fetched_at, second_fetch);
+        assert!(cache.as_ref().unwrap().conditional_headers().is_empty());
+
+        let third = page_snapshot(
+            &mut cache,
+            &page(
+                "token3",
+                "<li>№43/44 Київ-Пас.-Івано-Франківськ (+1:25)</li>",
+            ),
+            None,
+            None,
+            second_fetch,
+        )
+        .unwrap();
+        assert!(!Arc::ptr_eq(&first.trains, &third.trains));
+        assert_eq!(third.trains.0[0].delay.total_minutes(), 85);
+    }
+
+    #[test]
+    fn test_not_modified() {
+        let mut cache = None;
+        assert!(matches!(
+            not_modified(&mut cache, Utc::now()),
+            Err(UzParseError::NotModifiedWithoutCache)
+        ));
+
+        let first = page_snapshot(&mut cache, &page("token", ""), None, None, Utc::now()).unwrap();
+        let later = first.fetched_at + TimeDelta::minutes(1);
+        let snapshot = not_modified(&mut cache, later).unwrap();
+        assert!(Arc::ptr_eq(&first.trains, &snapshot.trains));
+        assert_eq!(snapshot.fetched_at, later);
+    }
+}
@@ -1 +1,4 @@
+#[allow(clippy::redundant_static_lifetimes)]
 pub const UZ_DELAYS_URL_STR: &'static str = "https://uz-vezemo.uz.gov.ua/delayform/";
+pub const UZ_ACCEPT: &str = "text/html,application/xhtml+xml,application/xml;q=0.9,image/avif,image/webp,image/apng,*/*;q=0.8";
+pub const UZ_USER_AGENT: &str = "Mozilla/5.0 (Macintosh; Intel Mac OS X 10_15_7) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/123.0.0.0 Safari/537.36";
@@ -1,5 +1,6 @@
 use crate::errors::UzParseError;
 use std::{fmt::Display, str::FromStr};
+use train_number::TrainNumber;
 
 #[derive(Debug)]
 pub struct DelayedTrains(pub Vec<DelayedTrain>);
@@ -12,19 +13,100 @@ pub struct DelayedTrain {
 }
 
 #[derive(Debug)]
-pub struct TrainNumbers(pub Vec<String>);
+pub struct TrainNumbers(pub Vec<TrainNumber>);
+
+impl TrainNumbers {
+    pub fn matches(&self, train_number: &TrainNumber) -> bool {
+        self.0.iter().any(|number| number.matches(train_number))
+    }
+}
 
 #[derive(Debug)]
 pub struct TrainDelayTime {
     pub hr: usize,
     pub min: usize,
 }
+
+impl TrainDelayTime {
+    pub fn total_minutes(&self) -> usize {
+        self.hr * 60 + self.min
+    }
+
+    pub fn severity(&self, thresholds: &SeverityThresholds) -> DelaySeverity {
+        thresholds.classify(self.total_minutes())
+    }
+}
+
+/// How much a delay disrupts the trip, ordered from the least.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum DelaySeverity {
+    Minor,
+    Major,
+    Severe,
+}
+
+/// Delays of at least this many minutes are of the tier, shorter ones are minor.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SeverityThresholds {
+    pub major_minutes: usize,
+    pub severe_minutes: usize,
+}
+
+impl Default for SeverityThresholds {
+    fn default() -> Self {
+        Self {
+            major_minutes: 60,
+            severe_minutes: 180,
+        }
+    }
+}
+
+impl SeverityThresholds {
+    pub fn classify(&self, delay_minutes: usize) -> DelaySeverity {
+        if delay_minutes >= self.severe_minutes {
+            DelaySeverity::Severe
+        } else if delay_minutes >= self.major_minutes {
+            DelaySeverity::Major
+        } else {
+            DelaySeverity::Minor
+        }
+    }
+}
+
+/// Route of the train, written as `origin-destination` on the page, e.g. `Київ-Пас.-Відень Головний`.
 #[derive(Debug)]
-pub struct TrainDirection(String);
+pub struct TrainDirection {
+    text: String,
+    /// Full station name if it is in the station dictionary, otherwise as written on the page.
+    /// `None` if the text could not be split into two stations.
+    pub origin: Option<String>,
+    pub destination: Option<String>,
+}
+
+impl TrainDirection {
+    fn new(text: &str) -> Self {
+        // a misspelled or unknown name is kept, search would turn it into a neighbouring station
+        let full_name = |name: &str| {
+            stations::find(name)
+                .map(|station| station.name.clone())
+                .unwrap_or_else(|| name.to_owned())
+        };
+        let (origin, destination) = match stations::split_route(text) {
+            Some((origin, destination)) => (Some(full_name(origin)), Some(full_name(destination))),
+            None => (None, None),
+        };
+
+        Self {
+            text: text.to_owned(),
+            origin,
+            destination,
+        }
+    }
+}
 
 impl Display for TrainDirection {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}", self.0)
+        write!(f, "{}", self.text)
     }
 }
 
@@ -68,7 +150,7 @@ impl FromStr for DelayedTrains {
             let train_delay = &rest[..rest.len() - 1];
 
             let train_numbers: TrainNumbers = numbers.parse()?;
-            let train_direction = TrainDirection(train_direction.to_owned());
+            let train_direction = TrainDirection::new(train_direction);
             let train_delay: TrainDelayTime = train_delay.parse()?;
 
             delayed_trains.push(DelayedTrain {
@@ -86,13 +168,8 @@ impl FromStr for TrainNumbers {
     type Err = UzParseError;
 
     fn from_str(numbers: &str) -> Result<Self, Self::Err> {
-        // skip 1 because 1st element is `№` sign
-        let numbers = numbers.chars().skip(1).collect::<String>();
-
-        let numbers = numbers
-            .split("/")
-            .map(str::to_owned)
-            .collect::<Vec<String>>();
+        let numbers = TrainNumber::parse_all(numbers)
+            .map_err(|e| UzParseError::ParseTrainNumber(numbers.to_owned(), e))?;
         Ok(TrainNumbers(numbers))
     }
 }
@@ -115,3 +192,24 @@ impl FromStr for TrainDelayTime {
         Ok(TrainDelayTime { hr, min })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{DelaySeverity, SeverityThresholds, TrainDelayTime};
+
+    #[test]
+    fn test_delay_severity() {
+        let thresholds = SeverityThresholds::default();
+        let severity = |delay: &str| {
+            delay
+                .parse::<TrainDelayTime>()
+                .unwrap()
+                .severity(&thresholds)
+        };
+        assert_eq!(severity("0:59"), DelaySeverity::Minor);
+        assert_eq!(severity("1:00"), DelaySeverity::Major);
+        assert_eq!(severity("2:59"), DelaySeverity::Major);
+        assert_eq!(severity("3:00"), DelaySeverity::Severe);
+        assert!(DelaySeverity::Severe > DelaySeverity::Major);
+    }
+}
@@ -1,4 +1,4 @@
-use std::num::ParseIntError;
+use std::{num::ParseIntError, time::Duration};
 
 #[derive(thiserror::Error, Debug)]
 pub enum UzParseError {
@@ -11,7 +11,7 @@ pub enum UzParseError {
     #[error("Error spliting train to get train name: {0}")]
     GetTrainName(String),
     #[error("Error parsing train number {0}: {1}")]
-    ParseTrainNumber(String, ParseIntError),
+    ParseTrainNumber(String, train_number::TrainNumberError),
     #[error("Error spliting train delay: {0}")]
     SplitDelay(String),
     #[error("Error parsing delay hour: {0}")]
@@ -22,4 +22,28 @@ pub enum UzParseError {
     DomHtmlNotFound(String),
     #[error("Container with delayed trains not found in dom: {0}")]
     DelayedTrainsContainer(String),
+    #[error("UZ page answered not modified, but no page was fetched before")]
+    NotModifiedWithoutCache,
+    #[error("UZ page failed recently, next request allowed in {0:?}")]
+    BackingOff(Duration),
+}
+
+impl UzParseError {
+    /// Variant name, for labelling metrics.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            UzParseError::Reqwest(_) => "Reqwest",
+            UzParseError::HtmlParsing(_) => "HtmlParsing",
+            UzParseError::GetTrainNumbers(_) => "GetTrainNumbers",
+            UzParseError::GetTrainName(_) => "GetTrainName",
+            UzParseError::ParseTrainNumber(..) => "ParseTrainNumber",
+            UzParseError::SplitDelay(_) => "SplitDelay",
+            UzParseError::ParseDelayHour(..) => "ParseDelayHour",
+            UzParseError::ParseDelayMinute(..) => "ParseDelayMinute",
+            UzParseError::DomHtmlNotFound(_) => "DomHtmlNotFound",
+            UzParseError::DelayedTrainsContainer(_) => "DelayedTrainsContainer",
+            UzParseError::NotModifiedWithoutCache => "NotModifiedWithoutCache",
+            UzParseError::BackingOff(_) => "BackingOff",
+        }
+    }
 }
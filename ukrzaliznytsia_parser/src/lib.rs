@@ -1,53 +1,159 @@
+mod breaker;
+mod cache;
 mod consts;
 mod delayed_trains;
 mod errors;
-pub use delayed_trains::DelayedTrain;
-use delayed_trains::DelayedTrains;
+use breaker::Breaker;
+pub use breaker::{CircuitState, FetchPolicy, UpstreamHealth};
+pub use cache::DelaysSnapshot;
+use cache::{not_modified, page_snapshot, CachedPage};
+pub use consts::{UZ_ACCEPT, UZ_DELAYS_URL_STR, UZ_USER_AGENT};
+pub use delayed_trains::{
+    DelaySeverity, DelayedTrain, DelayedTrains, SeverityThresholds, TrainDelayTime, TrainDirection,
+};
 pub use errors::UzParseError;
-use reqwest::{header::HeaderMap, Client, ClientBuilder, Url};
-use tracing::trace;
+use reqwest::{
+    header::{HeaderMap, ETAG, LAST_MODIFIED},
+    Client, ClientBuilder, StatusCode, Url,
+};
+use std::{sync::Mutex, time::Instant};
+use tracing::warn;
 
 pub struct UzParserClient {
     web_client: Client,
     url: Url,
+    cache: Mutex<Option<CachedPage>>,
+    breaker: Mutex<Breaker>,
 }
 
 impl UzParserClient {
     pub fn new() -> Self {
         let url: Url = consts::UZ_DELAYS_URL_STR.parse().expect("Wrong uz url");
+        Self::with_config(url, uz_default_headers(), FetchPolicy::default())
+    }
+
+    /// Client of the delays page at `url`, sending `headers` with every request.
+    pub fn with_config(url: Url, headers: HeaderMap, policy: FetchPolicy) -> Self {
         let web_client = ClientBuilder::new()
-            .default_headers(uz_default_headers())
+            .default_headers(headers)
+            .timeout(policy.request_timeout)
+            .connect_timeout(policy.request_timeout)
             .build()
             .expect("Error building uz web client");
 
-        Self { url, web_client }
+        Self {
+            url,
+            web_client,
+            cache: Mutex::new(None),
+            breaker: Mutex::new(Breaker::new(policy)),
+        }
     }
 
-    pub async fn delayed_trains(&self) -> Result<DelayedTrains, UzParseError> {
-        let html_body = self.delays_page_content().await?;
-        let delayed_trains: DelayedTrains = html_body.parse()?;
-        trace!(?delayed_trains);
-        Ok(delayed_trains)
+    /// Fetches delays page, asking the server to skip the body if it is unchanged since the last fetch.
+    /// Unchanged page is not parsed again, the previous result is returned with a new timestamp.
+    ///
+    /// After a failure no requests are made until the backoff passes, [`UzParseError::BackingOff`] is returned instead.
+    pub async fn delayed_trains(&self) -> Result<DelaysSnapshot, UzParseError> {
+        if let Some(wait) = self
+            .breaker
+            .lock()
+            .expect("poisoned uz breaker")
+            .wait_time(Instant::now())
+        {
+            return Err(UzParseError::BackingOff(wait));
+        }
+
+        let started = Instant::now();
+        let result = self.fetch_delayed_trains().await;
+        metrics::histogram!("uz_bot_uz_fetch_duration_seconds")
+            .record(started.elapsed().as_secs_f64());
+
+        let mut breaker = self.breaker.lock().expect("poisoned uz breaker");
+        match &result {
+            Ok(_) => breaker.on_success(),
+            Err(e) => {
+                metrics::counter!("uz_bot_uz_fetch_failures_total", "error" => e.kind())
+                    .increment(1);
+                breaker.on_failure(Instant::now(), e.to_string(), rand::random());
+                let health = breaker.health(Instant::now());
+                if health.circuit == CircuitState::Open {
+                    warn!(
+                        failures = health.consecutive_failures,
+                        open_for = ?breaker.policy().open_duration,
+                        "uz circuit opened"
+                    );
+                }
+            }
+        }
+        result
     }
 
-    async fn delays_page_content(&self) -> Result<String, UzParseError> {
-        let resp_body_html = self
+    /// Current state of the UZ page, degraded while requests to it fail.
+    pub fn health(&self) -> UpstreamHealth {
+        self.breaker
+            .lock()
+            .expect("poisoned uz breaker")
+            .health(Instant::now())
+    }
+
+    async fn fetch_delayed_trains(&self) -> Result<DelaysSnapshot, UzParseError> {
+        let conditional_headers = self
+            .cache
+            .lock()
+            .expect("poisoned uz cache")
+            .as_ref()
+            .map(CachedPage::conditional_headers)
+            .unwrap_or_default();
+        let response = self
             .web_client
             .get(self.url.clone())
+            .headers(conditional_headers)
             .send()
-            .await?
-            .text()
             .await?;
+        let fetched_at = chrono::Utc::now();
+
+        if response.status() == StatusCode::NOT_MODIFIED {
+            return not_modified(
+                &mut self.cache.lock().expect("poisoned uz cache"),
+                fetched_at,
+            );
+        }
+
+        let response = response.error_for_status()?;
+        let etag = response.headers().get(ETAG).cloned();
+        let last_modified = response.headers().get(LAST_MODIFIED).cloned();
+        let html_body = response.text().await?;
 
-        Ok(resp_body_html)
+        page_snapshot(
+            &mut self.cache.lock().expect("poisoned uz cache"),
+            &html_body,
+            etag,
+            last_modified,
+            fetched_at,
+        )
     }
+}
 
+impl Default for UzParserClient {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 fn uz_default_headers() -> HeaderMap {
     let mut headers = HeaderMap::new();
-    headers.append("Accept", "text/html,application/xhtml+xml,application/xml;q=0.9,image/avif,image/webp,image/apng,*/*;q=0.8".parse().expect("Eror parsing Accept header"));
-    headers.append("User-Agent", "Mozilla/5.0 (Macintosh; Intel Mac OS X 10_15_7) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/123.0.0.0 Safari/537.36".parse().expect("Eror parsing User-Agent header"));
+    headers.append(
+        "Accept",
+        consts::UZ_ACCEPT
+            .parse()
+            .expect("Eror parsing Accept header"),
+    );
+    headers.append(
+        "User-Agent",
+        consts::UZ_USER_AGENT
+            .parse()
+            .expect("Eror parsing User-Agent header"),
+    );
     headers
 }
 
@@ -151,7 +257,7 @@ mod tests {
 
         assert_eq!(
             format!("{trains:?}"),
-            r#"DelayedTrains([DelayedTrain { direction: TrainDirection("Пшемисль Головний-Київ-Пас."), numbers: TrainNumbers([705, 706]), delay: TrainDelayTime { hr: 0, min: 30 } }, DelayedTrain { direction: TrainDirection("Київ-Пас.-Відень Головний"), numbers: TrainNumbers([749, 750]), delay: TrainDelayTime { hr: 0, min: 11 } }, DelayedTrain { direction: TrainDirection("Київ-Пас.-Харків-Пас."), numbers: TrainNumbers([721, 722]), delay: TrainDelayTime { hr: 0, min: 9 } }])"#
+            r#"DelayedTrains([DelayedTrain { direction: TrainDirection { text: "Пшемисль Головний-Київ-Пас.", origin: Some("Пшемисль Головний"), destination: Some("Київ-Пасажирський") }, numbers: TrainNumbers([TrainNumber { number: 705, suffix: None }, TrainNumber { number: 706, suffix: None }]), delay: TrainDelayTime { hr: 0, min: 30 } }, DelayedTrain { direction: TrainDirection { text: "Київ-Пас.-Відень Головний", origin: Some("Київ-Пасажирський"), destination: Some("Відень Головний") }, numbers: TrainNumbers([TrainNumber { number: 749, suffix: None }, TrainNumber { number: 750, suffix: None }]), delay: TrainDelayTime { hr: 0, min: 11 } }, DelayedTrain { direction: TrainDirection { text: "Київ-Пас.-Харків-Пас.", origin: Some("Київ-Пасажирський"), destination: Some("Харків-Пасажирський") }, numbers: TrainNumbers([TrainNumber { number: 721, suffix: None }, TrainNumber { number: 722, suffix: None }]), delay: TrainDelayTime { hr: 0, min: 9 } }])"#
         );
     }
 }